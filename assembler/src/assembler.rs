@@ -0,0 +1,640 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+
+use chip_8_emulator::cpu::decoder::Instruction;
+use chip_8_emulator::symbols::SymbolMap;
+
+use crate::error::AsmError;
+use crate::expression::{self, Expr, ExpressionParser, Scope};
+use crate::lexer::{self, Token, TokenKind};
+use crate::source::SourceLine;
+
+//Programs are loaded right after the interpreter area.
+pub const LOAD_ADDRESS: u16 = 0x200;
+//Constants referring to each other deeper than this are assumed to be circular.
+const MAX_CONSTANT_DEPTH: usize = 64;
+
+pub struct Program {
+    pub rom: Vec<u8>,
+    pub symbols: SymbolMap,
+}
+
+enum Operand {
+    Register(u8),
+    I,
+    //[I]
+    IndirectI,
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    Bcd,
+    Value(Expr),
+    Str(String),
+}
+
+struct Argument {
+    operand: Operand,
+    column: usize,
+}
+
+enum Statement {
+    Instruction {
+        mnemonic: String,
+        column: usize,
+        arguments: Vec<Argument>,
+    },
+    //DB when width is 1, DW when it is 2
+    Data {
+        width: u16,
+        arguments: Vec<Argument>,
+    },
+}
+
+struct Placed<'a> {
+    line: &'a SourceLine,
+    address: u16,
+    statement: Statement,
+}
+
+struct Constant<'a> {
+    expr: Expr,
+    //Value of $ where the constant was defined
+    here: u16,
+    line: &'a SourceLine,
+}
+
+struct Symbols<'a> {
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, Constant<'a>>,
+    //Set while resolving ORG, where only symbols defined above can be used
+    forward_allowed: Cell<bool>,
+}
+
+impl Scope for Symbols<'_> {
+    fn lookup(&self, name: &str, depth: usize) -> Result<i64, String> {
+        if let Some(address) = self.labels.get(name) {
+            return Ok(*address as i64);
+        }
+        match self.constants.get(name) {
+            Some(_) if depth > MAX_CONSTANT_DEPTH => {
+                Err(format!("circular definition of '{name}'"))
+            }
+            Some(constant) => expression::evaluate(&constant.expr, constant.here, self, depth + 1)
+                .map_err(|(_, message)| message),
+            None if !self.forward_allowed.get() => {
+                Err(format!("'{name}' must be defined before it is used here"))
+            }
+            None => Err(format!("undefined symbol '{name}'")),
+        }
+    }
+}
+
+fn is_reserved(name: &str) -> bool {
+    operand_keyword(name).is_some()
+        || ["db", "dw", "org", "equ", "include"]
+            .iter()
+            .any(|keyword| keyword.eq_ignore_ascii_case(name))
+}
+
+fn operand_keyword(name: &str) -> Option<Operand> {
+    let upper = name.to_ascii_uppercase();
+    match upper.as_str() {
+        "I" => Some(Operand::I),
+        "DT" => Some(Operand::DelayTimer),
+        "ST" => Some(Operand::SoundTimer),
+        "K" => Some(Operand::Key),
+        "F" => Some(Operand::Font),
+        "B" => Some(Operand::Bcd),
+        register if register.len() == 2 && register.starts_with('V') => {
+            u8::from_str_radix(&register[1..], 16)
+                .ok()
+                .map(Operand::Register)
+        }
+        _ => None,
+    }
+}
+
+fn end_column(line: &SourceLine) -> usize {
+    line.text.chars().count() + 1
+}
+
+//Splits the operands after a mnemonic on commas.
+fn parse_arguments(tokens: &[Token], end: usize) -> Result<Vec<Argument>, (usize, String)> {
+    let mut arguments = Vec::new();
+    let mut position = 0;
+
+    while position < tokens.len() {
+        let column = tokens[position].column;
+        let rest = &tokens[position..];
+
+        let (operand, length) = match &rest[0].kind {
+            TokenKind::Str(string) => (Operand::Str(string.clone()), 1),
+            TokenKind::LeftBracket => {
+                match rest.get(1..3).map(|pair| (&pair[0].kind, &pair[1].kind)) {
+                    Some((TokenKind::Identifier(name), TokenKind::RightBracket))
+                        if name.eq_ignore_ascii_case("i") =>
+                    {
+                        (Operand::IndirectI, 3)
+                    }
+                    _ => return Err((column, String::from("expected [I]"))),
+                }
+            }
+            TokenKind::Identifier(name)
+                if operand_keyword(name).is_some()
+                    && matches!(
+                        rest.get(1).map(|token| &token.kind),
+                        None | Some(TokenKind::Comma)
+                    ) =>
+            {
+                (operand_keyword(name).unwrap(), 1)
+            }
+            _ => {
+                let mut parser = ExpressionParser::new(rest, end);
+                let expr = parser.parse()?;
+                (Operand::Value(expr), parser.position())
+            }
+        };
+        arguments.push(Argument { operand, column });
+        position += length;
+
+        match tokens.get(position).map(|token| &token.kind) {
+            None => break,
+            Some(TokenKind::Comma) if position + 1 < tokens.len() => position += 1,
+            Some(TokenKind::Comma) => return Err((end, String::from("expected an operand"))),
+            Some(_) => return Err((tokens[position].column, String::from("expected ','"))),
+        }
+    }
+
+    Ok(arguments)
+}
+
+fn statement_size(statement: &Statement) -> u16 {
+    match statement {
+        Statement::Instruction { .. } => 2,
+        Statement::Data { width, arguments } => arguments
+            .iter()
+            .map(|argument| match &argument.operand {
+                Operand::Str(string) => string.len() as u16 * width,
+                _ => *width,
+            })
+            .sum(),
+    }
+}
+
+pub fn assemble(lines: &[SourceLine]) -> Result<Program, Vec<AsmError>> {
+    let mut errors = Vec::new();
+    let mut symbols = Symbols {
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        forward_allowed: Cell::new(false),
+    };
+    let mut label_order = Vec::new();
+    let mut placed = Vec::new();
+    let mut address = LOAD_ADDRESS;
+
+    //First pass: parse every line, give addresses to labels and statements
+    for line in lines {
+        let tokens = match lexer::tokenize(&line.text) {
+            Ok(tokens) => tokens,
+            Err((column, message)) => {
+                errors.push(line.error(column, message));
+                continue;
+            }
+        };
+        let end = end_column(line);
+        let mut tokens = tokens.as_slice();
+
+        //label:
+        if let [Token {
+            kind: TokenKind::Identifier(name),
+            column,
+        }, Token {
+            kind: TokenKind::Colon,
+            ..
+        }, ..] = tokens
+        {
+            if is_reserved(name) {
+                errors.push(line.error(*column, format!("'{name}' is a reserved name")));
+            } else if symbols.labels.contains_key(name) || symbols.constants.contains_key(name) {
+                errors.push(line.error(*column, format!("'{name}' is already defined")));
+            } else {
+                symbols.labels.insert(name.clone(), address);
+                label_order.push(name.clone());
+            }
+            tokens = &tokens[2..];
+        }
+
+        let (first, rest) = match tokens.split_first() {
+            Some((first, rest)) => (first, rest),
+            None => continue,
+        };
+        let name = match &first.kind {
+            TokenKind::Identifier(name) => name,
+            _ => {
+                errors.push(line.error(first.column, String::from("expected a mnemonic")));
+                continue;
+            }
+        };
+
+        //NAME equ expr
+        if let Some(Token {
+            kind: TokenKind::Identifier(directive),
+            ..
+        }) = rest.first()
+        {
+            if directive.eq_ignore_ascii_case("equ") {
+                let mut parser = ExpressionParser::new(&rest[1..], end);
+                let result =
+                    parser
+                        .parse()
+                        .and_then(|expr| match rest.get(1 + parser.position()) {
+                            Some(token) => Err((token.column, String::from("unexpected token"))),
+                            None => Ok(expr),
+                        });
+                match result {
+                    Err((column, message)) => errors.push(line.error(column, message)),
+                    Ok(_) if is_reserved(name) => errors
+                        .push(line.error(first.column, format!("'{name}' is a reserved name"))),
+                    Ok(_)
+                        if symbols.labels.contains_key(name)
+                            || symbols.constants.contains_key(name) =>
+                    {
+                        errors
+                            .push(line.error(first.column, format!("'{name}' is already defined")))
+                    }
+                    Ok(expr) => {
+                        symbols.constants.insert(
+                            name.clone(),
+                            Constant {
+                                expr,
+                                here: address,
+                                line,
+                            },
+                        );
+                    }
+                }
+                continue;
+            }
+        }
+
+        let arguments = match parse_arguments(rest, end) {
+            Ok(arguments) => arguments,
+            Err((column, message)) => {
+                errors.push(line.error(column, message));
+                continue;
+            }
+        };
+
+        let statement = match name.to_ascii_uppercase().as_str() {
+            "ORG" => {
+                let target = match arguments.as_slice() {
+                    [Argument {
+                        operand: Operand::Value(expr),
+                        column,
+                    }] => expression::evaluate(expr, address, &symbols, 0).and_then(|value| {
+                        if (LOAD_ADDRESS as i64..=0xFFF).contains(&value) {
+                            Ok(value as u16)
+                        } else {
+                            Err((
+                                *column,
+                                format!("org address {value:#X} is outside 0x200-0xFFF"),
+                            ))
+                        }
+                    }),
+                    _ => Err((first.column, String::from("org takes one address"))),
+                };
+                match target {
+                    Ok(target) => address = target,
+                    Err((column, message)) => errors.push(line.error(column, message)),
+                }
+                continue;
+            }
+            "DB" | "DW" if arguments.is_empty() => {
+                errors.push(line.error(first.column, format!("{name} needs at least one value")));
+                continue;
+            }
+            "DB" => Statement::Data {
+                width: 1,
+                arguments,
+            },
+            "DW" => Statement::Data {
+                width: 2,
+                arguments,
+            },
+            _ => Statement::Instruction {
+                mnemonic: name.to_ascii_uppercase(),
+                column: first.column,
+                arguments,
+            },
+        };
+
+        let size = statement_size(&statement);
+        if address as usize + size as usize > 0x1000 {
+            errors.push(line.error(first.column, String::from("program does not fit in memory")));
+            continue;
+        }
+        placed.push(Placed {
+            line,
+            address,
+            statement,
+        });
+        address += size;
+    }
+
+    //Constants that are never used are still checked
+    symbols.forward_allowed.set(true);
+    let mut constants: Vec<_> = symbols.constants.values().collect();
+    constants.sort_by_key(|constant| (constant.line.file.clone(), constant.line.number));
+    for constant in constants {
+        if let Err((column, message)) =
+            expression::evaluate(&constant.expr, constant.here, &symbols, 0)
+        {
+            errors.push(constant.line.error(column, message));
+        }
+    }
+
+    //Second pass: everything is defined, emit the bytes
+    let mut rom: Vec<u8> = Vec::new();
+    for statement in &placed {
+        let mut bytes = Vec::new();
+        let result = match &statement.statement {
+            Statement::Instruction {
+                mnemonic,
+                column,
+                arguments,
+            } => encode_instruction(mnemonic, *column, arguments, statement.address, &symbols)
+                .map(|instruction| bytes.extend_from_slice(&instruction.encode().to_be_bytes())),
+            Statement::Data { width, arguments } => {
+                encode_data(*width, arguments, statement.address, &symbols, &mut bytes)
+            }
+        };
+
+        if let Err((column, message)) = result {
+            errors.push(statement.line.error(column, message));
+            continue;
+        }
+
+        let offset = (statement.address - LOAD_ADDRESS) as usize;
+        if rom.len() < offset + bytes.len() {
+            rom.resize(offset + bytes.len(), 0);
+        }
+        rom[offset..offset + bytes.len()].copy_from_slice(&bytes);
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut symbol_map = SymbolMap::default();
+    for name in &label_order {
+        symbol_map.add_label(symbols.labels[name], name);
+    }
+    for statement in &placed {
+        symbol_map.add_line(
+            statement.address,
+            &statement.line.file,
+            statement.line.number,
+        );
+    }
+    Ok(Program {
+        rom,
+        symbols: symbol_map,
+    })
+}
+
+fn evaluate_ranged(
+    argument: &Argument,
+    here: u16,
+    symbols: &Symbols,
+    range: std::ops::RangeInclusive<i64>,
+    what: &str,
+) -> Result<i64, (usize, String)> {
+    match &argument.operand {
+        Operand::Value(expr) => {
+            let value = expression::evaluate(expr, here, symbols, 0)?;
+            if range.contains(&value) {
+                Ok(value)
+            } else {
+                Err((argument.column, format!("{value} does not fit in {what}")))
+            }
+        }
+        _ => Err((argument.column, format!("expected {what}"))),
+    }
+}
+
+fn encode_data(
+    width: u16,
+    arguments: &[Argument],
+    here: u16,
+    symbols: &Symbols,
+    bytes: &mut Vec<u8>,
+) -> Result<(), (usize, String)> {
+    for argument in arguments {
+        match (&argument.operand, width) {
+            (Operand::Str(string), 1) => bytes.extend_from_slice(string.as_bytes()),
+            (Operand::Str(string), _) => {
+                for byte in string.bytes() {
+                    bytes.extend_from_slice(&(byte as u16).to_be_bytes());
+                }
+            }
+            (_, 1) => {
+                bytes.push(evaluate_ranged(argument, here, symbols, -128..=255, "a byte")? as u8)
+            }
+            _ => bytes.extend_from_slice(
+                &(evaluate_ranged(argument, here, symbols, -32768..=65535, "a word")? as u16)
+                    .to_be_bytes(),
+            ),
+        }
+    }
+    Ok(())
+}
+
+fn encode_instruction(
+    mnemonic: &str,
+    column: usize,
+    arguments: &[Argument],
+    here: u16,
+    symbols: &Symbols,
+) -> Result<Instruction, (usize, String)> {
+    let address = |argument| {
+        evaluate_ranged(argument, here, symbols, 0..=0xFFF, "an address").map(|value| value as u16)
+    };
+    let byte = |argument| {
+        evaluate_ranged(argument, here, symbols, -128..=255, "a byte").map(|value| value as u8)
+    };
+    let nibble = |argument| {
+        evaluate_ranged(argument, here, symbols, 0..=0xF, "a nibble").map(|value| value as u8)
+    };
+
+    use Operand::*;
+    let operands: Vec<&Operand> = arguments.iter().map(|argument| &argument.operand).collect();
+    let instruction = match (mnemonic, operands.as_slice()) {
+        ("CLS", []) => Instruction::ClearScreen,
+        ("RET", []) => Instruction::ReturnFromSubroutine,
+        ("SYS", [Value(_)]) => Instruction::Call(address(&arguments[0])?),
+        ("JP", [Value(_)]) => Instruction::Goto(address(&arguments[0])?),
+        ("JP", [Register(0), Value(_)]) => Instruction::SetIToAddrPlusV0(address(&arguments[1])?),
+        ("CALL", [Value(_)]) => Instruction::CallSub(address(&arguments[0])?),
+        ("SE", [Register(x), Register(y)]) => Instruction::SkipIfRegisterEqualsRegister(*x, *y),
+        ("SE", [Register(x), Value(_)]) => {
+            Instruction::SkipIfRegisterEquals(*x, byte(&arguments[1])?)
+        }
+        ("SNE", [Register(x), Register(y)]) => Instruction::SkipIfRegisterNequalRegister(*x, *y),
+        ("SNE", [Register(x), Value(_)]) => {
+            Instruction::SkipIfRegisterNequals(*x, byte(&arguments[1])?)
+        }
+        ("LD", [Register(x), Register(y)]) => Instruction::SetRegisterAsRegister(*x, *y),
+        ("LD", [Register(x), Value(_)]) => {
+            Instruction::SetRegisterToValue(*x, byte(&arguments[1])?)
+        }
+        ("LD", [Register(x), DelayTimer]) => Instruction::SetRegisterToTregister(*x),
+        ("LD", [Register(x), Key]) => Instruction::SetRegisterToKey(*x),
+        ("LD", [Register(x), IndirectI]) => Instruction::RegisterLoadFromMemory(*x),
+        ("LD", [I, Value(_)]) => Instruction::SetIToAddr(address(&arguments[1])?),
+        ("LD", [DelayTimer, Register(x)]) => Instruction::SetTregisterToRegister(*x),
+        ("LD", [SoundTimer, Register(x)]) => Instruction::SetSregisterToRegister(*x),
+        ("LD", [Font, Register(x)]) => Instruction::SetIregisterToSprite(*x),
+        ("LD", [Bcd, Register(x)]) => Instruction::RegisterToDecimal(*x),
+        ("LD", [IndirectI, Register(x)]) => Instruction::RegisterDumpToMemory(*x),
+        ("ADD", [Register(x), Register(y)]) => Instruction::SetRegisterAsAddition(*x, *y),
+        ("ADD", [Register(x), Value(_)]) => Instruction::AddToRegister(*x, byte(&arguments[1])?),
+        ("ADD", [I, Register(x)]) => Instruction::AddRegisterToIregister(*x),
+        ("OR", [Register(x), Register(y)]) => Instruction::SetRegisterAsBitwiseOrRegister(*x, *y),
+        ("AND", [Register(x), Register(y)]) => Instruction::SetRegisterAsBitwiseAndRegister(*x, *y),
+        ("XOR", [Register(x), Register(y)]) => Instruction::SetRegisterAsBitwiseXorRegister(*x, *y),
+        ("SUB", [Register(x), Register(y)]) => Instruction::SetRegisterAsSubtraction(*x, *y),
+        ("SUBN", [Register(x), Register(y)]) => Instruction::SetRegisterAsBSubA(*x, *y),
+        //The second register is optional, it only matters with the shift quirk off
+        ("SHR", [Register(x)]) => Instruction::SetRegisterAsShiftRight(*x, *x),
+        ("SHR", [Register(x), Register(y)]) => Instruction::SetRegisterAsShiftRight(*x, *y),
+        ("SHL", [Register(x)]) => Instruction::SetRegisterAsShiftLeft(*x, *x),
+        ("SHL", [Register(x), Register(y)]) => Instruction::SetRegisterAsShiftLeft(*x, *y),
+        ("RND", [Register(x), Value(_)]) => {
+            Instruction::SetRegisterAsBitwiseAndWithRandom(*x, byte(&arguments[1])?)
+        }
+        ("DRW", [Register(x), Register(y), Value(_)]) => {
+            Instruction::Draw(*x, *y, nibble(&arguments[2])?)
+        }
+        ("SKP", [Register(x)]) => Instruction::SkipIfKeyPressed(*x),
+        ("SKNP", [Register(x)]) => Instruction::SkipIfKeyNpressed(*x),
+        (
+            "CLS" | "RET" | "SYS" | "JP" | "CALL" | "SE" | "SNE" | "LD" | "ADD" | "OR" | "AND"
+            | "XOR" | "SUB" | "SUBN" | "SHR" | "SHL" | "RND" | "DRW" | "SKP" | "SKNP",
+            _,
+        ) => return Err((column, format!("invalid operands for {mnemonic}"))),
+        _ => return Err((column, format!("unknown mnemonic '{mnemonic}'"))),
+    };
+    Ok(instruction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    fn source(text: &str) -> Vec<SourceLine> {
+        text.lines()
+            .enumerate()
+            .map(|(index, line)| SourceLine {
+                file: Rc::from("test.asm"),
+                number: index + 1,
+                text: String::from(line),
+            })
+            .collect()
+    }
+
+    fn errors(text: &str) -> Vec<(usize, usize, String)> {
+        match assemble(&source(text)) {
+            Ok(_) => Vec::new(),
+            Err(errors) => errors
+                .into_iter()
+                .map(|error| (error.line, error.column, error.message))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn encodes_every_instruction() {
+        let table: [(&str, u16); 34] = [
+            ("CLS", 0x00E0),
+            ("RET", 0x00EE),
+            ("SYS 0x123", 0x0123),
+            ("JP 0x2A4", 0x12A4),
+            ("JP V0, 0x300", 0xB300),
+            ("CALL 0x250", 0x2250),
+            ("SE V3, 0x42", 0x3342),
+            ("SE V3, V4", 0x5340),
+            ("SNE VA, 7", 0x4A07),
+            ("SNE VA, VB", 0x9AB0),
+            ("LD V1, V2", 0x8120),
+            ("LD V1, -1", 0x61FF),
+            ("LD V5, DT", 0xF507),
+            ("LD V5, K", 0xF50A),
+            ("LD V5, [I]", 0xF565),
+            ("LD I, 0x222", 0xA222),
+            ("LD DT, V6", 0xF615),
+            ("LD ST, V6", 0xF618),
+            ("LD F, V7", 0xF729),
+            ("LD B, V7", 0xF733),
+            ("LD [I], V8", 0xF855),
+            ("ADD V2, V3", 0x8234),
+            ("ADD V2, 1", 0x7201),
+            ("ADD I, V9", 0xF91E),
+            ("OR V1, V2", 0x8121),
+            ("AND V1, V2", 0x8122),
+            ("XOR V1, V2", 0x8123),
+            ("SUB V1, V2", 0x8125),
+            ("SUBN V1, V2", 0x8127),
+            ("SHR V4", 0x8446),
+            ("SHL V4, V5", 0x845E),
+            ("RND VE, 0x0F", 0xCE0F),
+            ("drw v0, v1, 5", 0xD015),
+            ("SKNP VF", 0xEFA1),
+        ];
+        for (text, opcode) in table {
+            let program = assemble(&source(text)).unwrap_or_else(|errors| {
+                panic!("{text}: {}", errors[0]);
+            });
+            assert_eq!(program.rom, opcode.to_be_bytes(), "{text}");
+        }
+    }
+
+    #[test]
+    fn resolves_labels_and_data() {
+        let program = assemble(&source(
+            "SPRITE_SIZE equ end - sprite\n\
+             start: LD I, sprite\n\
+             DRW V0, V1, SPRITE_SIZE\n\
+             JP $\n\
+             sprite: db 0xF0, 0x90, \"A\"\n\
+             dw start\n\
+             end:",
+        ))
+        .unwrap_or_else(|errors| panic!("{}", errors[0]));
+
+        assert_eq!(
+            program.rom,
+            [0xA2, 0x06, 0xD0, 0x15, 0x12, 0x04, 0xF0, 0x90, b'A', 0x02, 0x00]
+        );
+    }
+
+    #[test]
+    fn reports_errors_at_their_column() {
+        assert_eq!(
+            errors("LD V0, 0x100"),
+            [(1, 8, String::from("256 does not fit in a byte"))]
+        );
+        assert_eq!(
+            errors("NOP\nJP missing"),
+            [
+                (1, 1, String::from("unknown mnemonic 'NOP'")),
+                (2, 4, String::from("undefined symbol 'missing'")),
+            ]
+        );
+        assert_eq!(
+            errors("DRW V0, V1"),
+            [(1, 1, String::from("invalid operands for DRW"))]
+        );
+    }
+}
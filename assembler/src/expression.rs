@@ -0,0 +1,232 @@
+use crate::lexer::{Op, Token, TokenKind};
+
+//Expression tree node, with the column of the token it comes from: the number, name or '$'
+//for operands, the operator for the others.
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
+    Number(i64),
+    //Label or constant
+    Symbol(String),
+    //Address of the current statement
+    Here,
+    Unary(Op, Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+//Lowest to highest precedence.
+const PRECEDENCE: [&[Op]; 6] = [
+    &[Op::Or],
+    &[Op::Xor],
+    &[Op::And],
+    &[Op::ShiftLeft, Op::ShiftRight],
+    &[Op::Plus, Op::Minus],
+    &[Op::Star, Op::Slash, Op::Percent],
+];
+
+pub struct ExpressionParser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    //Column reported when running out of tokens
+    end_column: usize,
+}
+
+impl<'a> ExpressionParser<'a> {
+    pub fn new(tokens: &'a [Token], end_column: usize) -> ExpressionParser<'a> {
+        ExpressionParser {
+            tokens,
+            position: 0,
+            end_column,
+        }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn column(&self) -> usize {
+        self.peek()
+            .map(|token| token.column)
+            .unwrap_or(self.end_column)
+    }
+
+    pub fn parse(&mut self) -> Result<Expr, (usize, String)> {
+        self.parse_level(0)
+    }
+
+    fn parse_level(&mut self, level: usize) -> Result<Expr, (usize, String)> {
+        if level == PRECEDENCE.len() {
+            return self.parse_unary();
+        }
+
+        let mut left = self.parse_level(level + 1)?;
+        while let Some(TokenKind::Operator(op)) = self.peek().map(|token| &token.kind) {
+            if !PRECEDENCE[level].contains(op) {
+                break;
+            }
+            let (op, column) = (*op, self.column());
+            self.position += 1;
+            let right = self.parse_level(level + 1)?;
+            left = Expr {
+                kind: ExprKind::Binary(op, Box::new(left), Box::new(right)),
+                column,
+            };
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, (usize, String)> {
+        let column = self.column();
+        let token = self
+            .peek()
+            .cloned()
+            .ok_or((column, String::from("expected an expression")))?;
+        self.position += 1;
+
+        let kind = match token.kind {
+            TokenKind::Number(value) => ExprKind::Number(value),
+            TokenKind::Identifier(name) => ExprKind::Symbol(name),
+            TokenKind::Dollar => ExprKind::Here,
+            TokenKind::Operator(op @ (Op::Minus | Op::Not | Op::Plus)) => {
+                ExprKind::Unary(op, Box::new(self.parse_unary()?))
+            }
+            TokenKind::LeftParen => {
+                let inner = self.parse()?;
+                return match self.peek().map(|token| &token.kind) {
+                    Some(TokenKind::RightParen) => {
+                        self.position += 1;
+                        Ok(inner)
+                    }
+                    _ => Err((self.column(), String::from("expected ')'"))),
+                };
+            }
+            _ => return Err((token.column, String::from("expected an expression"))),
+        };
+        Ok(Expr {
+            kind,
+            column: token.column,
+        })
+    }
+}
+
+//Resolves the symbols an expression refers to.
+pub trait Scope {
+    fn lookup(&self, name: &str, depth: usize) -> Result<i64, String>;
+}
+
+pub fn evaluate(
+    expr: &Expr,
+    here: u16,
+    scope: &dyn Scope,
+    depth: usize,
+) -> Result<i64, (usize, String)> {
+    let evaluate_at = |expr: &Expr| evaluate(expr, here, scope, depth);
+    let overflow = || (expr.column, String::from("arithmetic overflow"));
+
+    match &expr.kind {
+        ExprKind::Number(value) => Ok(*value),
+        ExprKind::Here => Ok(here as i64),
+        ExprKind::Symbol(name) => scope
+            .lookup(name, depth)
+            .map_err(|message| (expr.column, message)),
+        ExprKind::Unary(op, operand) => {
+            let value = evaluate_at(operand)?;
+            match op {
+                Op::Minus => value.checked_neg().ok_or_else(overflow),
+                Op::Not => Ok(!value),
+                _ => Ok(value),
+            }
+        }
+        ExprKind::Binary(op, left, right) => {
+            let left_value = evaluate_at(left)?;
+            let right_value = evaluate_at(right)?;
+            match op {
+                Op::Slash | Op::Percent if right_value == 0 => {
+                    Err((right.column, String::from("division by zero")))
+                }
+                Op::Slash => left_value.checked_div(right_value).ok_or_else(overflow),
+                Op::Percent => left_value.checked_rem(right_value).ok_or_else(overflow),
+                _ => Ok(match op {
+                    Op::Plus => left_value.wrapping_add(right_value),
+                    Op::Minus => left_value.wrapping_sub(right_value),
+                    Op::Star => left_value.wrapping_mul(right_value),
+                    Op::ShiftLeft => left_value.wrapping_shl(right_value as u32),
+                    Op::ShiftRight => left_value.wrapping_shr(right_value as u32),
+                    Op::And => left_value & right_value,
+                    Op::Or => left_value | right_value,
+                    Op::Xor => left_value ^ right_value,
+                    _ => unreachable!("~ is a unary operator, / and % are handled above"),
+                }),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer;
+
+    //Knows a single symbol, SIZE, worth 8.
+    struct TestScope;
+
+    impl Scope for TestScope {
+        fn lookup(&self, name: &str, _depth: usize) -> Result<i64, String> {
+            match name {
+                "SIZE" => Ok(8),
+                _ => Err(format!("unknown symbol '{name}'")),
+            }
+        }
+    }
+
+    fn run(text: &str) -> Result<i64, (usize, String)> {
+        let tokens = lexer::tokenize(text)?;
+        let expr = ExpressionParser::new(&tokens, text.len() + 1).parse()?;
+        evaluate(&expr, 0x300, &TestScope, 0)
+    }
+
+    #[test]
+    fn evaluates_with_precedence() {
+        let table: [(&str, i64); 12] = [
+            ("1 + 2 * 3", 7),
+            ("(1 + 2) * 3", 9),
+            ("10 - 4 - 3", 3),
+            ("17 / 5", 3),
+            ("17 % 5", 2),
+            ("1 << 4 | 1", 17),
+            ("0xF0 & 0x3C ^ 0x0F", 0x3F),
+            ("-SIZE + 1", -7),
+            ("~0 & 0xFF", 0xFF),
+            ("$ + 2", 0x302),
+            ("$10 + 0b11", 0x13),
+            ("SIZE * SIZE >> 1", 32),
+        ];
+        for (text, expected) in table {
+            assert_eq!(run(text), Ok(expected), "{text}");
+        }
+    }
+
+    #[test]
+    fn reports_errors_at_their_column() {
+        let table = [
+            ("4 / (2 - 2)", 8, "division by zero"),
+            ("1 % 0", 5, "division by zero"),
+            ("(-9223372036854775807 - 1) / -1", 28, "arithmetic overflow"),
+            ("-(-9223372036854775807 - 1)", 1, "arithmetic overflow"),
+            ("1 + MISSING", 5, "unknown symbol 'MISSING'"),
+            ("(1 + 2", 7, "expected ')'"),
+        ];
+        for (text, column, message) in table {
+            assert_eq!(run(text), Err((column, String::from(message))), "{text}");
+        }
+    }
+}
@@ -0,0 +1,150 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    ShiftLeft,
+    ShiftRight,
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenKind {
+    Identifier(String),
+    Number(i64),
+    Str(String),
+    Comma,
+    Colon,
+    LeftParen,
+    RightParen,
+    LeftBracket,
+    RightBracket,
+    //Current address
+    Dollar,
+    Operator(Op),
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    //1-based column of the first character
+    pub column: usize,
+}
+
+fn is_identifier_start(character: char) -> bool {
+    character.is_ascii_alphabetic() || character == '_' || character == '.'
+}
+
+fn is_identifier_char(character: char) -> bool {
+    character.is_ascii_alphanumeric() || character == '_' || character == '.'
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let text = text.replace('_', "");
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = text.strip_prefix("0b").or_else(|| text.strip_prefix("0B")) {
+        i64::from_str_radix(binary, 2).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+//Splits a line into tokens, stopping at a ';' comment. Errors carry the column they happen at.
+pub fn tokenize(text: &str) -> Result<Vec<Token>, (usize, String)> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut position = 0;
+
+    while position < chars.len() {
+        let character = chars[position];
+        let column = position + 1;
+        let mut push = |kind, length: usize| {
+            tokens.push(Token { kind, column });
+            length
+        };
+
+        let length = match character {
+            ';' => break,
+            character if character.is_whitespace() => 1,
+            ',' => push(TokenKind::Comma, 1),
+            ':' => push(TokenKind::Colon, 1),
+            '(' => push(TokenKind::LeftParen, 1),
+            ')' => push(TokenKind::RightParen, 1),
+            '[' => push(TokenKind::LeftBracket, 1),
+            ']' => push(TokenKind::RightBracket, 1),
+            '+' => push(TokenKind::Operator(Op::Plus), 1),
+            '-' => push(TokenKind::Operator(Op::Minus), 1),
+            '*' => push(TokenKind::Operator(Op::Star), 1),
+            '/' => push(TokenKind::Operator(Op::Slash), 1),
+            '%' => push(TokenKind::Operator(Op::Percent), 1),
+            '&' => push(TokenKind::Operator(Op::And), 1),
+            '|' => push(TokenKind::Operator(Op::Or), 1),
+            '^' => push(TokenKind::Operator(Op::Xor), 1),
+            '~' => push(TokenKind::Operator(Op::Not), 1),
+            '<' | '>' => {
+                if chars.get(position + 1) != Some(&character) {
+                    return Err((column, format!("unexpected '{character}'")));
+                }
+                let op = if character == '<' {
+                    Op::ShiftLeft
+                } else {
+                    Op::ShiftRight
+                };
+                push(TokenKind::Operator(op), 2)
+            }
+            '"' => {
+                let end = chars[position + 1..]
+                    .iter()
+                    .position(|c| *c == '"')
+                    .ok_or_else(|| (column, String::from("unterminated string")))?;
+                let string: String = chars[position + 1..position + 1 + end].iter().collect();
+                push(TokenKind::Str(string), end + 2)
+            }
+            '$' => {
+                //$ followed by hex digits is a hex number, on its own the current address
+                let digits = chars[position + 1..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_hexdigit())
+                    .count();
+                if digits == 0 {
+                    push(TokenKind::Dollar, 1)
+                } else {
+                    let hex: String = chars[position + 1..position + 1 + digits].iter().collect();
+                    push(
+                        TokenKind::Number(i64::from_str_radix(&hex, 16).unwrap_or_default()),
+                        digits + 1,
+                    )
+                }
+            }
+            character if character.is_ascii_digit() => {
+                let length = chars[position..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+                    .count();
+                let literal: String = chars[position..position + length].iter().collect();
+                let value = parse_number(&literal)
+                    .ok_or_else(|| (column, format!("invalid number '{literal}'")))?;
+                push(TokenKind::Number(value), length)
+            }
+            character if is_identifier_start(character) => {
+                let length = chars[position..]
+                    .iter()
+                    .take_while(|c| is_identifier_char(**c))
+                    .count();
+                let name: String = chars[position..position + length].iter().collect();
+                push(TokenKind::Identifier(name), length)
+            }
+            character => return Err((column, format!("unexpected '{character}'"))),
+        };
+
+        position += length;
+    }
+
+    Ok(tokens)
+}
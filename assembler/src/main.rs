@@ -0,0 +1,91 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+mod assembler;
+mod error;
+mod expression;
+mod lexer;
+mod source;
+
+const USAGE: &str = "usage: assembler <source> [-o <out.ch8>] [--symbols <out.sym>]";
+
+struct Options {
+    source_path: String,
+    output_path: String,
+    symbols_path: String,
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut source_path: Option<String> = None;
+    let mut output_path = None;
+    let mut symbols_path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value for {arg}"))
+        };
+
+        match arg.as_str() {
+            "-o" | "--output" => output_path = Some(value()?.clone()),
+            "--symbols" => symbols_path = Some(value()?.clone()),
+            flag if flag.starts_with('-') => return Err(format!("unknown option '{flag}'")),
+            path if source_path.is_none() => source_path = Some(path.to_string()),
+            extra => return Err(format!("unexpected argument '{extra}'")),
+        }
+    }
+
+    let source_path = source_path.ok_or_else(|| String::from("missing source path"))?;
+    //Outputs default to the source path with its extension swapped
+    let output_path = output_path.unwrap_or_else(|| {
+        Path::new(&source_path)
+            .with_extension("ch8")
+            .display()
+            .to_string()
+    });
+    let symbols_path = symbols_path.unwrap_or_else(|| {
+        Path::new(&output_path)
+            .with_extension("sym")
+            .display()
+            .to_string()
+    });
+
+    Ok(Options {
+        source_path,
+        output_path,
+        symbols_path,
+    })
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = parse_options(&args[1..]).unwrap_or_else(|err| {
+        eprintln!("{err}\n{USAGE}");
+        process::exit(2);
+    });
+
+    let lines = source::load(Path::new(&options.source_path)).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(1);
+    });
+
+    let program = assembler::assemble(&lines).unwrap_or_else(|errors| {
+        for err in &errors {
+            eprintln!("{err}");
+        }
+        eprintln!("{} error(s), nothing written", errors.len());
+        process::exit(1);
+    });
+
+    if let Err(err) = fs::write(&options.output_path, &program.rom) {
+        eprintln!("Problem writing {}: {err}", options.output_path);
+        process::exit(1);
+    }
+    if let Err(err) = program.symbols.write(&options.symbols_path) {
+        eprintln!("Problem writing {}: {err}", options.symbols_path);
+        process::exit(1);
+    }
+}
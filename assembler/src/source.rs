@@ -0,0 +1,100 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::error::AsmError;
+use crate::lexer::{self, TokenKind};
+
+//Nested includes deeper than this are assumed to be a cycle.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+//A line of source after includes were expanded, remembering where it came from.
+pub struct SourceLine {
+    pub file: Rc<str>,
+    //1-based line number inside file
+    pub number: usize,
+    pub text: String,
+}
+
+impl SourceLine {
+    pub fn error(&self, column: usize, message: String) -> AsmError {
+        AsmError {
+            file: self.file.to_string(),
+            line: self.number,
+            column,
+            message,
+        }
+    }
+}
+
+//Reads a source file, replacing every `include "file"` line with the lines of that file.
+//Included paths are relative to the file including them.
+pub fn load(path: &Path) -> Result<Vec<SourceLine>, AsmError> {
+    let mut lines = Vec::new();
+    load_into(path, 0, &mut lines, None)?;
+    Ok(lines)
+}
+
+fn load_into(
+    path: &Path,
+    depth: usize,
+    lines: &mut Vec<SourceLine>,
+    included_from: Option<&SourceLine>,
+) -> Result<(), AsmError> {
+    let fail = |message: String| match included_from {
+        Some(line) => line.error(1, message),
+        None => AsmError {
+            file: path.display().to_string(),
+            line: 0,
+            column: 0,
+            message,
+        },
+    };
+
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(fail(String::from("includes nested too deeply")));
+    }
+    let text = fs::read_to_string(path)
+        .map_err(|err| fail(format!("cannot read {}: {err}", path.display())))?;
+
+    let file: Rc<str> = Rc::from(path.display().to_string());
+    for (index, text) in text.lines().enumerate() {
+        let line = SourceLine {
+            file: Rc::clone(&file),
+            number: index + 1,
+            text: text.to_string(),
+        };
+
+        match include_path(&line.text) {
+            Some(included) => {
+                let included = resolve(path, &included);
+                load_into(&included, depth + 1, lines, Some(&line))?;
+            }
+            None => lines.push(line),
+        }
+    }
+    Ok(())
+}
+
+//Path of an include directive. Malformed lines are left for the assembler to report.
+fn include_path(text: &str) -> Option<String> {
+    let tokens = lexer::tokenize(text).ok()?;
+    match tokens.as_slice() {
+        [directive, path] => match (&directive.kind, &path.kind) {
+            (TokenKind::Identifier(name), TokenKind::Str(path))
+                if name.eq_ignore_ascii_case("include") =>
+            {
+                Some(path.clone())
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn resolve(including: &Path, included: &str) -> PathBuf {
+    match including.parent() {
+        Some(directory) => directory.join(included),
+        None => PathBuf::from(included),
+    }
+}
@@ -0,0 +1,373 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use chip_8_emulator::cpu::decoder::{self, Instruction, Platform};
+
+//Longest BNNN jump table we are willing to guess at.
+const MAX_JUMP_TABLE_ENTRIES: u16 = 64;
+
+//Result of following the control flow of a ROM from its entry point. Everything that was
+//never reached is assumed to be data.
+pub struct Analysis {
+    //Load address of the ROM, also its entry point.
+    pub base: u16,
+    //Every reached instruction, by address. SCHIP and XO-CHIP instructions are included, decoded
+    //as 0NNN calls or Unknown.
+    pub instructions: BTreeMap<u16, Instruction>,
+    pub jump_targets: BTreeSet<u16>,
+    pub call_targets: BTreeSet<u16>,
+    //Addresses loaded into I with ANNN.
+    pub data_references: BTreeSet<u16>,
+    //Sprites drawn by an ANNN ... DXYN sequence: address and height (0 for 16x16).
+    pub sprites: BTreeMap<u16, u8>,
+    //Targets guessed for each BNNN instruction.
+    pub jump_tables: BTreeMap<u16, Vec<u16>>,
+    //Memory writes (FX33, FX55) made while the value of I was known, with that value.
+    pub stores: BTreeMap<u16, u16>,
+    //Reached opcodes that are not an instruction on any platform.
+    pub invalid: BTreeSet<u16>,
+    //Bytes of the ROM covered by a reached instruction.
+    code: Vec<bool>,
+}
+
+//Where execution can continue after an instruction.
+pub enum Flow {
+    //Falls through to the next instruction.
+    Next,
+    //Skips over the next instruction or falls through to it.
+    Skip,
+    Jump(u16),
+    //Calls a subroutine, then continues with the next instruction.
+    Call(u16),
+    //Computed jump through a table (BNNN).
+    Table(u16),
+    Return,
+}
+
+pub fn flow(instruction: &Instruction) -> Flow {
+    match *instruction {
+        Instruction::Goto(address) => Flow::Jump(address),
+        Instruction::CallSub(address) => Flow::Call(address),
+        Instruction::SetIToAddrPlusV0(address) => Flow::Table(address),
+        //00FD exits the interpreter on SCHIP
+        Instruction::ReturnFromSubroutine | Instruction::Call(0x0FD) => Flow::Return,
+        Instruction::SkipIfRegisterEquals(..)
+        | Instruction::SkipIfRegisterNequals(..)
+        | Instruction::SkipIfRegisterEqualsRegister(..)
+        | Instruction::SkipIfRegisterNequalRegister(..)
+        | Instruction::SkipIfKeyPressed(_)
+        | Instruction::SkipIfKeyNpressed(_) => Flow::Skip,
+        _ => Flow::Next,
+    }
+}
+
+impl Analysis {
+    pub fn is_code(&self, address: u16) -> bool {
+        address >= self.base
+            && self
+                .code
+                .get((address - self.base) as usize)
+                .copied()
+                .unwrap_or(false)
+    }
+
+    //Address after the instruction at address, which takes 4 bytes for F000 NNNN.
+    pub fn next_address(&self, address: u16) -> u16 {
+        let size = self.instructions.get(&address).map_or(2, length);
+        address.wrapping_add(size)
+    }
+
+    //Addresses execution can continue at after the instruction at address, leaving out the
+    //subroutine it calls.
+    pub fn successors(&self, address: u16) -> Vec<u16> {
+        let instruction = match self.instructions.get(&address) {
+            Some(instruction) => instruction,
+            None => return Vec::new(),
+        };
+        let next = address.wrapping_add(length(instruction));
+        match flow(instruction) {
+            Flow::Next | Flow::Call(_) => vec![next],
+            Flow::Skip => vec![next, self.next_address(next)],
+            Flow::Jump(target) => vec![target],
+            Flow::Table(_) => self.jump_tables.get(&address).cloned().unwrap_or_default(),
+            Flow::Return => Vec::new(),
+        }
+    }
+
+    //Instructions reachable from entry without going through a call or a return.
+    pub fn function_body(&self, entry: u16) -> BTreeSet<u16> {
+        let mut body = BTreeSet::new();
+        let mut worklist = vec![entry];
+
+        while let Some(address) = worklist.pop() {
+            if self.instructions.contains_key(&address) && body.insert(address) {
+                worklist.extend(self.successors(address));
+            }
+        }
+        body
+    }
+}
+
+fn read_instruction(rom_data: &[u8], base: u16, address: u16) -> Option<Instruction> {
+    let offset = address.checked_sub(base)? as usize;
+    match (rom_data.get(offset), rom_data.get(offset + 1)) {
+        (Some(high), Some(low)) => match decoder::decode(((*high as u16) << 8) | *low as u16) {
+            Instruction::Unknown(opcode)
+                if decoder::required_platform(opcode) == Platform::Chip8 =>
+            {
+                None
+            }
+            instruction => Some(instruction),
+        },
+        _ => None,
+    }
+}
+
+//Bytes taken by the instruction: XO-CHIP's F000 NNNN is the only one with an operand word.
+pub fn length(instruction: &Instruction) -> u16 {
+    match instruction {
+        Instruction::Unknown(0xF000) => 4,
+        _ => 2,
+    }
+}
+
+//Guesses the targets of a BNNN jump. If V0 was just loaded with a constant the target is
+//exact, otherwise we assume a table of JP instructions starting at NNN.
+fn jump_table_targets(rom_data: &[u8], base: u16, address: u16, table: u16) -> Vec<u16> {
+    if let Some(Instruction::SetRegisterToValue(0, value)) =
+        read_instruction(rom_data, base, address.wrapping_sub(2))
+    {
+        return vec![table + value as u16];
+    }
+
+    let mut targets = vec![table];
+    for entry in 1..MAX_JUMP_TABLE_ENTRIES {
+        let entry_address = table + entry * 2;
+        match read_instruction(rom_data, base, entry_address) {
+            Some(Instruction::Goto(_)) => targets.push(entry_address),
+            _ => break,
+        }
+    }
+    targets
+}
+
+pub fn analyze(rom_data: &[u8], base: u16) -> Analysis {
+    let mut analysis = Analysis {
+        base,
+        instructions: BTreeMap::new(),
+        jump_targets: BTreeSet::new(),
+        call_targets: BTreeSet::new(),
+        data_references: BTreeSet::new(),
+        sprites: BTreeMap::new(),
+        jump_tables: BTreeMap::new(),
+        stores: BTreeMap::new(),
+        invalid: BTreeSet::new(),
+        code: vec![false; rom_data.len()],
+    };
+
+    //Addresses still to visit, with the value of I when known so sprites can be found
+    let mut worklist: Vec<(u16, Option<u16>)> = vec![(base, None)];
+
+    while let Some((mut address, mut i_register)) = worklist.pop() {
+        loop {
+            if analysis.instructions.contains_key(&address) {
+                break;
+            }
+            let instruction = match read_instruction(rom_data, base, address) {
+                Some(instruction) => instruction,
+                None => {
+                    if address >= base && ((address - base) as usize) + 1 < rom_data.len() {
+                        analysis.invalid.insert(address);
+                    }
+                    break;
+                }
+            };
+            //Do not decode instructions overlapping ones we already found
+            let offset = (address - base) as usize;
+            let end = (offset + length(&instruction) as usize).min(rom_data.len());
+            if analysis.code[offset..end].contains(&true) {
+                break;
+            }
+
+            analysis.code[offset..end].fill(true);
+            analysis.instructions.insert(address, instruction);
+
+            match instruction {
+                Instruction::SetIToAddr(target) => {
+                    analysis.data_references.insert(target);
+                    i_register = Some(target);
+                }
+                Instruction::Draw(_, _, height) => {
+                    if let Some(sprite) = i_register {
+                        analysis.sprites.insert(sprite, height);
+                    }
+                }
+                Instruction::RegisterToDecimal(_) | Instruction::RegisterDumpToMemory(_) => {
+                    if let Some(target) = i_register {
+                        analysis.stores.insert(address, target);
+                    }
+                }
+                Instruction::AddRegisterToIregister(_)
+                | Instruction::SetIregisterToSprite(_)
+                | Instruction::SetIToAddrPlusV0(_) => i_register = None,
+                _ => (),
+            }
+
+            let next = address + length(&instruction);
+            match flow(&instruction) {
+                Flow::Next => address = next,
+                Flow::Skip => {
+                    //The skipped instruction may be F000 NNNN
+                    let skipped = read_instruction(rom_data, base, next)
+                        .map_or(2, |skipped| length(&skipped));
+                    worklist.push((next + skipped, i_register));
+                    address = next;
+                }
+                Flow::Jump(target) => {
+                    analysis.jump_targets.insert(target);
+                    worklist.push((target, i_register));
+                    break;
+                }
+                Flow::Call(target) => {
+                    analysis.call_targets.insert(target);
+                    worklist.push((target, i_register));
+                    //The subroutine may have changed I
+                    i_register = None;
+                    address = next;
+                }
+                Flow::Table(table) => {
+                    let targets = jump_table_targets(rom_data, base, address, table);
+                    for target in &targets {
+                        analysis.jump_targets.insert(*target);
+                        worklist.push((*target, None));
+                    }
+                    analysis.jump_tables.insert(address, targets);
+                    break;
+                }
+                Flow::Return => break,
+            }
+        }
+    }
+
+    analysis
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addresses(analysis: &Analysis) -> Vec<u16> {
+        analysis.instructions.keys().copied().collect()
+    }
+
+    #[test]
+    fn follows_skips_and_calls_around_data() {
+        #[rustfmt::skip]
+        let rom = [
+            0xA2, 0x0E, //LD I, 0x20E
+            0xD0, 0x15, //DRW V0, V1, 5
+            0x30, 0x01, //SE V0, 0x01
+            0x22, 0x0A, //CALL 0x20A
+            0x12, 0x08, //JP 0x208
+            0x00, 0xEE, //RET
+            0x00, 0x00, //Never reached
+            0xF0, 0x90, 0x90, 0x90, 0xF0, //The sprite
+        ];
+        let analysis = analyze(&rom, 0x200);
+
+        assert_eq!(
+            addresses(&analysis),
+            [0x200, 0x202, 0x204, 0x206, 0x208, 0x20A]
+        );
+        assert_eq!(analysis.call_targets, BTreeSet::from([0x20A]));
+        assert_eq!(analysis.jump_targets, BTreeSet::from([0x208]));
+        assert_eq!(analysis.data_references, BTreeSet::from([0x20E]));
+        assert_eq!(analysis.sprites, BTreeMap::from([(0x20E, 5)]));
+        assert!(analysis.invalid.is_empty());
+
+        assert!(analysis.is_code(0x20B));
+        assert!(!analysis.is_code(0x20C));
+        assert!(!analysis.is_code(0x20E));
+        assert!(!analysis.is_code(0x1FE));
+        assert_eq!(analysis.successors(0x204), [0x206, 0x208]);
+        assert_eq!(analysis.successors(0x206), [0x208]);
+        assert!(analysis.successors(0x20A).is_empty());
+        assert_eq!(
+            analysis.function_body(0x200),
+            BTreeSet::from([0x200, 0x202, 0x204, 0x206, 0x208])
+        );
+    }
+
+    #[test]
+    fn skips_over_long_instructions() {
+        #[rustfmt::skip]
+        let rom = [
+            0x30, 0x00, //SE V0, 0x00
+            0xF0, 0x00, 0x03, 0x00, //i := long 0x300
+            0x12, 0x06, //JP 0x206
+        ];
+        let analysis = analyze(&rom, 0x200);
+
+        assert_eq!(addresses(&analysis), [0x200, 0x202, 0x206]);
+        assert_eq!(analysis.successors(0x200), [0x202, 0x206]);
+        assert_eq!(analysis.next_address(0x202), 0x206);
+        assert!(analysis.is_code(0x205));
+    }
+
+    #[test]
+    fn follows_computed_jumps() {
+        //V0 was just loaded, so the target is known exactly
+        #[rustfmt::skip]
+        let rom = [
+            0x60, 0x02, //LD V0, 0x02
+            0xB2, 0x06, //JP V0, 0x206
+            0xFF, 0xFF, //Never reached
+            0xFF, 0xFF, //Never reached
+            0x12, 0x08, //JP 0x208
+        ];
+        let analysis = analyze(&rom, 0x200);
+        assert_eq!(addresses(&analysis), [0x200, 0x202, 0x208]);
+        assert_eq!(analysis.jump_tables, BTreeMap::from([(0x202, vec![0x208])]));
+
+        //Otherwise every JP from NNN on is taken as a table entry
+        #[rustfmt::skip]
+        let rom = [
+            0xB2, 0x04, //JP V0, 0x204
+            0xFF, 0xFF, //Never reached
+            0x12, 0x08, //JP 0x208
+            0x12, 0x0A, //JP 0x20A
+            0x12, 0x08, //JP 0x208
+            0x12, 0x0A, //JP 0x20A
+        ];
+        let analysis = analyze(&rom, 0x200);
+        assert_eq!(addresses(&analysis), [0x200, 0x204, 0x206, 0x208, 0x20A]);
+        assert_eq!(analysis.jump_tables[&0x200], [0x204, 0x206, 0x208, 0x20A]);
+        assert!(!analysis.is_code(0x202));
+    }
+
+    #[test]
+    fn reports_reached_invalid_opcodes() {
+        let analysis = analyze(&[0x61, 0x01, 0x81, 0x28, 0x12, 0x00], 0x200);
+        assert_eq!(addresses(&analysis), [0x200]);
+        assert_eq!(analysis.invalid, BTreeSet::from([0x202]));
+    }
+
+    #[test]
+    fn loads_at_the_base_address() {
+        #[rustfmt::skip]
+        let rom = [
+            0x16, 0x04, //JP 0x604
+            0x12, 0x34, //Never reached
+            0x26, 0x08, //CALL 0x608
+            0x12, 0x00, //JP 0x200, below the ROM
+            0x00, 0xEE, //RET
+        ];
+        let analysis = analyze(&rom, 0x600);
+
+        assert_eq!(addresses(&analysis), [0x600, 0x604, 0x606, 0x608]);
+        assert_eq!(analysis.jump_targets, BTreeSet::from([0x200, 0x604]));
+        assert!(analysis.invalid.is_empty());
+        assert!(analysis.is_code(0x600));
+        assert!(!analysis.is_code(0x602));
+        assert!(!analysis.is_code(0x200));
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+use chip_8_emulator::cpu::decoder::Instruction;
+
+use crate::analysis::{self, Analysis, Flow};
+
+//Straight-line run of instructions, entered only at the top and left only at the bottom.
+struct Block {
+    start: u16,
+    instructions: Vec<(u16, Instruction)>,
+}
+
+impl Block {
+    fn last(&self) -> (u16, Instruction) {
+        *self
+            .instructions
+            .last()
+            .expect("blocks hold at least one instruction")
+    }
+}
+
+enum EdgeKind {
+    //Falls through or jumps unconditionally
+    Flow,
+    //Skip not taken: the next instruction runs
+    NoSkip,
+    //Skip taken: the next instruction is jumped over
+    Skip,
+    //One of the guessed targets of a BNNN jump table
+    Table,
+    Call,
+}
+
+//Addresses where a new basic block has to start.
+fn leaders(analysis: &Analysis) -> BTreeSet<u16> {
+    let mut leaders = BTreeSet::new();
+    leaders.insert(analysis.base);
+    leaders.extend(&analysis.jump_targets);
+    leaders.extend(&analysis.call_targets);
+
+    for (address, instruction) in &analysis.instructions {
+        let next = analysis.next_address(*address);
+        match analysis::flow(instruction) {
+            Flow::Skip => {
+                leaders.insert(next);
+                leaders.insert(analysis.next_address(next));
+            }
+            Flow::Jump(_) | Flow::Table(_) | Flow::Return => {
+                leaders.insert(next);
+            }
+            Flow::Next | Flow::Call(_) => (),
+        }
+    }
+    leaders
+}
+
+fn blocks(analysis: &Analysis) -> BTreeMap<u16, Block> {
+    let leaders = leaders(analysis);
+    let mut blocks: BTreeMap<u16, Block> = BTreeMap::new();
+    let mut current: Option<Block> = None;
+
+    for (address, instruction) in &analysis.instructions {
+        let continues = match &current {
+            Some(block) => {
+                let (last, _) = block.last();
+                !leaders.contains(address) && analysis.next_address(last) == *address
+            }
+            None => false,
+        };
+
+        if !continues {
+            if let Some(block) = current.take() {
+                blocks.insert(block.start, block);
+            }
+            current = Some(Block {
+                start: *address,
+                instructions: Vec::new(),
+            });
+        }
+        if let Some(block) = current.as_mut() {
+            block.instructions.push((*address, *instruction));
+        }
+    }
+    if let Some(block) = current {
+        blocks.insert(block.start, block);
+    }
+    blocks
+}
+
+fn edges(analysis: &Analysis, block: &Block) -> Vec<(u16, EdgeKind)> {
+    let mut edges = Vec::new();
+
+    for (_, instruction) in &block.instructions {
+        if let Flow::Call(target) = analysis::flow(instruction) {
+            edges.push((target, EdgeKind::Call));
+        }
+    }
+
+    let (address, instruction) = block.last();
+    let next = analysis.next_address(address);
+    match analysis::flow(&instruction) {
+        Flow::Next | Flow::Call(_) => edges.push((next, EdgeKind::Flow)),
+        Flow::Skip => {
+            edges.push((next, EdgeKind::NoSkip));
+            edges.push((analysis.next_address(next), EdgeKind::Skip));
+        }
+        Flow::Jump(target) => edges.push((target, EdgeKind::Flow)),
+        Flow::Table(_) => {
+            for target in analysis.jump_tables.get(&address).into_iter().flatten() {
+                edges.push((*target, EdgeKind::Table));
+            }
+        }
+        Flow::Return => (),
+    }
+    edges
+}
+
+fn node_name(address: u16) -> String {
+    format!("b{:03X}", address)
+}
+
+//Writes the reachable code as a Graphviz graph: one box per basic block, solid edges for
+//fall through and jumps, dashed edges for both outcomes of a skip, dotted ones for jump tables
+//and bold blue ones for subroutine calls. Render it with `dot -Tsvg out.dot -o out.svg`.
+pub fn write_dot(analysis: &Analysis, output: &mut dyn Write) -> io::Result<()> {
+    let blocks = blocks(analysis);
+
+    writeln!(output, "digraph cfg {{")?;
+    writeln!(output, "  node [shape=box, fontname=\"monospace\"];")?;
+
+    for block in blocks.values() {
+        let title = if block.start == analysis.base {
+            format!("main {:#05X}", block.start)
+        } else if analysis.call_targets.contains(&block.start) {
+            format!("sub {:#05X}", block.start)
+        } else {
+            format!("{:#05X}", block.start)
+        };
+
+        let mut label = format!("{title}\\l");
+        for (address, instruction) in &block.instructions {
+            label.push_str(&format!("{:03X}  {}\\l", address, instruction));
+        }
+
+        let style = if block.start == analysis.base || analysis.call_targets.contains(&block.start)
+        {
+            ", penwidth=2"
+        } else {
+            ""
+        };
+        writeln!(
+            output,
+            "  {} [label=\"{}\"{}];",
+            node_name(block.start),
+            label,
+            style
+        )?;
+    }
+
+    for block in blocks.values() {
+        for (target, kind) in edges(analysis, block) {
+            //Targets outside the decoded code, like a jump into data, are left out
+            if !blocks.contains_key(&target) {
+                continue;
+            }
+            let attributes = match kind {
+                EdgeKind::Flow => "",
+                EdgeKind::NoSkip => " [style=dashed, label=\"no skip\"]",
+                EdgeKind::Skip => " [style=dashed, label=\"skip\"]",
+                EdgeKind::Table => " [style=dotted]",
+                EdgeKind::Call => " [style=bold, color=blue, label=\"call\"]",
+            };
+            writeln!(
+                output,
+                "  {} -> {}{};",
+                node_name(block.start),
+                node_name(target),
+                attributes
+            )?;
+        }
+    }
+
+    writeln!(output, "}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_blocks_and_edges() {
+        #[rustfmt::skip]
+        let rom = [
+            0x30, 0x01, //SE V0, 0x01
+            0x22, 0x06, //CALL 0x206
+            0x12, 0x04, //JP 0x204
+            0x00, 0xEE, //RET
+        ];
+        let mut output = Vec::new();
+        write_dot(&analysis::analyze(&rom, 0x200), &mut output).unwrap();
+
+        let expected = r#"digraph cfg {
+  node [shape=box, fontname="monospace"];
+  b200 [label="main 0x200\l200  SE V0, 0x01\l", penwidth=2];
+  b202 [label="0x202\l202  CALL 0x206\l"];
+  b204 [label="0x204\l204  JP 0x204\l"];
+  b206 [label="sub 0x206\l206  RET\l", penwidth=2];
+  b200 -> b202 [style=dashed, label="no skip"];
+  b200 -> b204 [style=dashed, label="skip"];
+  b202 -> b206 [style=bold, color=blue, label="call"];
+  b202 -> b204;
+  b204 -> b204;
+}
+"#;
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+}
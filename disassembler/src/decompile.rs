@@ -0,0 +1,852 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::process;
+
+use chip_8_emulator::cpu::decoder::{self, Instruction, Platform};
+use chip_8_emulator::symbols::SymbolMap;
+
+use crate::analysis::{self, Analysis, Flow};
+use crate::lint;
+
+const USAGE: &str = "usage: disassembler decompile <rom> [-o <out>] [--base <address>]";
+
+const INDENT: &str = "    ";
+
+//Register sets are bitmasks, bit n standing for Vn.
+type Registers = u16;
+
+fn register(x: u8) -> Registers {
+    1 << (x & 0xF)
+}
+
+//V0 to Vx, as used by FX55 and FX65.
+fn registers_up_to(x: u8) -> Registers {
+    (0..=x & 0xF).map(register).fold(0, |set, bit| set | bit)
+}
+
+fn register_list(registers: Registers) -> String {
+    let names: Vec<String> = (0..16)
+        .filter(|x| registers & (1 << x) != 0)
+        .map(|x| format!("v{:X}", x))
+        .collect();
+    names.join(", ")
+}
+
+//Registers read and written by an instruction. Shifts read VY as well, as on the COSMAC VIP.
+fn uses_and_defs(instruction: &Instruction) -> (Registers, Registers) {
+    let flag = register(0xF);
+    match *instruction {
+        Instruction::SkipIfRegisterEquals(x, _)
+        | Instruction::SkipIfRegisterNequals(x, _)
+        | Instruction::SkipIfKeyPressed(x)
+        | Instruction::SkipIfKeyNpressed(x)
+        | Instruction::SetTregisterToRegister(x)
+        | Instruction::SetSregisterToRegister(x)
+        | Instruction::AddRegisterToIregister(x)
+        | Instruction::SetIregisterToSprite(x)
+        | Instruction::RegisterToDecimal(x) => (register(x), 0),
+        Instruction::SkipIfRegisterEqualsRegister(x, y)
+        | Instruction::SkipIfRegisterNequalRegister(x, y) => (register(x) | register(y), 0),
+        Instruction::SetRegisterToValue(x, _)
+        | Instruction::SetRegisterAsBitwiseAndWithRandom(x, _)
+        | Instruction::SetRegisterToTregister(x)
+        | Instruction::SetRegisterToKey(x) => (0, register(x)),
+        Instruction::AddToRegister(x, _) => (register(x), register(x)),
+        Instruction::SetRegisterAsRegister(x, y) => (register(y), register(x)),
+        Instruction::SetRegisterAsBitwiseOrRegister(x, y)
+        | Instruction::SetRegisterAsBitwiseAndRegister(x, y)
+        | Instruction::SetRegisterAsBitwiseXorRegister(x, y) => {
+            (register(x) | register(y), register(x))
+        }
+        Instruction::SetRegisterAsAddition(x, y)
+        | Instruction::SetRegisterAsSubtraction(x, y)
+        | Instruction::SetRegisterAsBSubA(x, y)
+        | Instruction::SetRegisterAsShiftRight(x, y)
+        | Instruction::SetRegisterAsShiftLeft(x, y) => {
+            (register(x) | register(y), register(x) | flag)
+        }
+        Instruction::SetIToAddrPlusV0(_) => (register(0), 0),
+        Instruction::Draw(x, y, _) => (register(x) | register(y), flag),
+        Instruction::RegisterDumpToMemory(x) => (registers_up_to(x), 0),
+        Instruction::RegisterLoadFromMemory(x) => (0, registers_up_to(x)),
+        _ => (0, 0),
+    }
+}
+
+//Names of functions and jump targets, taken from the symbol file when there is one.
+struct Names {
+    symbols: SymbolMap,
+    base: u16,
+}
+
+impl Names {
+    fn function(&self, address: u16) -> String {
+        match self.symbols.label_at(address) {
+            Some(label) => label.to_string(),
+            None if address == self.base => String::from("main"),
+            None => format!("sub_{:03X}", address),
+        }
+    }
+
+    fn label(&self, address: u16) -> String {
+        match self.symbols.label_at(address) {
+            Some(label) => label.to_string(),
+            None => format!("L_{:03X}", address),
+        }
+    }
+}
+
+//Registers each function reads before writing them (its parameters) and the registers it or
+//the subroutines it calls may change (its results).
+struct Signatures {
+    parameters: BTreeMap<u16, Registers>,
+    results: BTreeMap<u16, Registers>,
+}
+
+fn signatures(analysis: &Analysis, bodies: &BTreeMap<u16, BTreeSet<u16>>) -> Signatures {
+    let mut signatures = Signatures {
+        parameters: bodies.keys().map(|entry| (*entry, 0)).collect(),
+        results: bodies.keys().map(|entry| (*entry, 0)).collect(),
+    };
+
+    //Calls make every function depend on the others, so iterate until nothing changes
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for (entry, body) in bodies {
+            let mut results = 0;
+            //Registers live before each instruction
+            let mut live: BTreeMap<u16, Registers> = BTreeMap::new();
+            let mut live_changed = true;
+            while live_changed {
+                live_changed = false;
+                for address in body.iter().rev() {
+                    let instruction = &analysis.instructions[address];
+                    let (mut uses, defs) = uses_and_defs(instruction);
+                    if let Flow::Call(target) = analysis::flow(instruction) {
+                        uses |= signatures.parameters.get(&target).copied().unwrap_or(0);
+                        results |= signatures.results.get(&target).copied().unwrap_or(0);
+                    }
+                    results |= defs;
+
+                    let live_out = analysis
+                        .successors(*address)
+                        .iter()
+                        .filter_map(|next| live.get(next))
+                        .fold(0, |set, registers| set | registers);
+                    let live_in = uses | (live_out & !defs);
+                    if live.insert(*address, live_in) != Some(live_in) {
+                        live_changed = true;
+                    }
+                }
+            }
+
+            let parameters = live.get(entry).copied().unwrap_or(0);
+            if signatures.parameters.insert(*entry, parameters) != Some(parameters)
+                || signatures.results.insert(*entry, results) != Some(results)
+            {
+                changed = true;
+            }
+        }
+    }
+    signatures
+}
+
+//Condition under which a skip instruction skips, and its negation.
+fn condition(instruction: &Instruction) -> Option<(String, String)> {
+    let (skips, negated) = match *instruction {
+        Instruction::SkipIfRegisterEquals(x, value) => (
+            format!("v{:X} == {:#04X}", x, value),
+            format!("v{:X} != {:#04X}", x, value),
+        ),
+        Instruction::SkipIfRegisterNequals(x, value) => (
+            format!("v{:X} != {:#04X}", x, value),
+            format!("v{:X} == {:#04X}", x, value),
+        ),
+        Instruction::SkipIfRegisterEqualsRegister(x, y) => (
+            format!("v{:X} == v{:X}", x, y),
+            format!("v{:X} != v{:X}", x, y),
+        ),
+        Instruction::SkipIfRegisterNequalRegister(x, y) => (
+            format!("v{:X} != v{:X}", x, y),
+            format!("v{:X} == v{:X}", x, y),
+        ),
+        Instruction::SkipIfKeyPressed(x) => (
+            format!("key_pressed(v{:X})", x),
+            format!("!key_pressed(v{:X})", x),
+        ),
+        Instruction::SkipIfKeyNpressed(x) => (
+            format!("!key_pressed(v{:X})", x),
+            format!("key_pressed(v{:X})", x),
+        ),
+        _ => return None,
+    };
+    Some((skips, negated))
+}
+
+//Statement for an instruction that does not change the control flow, or only through a call
+//or a return.
+fn simple_statement(instruction: &Instruction, names: &Names) -> Option<String> {
+    let shift = |x: u8, y: u8, operator: &str| {
+        if x == y {
+            format!("v{:X} {operator}= 1;", x)
+        } else {
+            format!(
+                "v{:X} = v{:X} {operator} 1;  //v{:X} {operator}= 1 on SCHIP",
+                x, y, x
+            )
+        }
+    };
+
+    let statement = match *instruction {
+        Instruction::Call(address) => match decoder::required_platform(address) {
+            Platform::Chip8 => format!("machine_code({:#05X});", address),
+            _ => format!("{};", lint::extension_name(address)),
+        },
+        Instruction::ClearScreen => String::from("clear_screen();"),
+        Instruction::ReturnFromSubroutine => String::from("return;"),
+        Instruction::CallSub(address) => format!("{}();", names.function(address)),
+        Instruction::SetRegisterToValue(x, value) => format!("v{:X} = {:#04X};", x, value),
+        Instruction::AddToRegister(x, value) => format!("v{:X} += {:#04X};", x, value),
+        Instruction::SetRegisterAsRegister(x, y) => format!("v{:X} = v{:X};", x, y),
+        Instruction::SetRegisterAsBitwiseOrRegister(x, y) => format!("v{:X} |= v{:X};", x, y),
+        Instruction::SetRegisterAsBitwiseAndRegister(x, y) => format!("v{:X} &= v{:X};", x, y),
+        Instruction::SetRegisterAsBitwiseXorRegister(x, y) => format!("v{:X} ^= v{:X};", x, y),
+        Instruction::SetRegisterAsAddition(x, y) => {
+            format!("v{:X} += v{:X};  //vF = carry", x, y)
+        }
+        Instruction::SetRegisterAsSubtraction(x, y) => {
+            format!("v{:X} -= v{:X};  //vF = no borrow", x, y)
+        }
+        Instruction::SetRegisterAsShiftRight(x, y) => shift(x, y, ">>"),
+        Instruction::SetRegisterAsBSubA(x, y) => {
+            format!("v{:X} = v{:X} - v{:X};  //vF = no borrow", x, y, x)
+        }
+        Instruction::SetRegisterAsShiftLeft(x, y) => shift(x, y, "<<"),
+        Instruction::SetIToAddr(address) => match names.symbols.label_at(address) {
+            Some(label) => format!("i = {label};"),
+            None => format!("i = {:#05X};", address),
+        },
+        Instruction::SetRegisterAsBitwiseAndWithRandom(x, mask) => {
+            format!("v{:X} = random() & {:#04X};", x, mask)
+        }
+        Instruction::Draw(x, y, height) => format!("vF = draw(v{:X}, v{:X}, {});", x, y, height),
+        Instruction::SetRegisterToTregister(x) => format!("v{:X} = delay;", x),
+        Instruction::SetRegisterToKey(x) => format!("v{:X} = wait_key();", x),
+        Instruction::SetTregisterToRegister(x) => format!("delay = v{:X};", x),
+        Instruction::SetSregisterToRegister(x) => format!("sound = v{:X};", x),
+        Instruction::AddRegisterToIregister(x) => format!("i += v{:X};", x),
+        Instruction::SetIregisterToSprite(x) => format!("i = font(v{:X});", x),
+        Instruction::RegisterToDecimal(x) => format!("memory[i..i + 3] = bcd(v{:X});", x),
+        Instruction::RegisterDumpToMemory(x) => {
+            format!("memory[i..i + {}] = v0..v{:X};", x as u16 + 1, x)
+        }
+        Instruction::RegisterLoadFromMemory(x) => {
+            format!("v0..v{:X} = memory[i..i + {}];", x, x as u16 + 1)
+        }
+        Instruction::Unknown(opcode) => match decoder::required_platform(opcode) {
+            Platform::Chip8 => format!("invalid({:#06X});", opcode),
+            _ => format!("{};", lint::extension_name(opcode)),
+        },
+        Instruction::Goto(_)
+        | Instruction::SetIToAddrPlusV0(_)
+        | Instruction::SkipIfRegisterEquals(..)
+        | Instruction::SkipIfRegisterNequals(..)
+        | Instruction::SkipIfRegisterEqualsRegister(..)
+        | Instruction::SkipIfRegisterNequalRegister(..)
+        | Instruction::SkipIfKeyPressed(_)
+        | Instruction::SkipIfKeyNpressed(_) => return None,
+    };
+    Some(statement)
+}
+
+struct Line {
+    //Address of the first instruction the line stands for, if any
+    address: Option<u16>,
+    indent: usize,
+    text: String,
+}
+
+//Turns the body of one function into nested statements. Skips over a forward jump become
+//if/else, jumps back to an earlier instruction become loops and whatever does not fit either
+//pattern is left as a goto.
+struct FunctionWriter<'a> {
+    analysis: &'a Analysis,
+    names: &'a Names,
+    body: &'a BTreeSet<u16>,
+    lines: Vec<Line>,
+    goto_targets: BTreeSet<u16>,
+    //Instructions a goto lands on, which have to start a line of their own
+    pinned: &'a BTreeSet<u16>,
+    //Instructions written as part of a line started by an earlier one
+    folded: BTreeSet<u16>,
+    //Start and exit of the loops being written, innermost last
+    loops: Vec<(u16, u16)>,
+    indent: usize,
+}
+
+impl<'a> FunctionWriter<'a> {
+    fn line(&mut self, address: Option<u16>, text: String) {
+        self.lines.push(Line {
+            address,
+            indent: self.indent,
+            text,
+        });
+    }
+
+    fn instruction(&self, address: u16) -> Option<&'a Instruction> {
+        if self.body.contains(&address) {
+            self.analysis.instructions.get(&address)
+        } else {
+            None
+        }
+    }
+
+    //break or continue for a jump to the exit or start of the innermost loop, goto otherwise.
+    fn jump(&mut self, target: u16) -> String {
+        match self.loops.last() {
+            Some((_, exit)) if *exit == target => String::from("break;"),
+            Some((start, _)) if *start == target => String::from("continue;"),
+            _ => {
+                self.goto_targets.insert(target);
+                format!("goto {};", self.names.label(target))
+            }
+        }
+    }
+
+    fn write_range(&mut self, start: u16, end: u16) {
+        let mut address = start;
+        while address < end {
+            address = if self.body.contains(&address) {
+                self.write_statement(address, end)
+            } else {
+                match self.body.range(address..end).next() {
+                    Some(next) => *next,
+                    None => break,
+                }
+            };
+        }
+    }
+
+    fn write_loop(&mut self, start: u16, back_jump: u16) -> u16 {
+        let exit = back_jump + 2;
+        //A skip right before the jump back decides whether the loop runs again
+        let test = back_jump
+            .checked_sub(2)
+            .filter(|address| *address >= start && !self.pinned.contains(address))
+            .and_then(|address| self.instruction(address))
+            .and_then(condition);
+
+        self.line(
+            Some(start),
+            String::from(if test.is_some() { "do {" } else { "loop {" }),
+        );
+        self.loops.push((start, exit));
+        if back_jump != start {
+            self.folded.insert(back_jump);
+        }
+        self.indent += 1;
+        match test {
+            Some((_, negated)) => {
+                self.folded.insert(back_jump - 2);
+                self.write_range(start, back_jump - 2);
+                self.indent -= 1;
+                self.line(None, format!("}} while ({negated});"));
+            }
+            None => {
+                let header = self.lines.len();
+                self.write_range(start, back_jump);
+                self.indent -= 1;
+                if self.lines.len() == header {
+                    self.lines[header - 1].text = String::from("loop {}");
+                } else {
+                    self.line(None, String::from("}"));
+                }
+            }
+        }
+        self.loops.pop();
+        exit
+    }
+
+    //Writes the statement starting at address and returns the address after it.
+    fn write_statement(&mut self, address: u16, end: u16) -> u16 {
+        let instruction = self.analysis.instructions[&address];
+
+        //Furthest jump back to here that is still inside the range starts a loop
+        if self.loops.last().map(|(start, _)| *start) != Some(address) {
+            let back_jump = self.body.range(address..end).rev().find(|jump| {
+                !self.pinned.contains(jump)
+                    && matches!(self.analysis.instructions[jump], Instruction::Goto(target) if target == address)
+            });
+            if let Some(back_jump) = back_jump.copied() {
+                return self.write_loop(address, back_jump);
+            }
+        }
+
+        if let Some((skips, negated)) = condition(&instruction) {
+            return self.write_skip(address, end, skips, negated);
+        }
+
+        let next = address.wrapping_add(analysis::length(&instruction));
+        match instruction {
+            Instruction::Goto(target) => {
+                let statement = self.jump(target);
+                self.line(Some(address), statement);
+            }
+            Instruction::SetIToAddrPlusV0(table) => {
+                self.line(
+                    Some(address),
+                    format!("switch (v0) {{  //jump table at {:#05X}", table),
+                );
+                self.indent += 1;
+                for target in self
+                    .analysis
+                    .jump_tables
+                    .get(&address)
+                    .into_iter()
+                    .flatten()
+                {
+                    self.goto_targets.insert(*target);
+                    let case = format!(
+                        "case {:#04X}: goto {};",
+                        target - table,
+                        self.names.label(*target)
+                    );
+                    self.line(None, case);
+                }
+                self.indent -= 1;
+                self.line(None, String::from("}"));
+            }
+            _ => {
+                let statement = simple_statement(&instruction, self.names)
+                    .expect("control flow instructions are handled above");
+                self.line(Some(address), statement);
+            }
+        }
+        next
+    }
+
+    fn write_skip(&mut self, address: u16, end: u16, skips: String, negated: String) -> u16 {
+        let next = self.analysis.next_address(address);
+        let after = self.analysis.next_address(next);
+        let skipped = self
+            .instruction(next)
+            .filter(|_| !self.pinned.contains(&next));
+        if skipped.is_some() {
+            self.folded.insert(next);
+        }
+
+        match skipped {
+            Some(Instruction::Goto(target)) => {
+                let target = *target;
+                let in_loop = self
+                    .loops
+                    .last()
+                    .is_some_and(|(start, exit)| target == *start || target == *exit);
+
+                if !in_loop && target > after && target <= end {
+                    //if (skips) { then } else { otherwise }, where then ends by jumping over
+                    //otherwise
+                    let otherwise = target
+                        .checked_sub(2)
+                        .filter(|jump| *jump >= after && !self.pinned.contains(jump))
+                        .and_then(|jump| match self.instruction(jump) {
+                            Some(Instruction::Goto(join)) if *join > target && *join <= end => {
+                                Some((jump, *join))
+                            }
+                            _ => None,
+                        });
+
+                    self.line(Some(address), format!("if ({skips}) {{"));
+                    self.indent += 1;
+                    let resume = match otherwise {
+                        Some((jump, join)) => {
+                            self.folded.insert(jump);
+                            self.write_range(after, jump);
+                            self.indent -= 1;
+                            self.line(None, String::from("} else {"));
+                            self.indent += 1;
+                            self.write_range(target, join);
+                            join
+                        }
+                        None => {
+                            self.write_range(after, target);
+                            target
+                        }
+                    };
+                    self.indent -= 1;
+                    self.line(None, String::from("}"));
+                    return resume;
+                }
+
+                let statement = self.jump(target);
+                self.line(Some(address), format!("if ({negated}) {statement}"));
+                after
+            }
+            Some(instruction) => match simple_statement(instruction, self.names) {
+                Some(statement) => {
+                    self.line(Some(address), format!("if ({negated}) {statement}"));
+                    after
+                }
+                None => {
+                    self.folded.remove(&next);
+                    self.write_skip_as_goto(address, skips)
+                }
+            },
+            None => self.write_skip_as_goto(address, skips),
+        }
+    }
+
+    fn write_skip_as_goto(&mut self, address: u16, skips: String) -> u16 {
+        let next = self.analysis.next_address(address);
+        let target = self.analysis.next_address(next);
+        self.goto_targets.insert(target);
+        let label = self.names.label(target);
+        self.line(Some(address), format!("if ({skips}) goto {label};"));
+        next
+    }
+
+    //Puts a label line in front of every goto target.
+    fn place_labels(&mut self) {
+        for target in self.goto_targets.iter().rev() {
+            let position = self
+                .lines
+                .iter()
+                .position(|line| line.address.is_some_and(|address| address >= *target));
+            let (position, indent) = match position {
+                Some(position) => (position, self.lines[position].indent),
+                None => (self.lines.len(), 1),
+            };
+            self.lines.insert(
+                position,
+                Line {
+                    address: None,
+                    indent: indent.saturating_sub(1),
+                    text: format!("{}:", self.names.label(*target)),
+                },
+            );
+        }
+    }
+}
+
+fn write_function(
+    analysis: &Analysis,
+    names: &Names,
+    entry: u16,
+    body: &BTreeSet<u16>,
+    signatures: &Signatures,
+    output: &mut dyn Write,
+) -> io::Result<()> {
+    let callers: Vec<String> = analysis
+        .instructions
+        .iter()
+        .filter(|(_, instruction)| matches!(analysis::flow(instruction), Flow::Call(target) if target == entry))
+        .map(|(address, _)| format!("{:#05X}", address))
+        .collect();
+    if callers.is_empty() {
+        writeln!(output, "//Entry point at {:#05X}", entry)?;
+    } else {
+        writeln!(
+            output,
+            "//At {:#05X}, called from {}",
+            entry,
+            callers.join(", ")
+        )?;
+    }
+
+    let parameters = register_list(signatures.parameters[&entry]);
+    let results = signatures.results[&entry];
+    if results == 0 {
+        writeln!(output, "fn {}({parameters}) {{", names.function(entry))?;
+    } else {
+        writeln!(
+            output,
+            "fn {}({parameters}) -> ({}) {{",
+            names.function(entry),
+            register_list(results)
+        )?;
+    }
+
+    //A goto into the middle of a folded statement would have nowhere to land, so write the
+    //function again without folding the instructions it needs until there are none
+    let mut pinned = BTreeSet::new();
+    let mut writer = loop {
+        let mut writer = FunctionWriter {
+            analysis,
+            names,
+            body,
+            lines: Vec::new(),
+            goto_targets: BTreeSet::new(),
+            pinned: &pinned,
+            folded: BTreeSet::new(),
+            loops: Vec::new(),
+            indent: 1,
+        };
+        //Code placed before the entry point, only reached by jumping back
+        let first = body.first().copied().unwrap_or(entry);
+        if first < entry {
+            let statement = writer.jump(entry);
+            writer.line(None, statement);
+        }
+        let end = body
+            .last()
+            .map_or(entry, |last| analysis.next_address(*last));
+        writer.write_range(first, end);
+
+        let stranded: Vec<u16> = writer
+            .goto_targets
+            .intersection(&writer.folded)
+            .copied()
+            .collect();
+        if stranded.is_empty() {
+            break writer;
+        }
+        //Pinning has to make progress, if it does not leave every jump as a goto
+        if stranded.iter().all(|address| pinned.contains(address)) {
+            pinned.clone_from(body);
+        } else {
+            pinned.extend(stranded);
+        }
+    };
+    writer.place_labels();
+
+    for line in &writer.lines {
+        writeln!(output, "{}{}", INDENT.repeat(line.indent), line.text)?;
+    }
+    writeln!(output, "}}")
+}
+
+fn write_program(analysis: &Analysis, names: &Names, output: &mut dyn Write) -> io::Result<()> {
+    let mut bodies = BTreeMap::new();
+    for entry in analysis.call_targets.iter().chain([&analysis.base]) {
+        bodies.insert(*entry, analysis.function_body(*entry));
+    }
+    let signatures = signatures(analysis, &bodies);
+
+    writeln!(
+        output,
+        "//Registers are variables: a function takes the ones it reads before writing them and"
+    )?;
+    writeln!(
+        output,
+        "//returns the ones it, or anything it calls, may change. vF holds the flag results."
+    )?;
+
+    let entries = [analysis.base].into_iter().chain(
+        analysis
+            .call_targets
+            .iter()
+            .copied()
+            .filter(|entry| *entry != analysis.base),
+    );
+    for entry in entries {
+        writeln!(output)?;
+        write_function(analysis, names, entry, &bodies[&entry], &signatures, output)?;
+    }
+    Ok(())
+}
+
+//Usage: disassembler decompile <rom> [-o <out>] [--base <address>]
+//Writes the reachable code as structured pseudocode, one function per subroutine. Labels from
+//a .sym file next to the ROM name the functions and jump targets.
+pub fn run(args: &[String]) {
+    let usage = |err: String| -> ! {
+        eprintln!("{err}\n{USAGE}");
+        process::exit(2);
+    };
+
+    let mut rom_path = None;
+    let mut output_path = None;
+    let mut base = 0x200;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| usage(format!("missing value for {arg}")))
+        };
+        match arg.as_str() {
+            "-o" | "--output" => output_path = Some(value().clone()),
+            "--base" => {
+                base = match crate::parse_number(value()) {
+                    Ok(base) if base <= 0xFFF => base,
+                    Ok(_) => usage(String::from("base address must be below 0x1000")),
+                    Err(err) => usage(err),
+                }
+            }
+            flag if flag.starts_with('-') => usage(format!("unknown option '{flag}'")),
+            path if rom_path.is_none() => rom_path = Some(path.to_string()),
+            extra => usage(format!("unexpected argument '{extra}'")),
+        }
+    }
+    let rom_path = rom_path.unwrap_or_else(|| usage(String::from("missing rom path")));
+
+    let rom_data = crate::load_rom(&rom_path, base);
+    let analysis = analysis::analyze(&rom_data, base);
+
+    let symbol_path = Path::new(&rom_path).with_extension("sym");
+    let symbols = if symbol_path.exists() {
+        SymbolMap::read(&symbol_path.to_string_lossy()).unwrap_or_else(|err| {
+            eprintln!("Problem reading {}: {err}", symbol_path.display());
+            process::exit(1);
+        })
+    } else {
+        SymbolMap::default()
+    };
+    let names = Names { symbols, base };
+
+    //Write to the output file if one was given, stdout otherwise
+    let mut output: Box<dyn Write> = match &output_path {
+        Some(path) => Box::new(BufWriter::new(File::create(path).unwrap_or_else(|err| {
+            eprintln!("Problem creating {path}: {err}");
+            process::exit(1);
+        }))),
+        None => Box::new(BufWriter::new(io::stdout())),
+    };
+    if let Err(err) = write_program(&analysis, &names, &mut output).and_then(|_| output.flush()) {
+        eprintln!("Problem writing pseudocode: {err}");
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Pseudocode for a ROM loaded at 0x200, without the comment at the top.
+    fn decompile(rom: &[u8]) -> String {
+        let names = Names {
+            symbols: SymbolMap::default(),
+            base: 0x200,
+        };
+        let mut output = Vec::new();
+        write_program(&analysis::analyze(rom, 0x200), &names, &mut output).unwrap();
+        let text = String::from_utf8(output).unwrap();
+        text.lines()
+            .skip(3)
+            .map(|line| format!("{line}\n"))
+            .collect()
+    }
+
+    #[test]
+    fn writes_if_else() {
+        #[rustfmt::skip]
+        let rom = [
+            0x30, 0x01, //SE V0, 0x01
+            0x12, 0x08, //JP 0x208
+            0x61, 0x01, //LD V1, 0x01
+            0x12, 0x0A, //JP 0x20A
+            0x61, 0x02, //LD V1, 0x02
+            0x12, 0x0A, //JP 0x20A
+        ];
+        let expected = "//Entry point at 0x200
+fn main(v0) -> (v1) {
+    if (v0 == 0x01) {
+        v1 = 0x01;
+    } else {
+        v1 = 0x02;
+    }
+    loop {}
+}
+";
+        assert_eq!(decompile(&rom), expected);
+    }
+
+    #[test]
+    fn writes_loops() {
+        #[rustfmt::skip]
+        let rom = [
+            0x60, 0x00, //LD V0, 0x00
+            0x70, 0x01, //ADD V0, 0x01
+            0x30, 0x0A, //SE V0, 0x0A
+            0x12, 0x02, //JP 0x202
+            0x12, 0x08, //JP 0x208
+        ];
+        let expected = "//Entry point at 0x200
+fn main() -> (v0) {
+    v0 = 0x00;
+    do {
+        v0 += 0x01;
+    } while (v0 != 0x0A);
+    loop {}
+}
+";
+        assert_eq!(decompile(&rom), expected);
+    }
+
+    #[test]
+    fn skips_over_long_instructions() {
+        #[rustfmt::skip]
+        let rom = [
+            0x30, 0x01,             //SE V0, 0x01
+            0xF0, 0x00, 0x03, 0x00, //LD I, LONG 0x0300
+            0x40, 0x02,             //SNE V0, 0x02
+            0x12, 0x0C,             //JP 0x20C
+            0x61, 0x01,             //LD V1, 0x01
+            0x12, 0x0C,             //JP 0x20C
+        ];
+        let expected = "//Entry point at 0x200
+fn main(v0) -> (v1) {
+    if (v0 != 0x01) i := long;
+    if (v0 != 0x02) {
+        v1 = 0x01;
+    }
+    loop {}
+}
+";
+        assert_eq!(decompile(&rom), expected);
+    }
+
+    #[test]
+    fn falls_back_to_gotos() {
+        //The else branch jumps into the then branch, so neither can be nested
+        #[rustfmt::skip]
+        let rom = [
+            0x30, 0x01, //SE V0, 0x01
+            0x12, 0x08, //JP 0x208
+            0x61, 0x01, //LD V1, 0x01
+            0x12, 0x0C, //JP 0x20C
+            0x61, 0x02, //LD V1, 0x02
+            0x12, 0x06, //JP 0x206
+            0x12, 0x0C, //JP 0x20C
+        ];
+        let expected = "//Entry point at 0x200
+fn main(v0) -> (v1) {
+    if (v0 == 0x01) {
+        v1 = 0x01;
+    L_206:
+        goto L_20C;
+    }
+    v1 = 0x02;
+    goto L_206;
+L_20C:
+    loop {}
+}
+";
+        assert_eq!(decompile(&rom), expected);
+
+        //A skip over a jump table switch has no statement to put in the if
+        #[rustfmt::skip]
+        let rom = [
+            0x30, 0x01, //SE V0, 0x01
+            0xB2, 0x06, //JP V0, 0x206
+            0x12, 0x04, //JP 0x204
+            0x12, 0x06, //JP 0x206
+        ];
+        let expected = "//Entry point at 0x200
+fn main(v0) {
+    if (v0 == 0x01) goto L_204;
+    switch (v0) {  //jump table at 0x206
+        case 0x00: goto L_206;
+    }
+L_204:
+    loop {}
+L_206:
+    loop {}
+}
+";
+        assert_eq!(decompile(&rom), expected);
+    }
+}
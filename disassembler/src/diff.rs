@@ -0,0 +1,248 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::process;
+
+use chip_8_emulator::cpu::decoder;
+use chip_8_emulator::trace::{self, Location, TraceRecord};
+
+//A location, its value in our trace and in the reference, None when a trace never reported it.
+type Difference = (Location, Option<u16>, Option<u16>);
+
+//Where two traces stop agreeing, by instruction index.
+#[derive(Debug, PartialEq, Eq)]
+enum Divergence {
+    //Different instructions were executed, with the state differences before them
+    Instruction(usize, Vec<Difference>),
+    //The same instruction left different state behind
+    Result(usize, Vec<Difference>),
+}
+
+//Machine state rebuilt from the changes listed in a trace. Registers start at zero like they
+//do in Cpu::new, memory is only known once a trace reports it.
+struct State {
+    values: BTreeMap<Location, u16>,
+}
+
+impl State {
+    fn new() -> State {
+        let mut values = BTreeMap::new();
+        for register in 0..16 {
+            values.insert(Location::Register(register), 0);
+        }
+        for location in [
+            Location::IRegister,
+            Location::TRegister,
+            Location::SRegister,
+            Location::StackPointer,
+        ] {
+            values.insert(location, 0);
+        }
+        State { values }
+    }
+
+    fn apply(&mut self, record: &TraceRecord) {
+        for change in &record.changes {
+            self.values.insert(change.location, change.value);
+        }
+    }
+
+    //Locations whose values differ, None being a location one trace never reported. A write
+    //only one emulator logged is a difference too.
+    fn differences(&self, other: &State) -> Vec<Difference> {
+        let locations: BTreeSet<&Location> =
+            self.values.keys().chain(other.values.keys()).collect();
+        locations
+            .into_iter()
+            .filter_map(|location| {
+                let ours = self.values.get(location).copied();
+                let reference = other.values.get(location).copied();
+                (ours != reference).then_some((*location, ours, reference))
+            })
+            .collect()
+    }
+}
+
+fn describe(record: &TraceRecord) -> String {
+    format!(
+        "cycle {} pc {:03X} opcode {:04X} ; {}",
+        record.cycle,
+        record.program_counter,
+        record.opcode,
+        decoder::decode(record.opcode)
+    )
+}
+
+fn print_differences(differences: &[Difference]) {
+    let value = |value: &Option<u16>| match value {
+        Some(value) => format!("{value:X}"),
+        None => String::from("?"),
+    };
+    println!("  {:<8} {:>6} {:>10}", "location", "ours", "reference");
+    for (location, ours, reference) in differences {
+        println!(
+            "  {:<8} {:>6} {:>10}",
+            location.to_string(),
+            value(ours),
+            value(reference)
+        );
+    }
+}
+
+//Walks both traces in step up to the end of the shorter one and finds the first instruction
+//where they disagree.
+fn compare(ours: &[TraceRecord], reference: &[TraceRecord]) -> Option<Divergence> {
+    let mut our_state = State::new();
+    let mut reference_state = State::new();
+
+    for (index, (our_record, reference_record)) in ours.iter().zip(reference).enumerate() {
+        //A different instruction means the previous one sent the program elsewhere
+        if our_record.program_counter != reference_record.program_counter
+            || our_record.opcode != reference_record.opcode
+        {
+            let differences = our_state.differences(&reference_state);
+            return Some(Divergence::Instruction(index, differences));
+        }
+
+        our_state.apply(our_record);
+        reference_state.apply(reference_record);
+
+        let differences = our_state.differences(&reference_state);
+        if !differences.is_empty() {
+            return Some(Divergence::Result(index, differences));
+        }
+    }
+    None
+}
+
+//Usage: disassembler diff <our trace> <reference trace>
+//Reports the first instruction where the traces disagree. Exits with 1 on divergence, like diff.
+pub fn run(args: &[String]) {
+    let (ours_path, reference_path) = match args {
+        [ours, reference] => (ours, reference),
+        _ => {
+            eprintln!("usage: disassembler diff <our trace> <reference trace>");
+            process::exit(2);
+        }
+    };
+
+    let read = |path: &String| {
+        trace::read_trace(path).unwrap_or_else(|err| {
+            eprintln!("Problem reading trace {path}: {err}");
+            process::exit(2);
+        })
+    };
+    let ours = read(ours_path);
+    let reference = read(reference_path);
+
+    match compare(&ours, &reference) {
+        Some(Divergence::Instruction(index, differences)) => {
+            println!("Traces diverge at instruction {index}: different instruction executed");
+            println!("  ours:      {}", describe(&ours[index]));
+            println!("  reference: {}", describe(&reference[index]));
+            if index > 0 {
+                println!("  previous:  {}", describe(&ours[index - 1]));
+            }
+            if !differences.is_empty() {
+                println!("State before the instruction:");
+                print_differences(&differences);
+            }
+            process::exit(1);
+        }
+        Some(Divergence::Result(index, differences)) => {
+            println!("Traces diverge at instruction {index}: different result");
+            println!("  ours:      {}", describe(&ours[index]));
+            println!("  reference: {}", describe(&reference[index]));
+            print_differences(&differences);
+            process::exit(1);
+        }
+        None => (),
+    }
+
+    if ours.len() != reference.len() {
+        let (shorter, length) = if ours.len() < reference.len() {
+            ("our", ours.len())
+        } else {
+            ("reference", reference.len())
+        };
+        println!("Traces agree, but the {shorter} trace ends after {length} instructions");
+        process::exit(1);
+    }
+
+    println!("Traces are identical ({} instructions)", ours.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip_8_emulator::trace::Change;
+
+    fn record(
+        cycle: u64,
+        program_counter: u16,
+        opcode: u16,
+        changes: &[(Location, u16)],
+    ) -> TraceRecord {
+        TraceRecord {
+            cycle,
+            program_counter,
+            opcode,
+            changes: changes
+                .iter()
+                .map(|(location, value)| Change {
+                    location: *location,
+                    value: *value,
+                })
+                .collect(),
+        }
+    }
+
+    fn program(second_value: u16) -> Vec<TraceRecord> {
+        vec![
+            record(0, 0x200, 0x6005, &[(Location::Register(0), 0x05)]),
+            record(1, 0x202, 0x7003, &[(Location::Register(0), second_value)]),
+            record(2, 0x204, 0x1204, &[]),
+        ]
+    }
+
+    #[test]
+    fn identical_traces_agree() {
+        assert_eq!(compare(&program(0x08), &program(0x08)), None);
+    }
+
+    #[test]
+    fn finds_the_first_different_result() {
+        assert_eq!(
+            compare(&program(0x08), &program(0x09)),
+            Some(Divergence::Result(
+                1,
+                vec![(Location::Register(0), Some(0x08), Some(0x09))]
+            ))
+        );
+    }
+
+    #[test]
+    fn finds_a_different_instruction_and_unreported_writes() {
+        let mut ours = program(0x08);
+        ours[1].changes.push(Change {
+            location: Location::Memory(0x300),
+            value: 0xFF,
+        });
+        let mut reference = ours.clone();
+        reference[1].changes.pop();
+        reference[2].program_counter = 0x206;
+
+        //The memory write only we reported already differs after the second instruction
+        assert_eq!(
+            compare(&ours, &reference),
+            Some(Divergence::Result(
+                1,
+                vec![(Location::Memory(0x300), Some(0xFF), None)]
+            ))
+        );
+
+        reference[1] = ours[1].clone();
+        assert_eq!(
+            compare(&ours, &reference),
+            Some(Divergence::Instruction(2, Vec::new()))
+        );
+    }
+}
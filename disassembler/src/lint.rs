@@ -0,0 +1,488 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::process;
+
+use chip_8_emulator::cpu::decoder::{self, Instruction, Platform};
+use chip_8_emulator::cpu::STACK_SIZE;
+
+use crate::analysis::{self, Analysis, Flow};
+
+//Instructions looked at after a load or store before deciding I is not read again.
+const LOOKAHEAD: usize = 32;
+
+//Behaviours that differ between the original COSMAC VIP interpreter (also followed by XO-CHIP)
+//and SCHIP.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Quirk {
+    //8XY6/8XYE: VX := VY shifted, or VX shifted in place
+    Shift,
+    //FX55/FX65: I advances past the registers, or is left alone
+    LoadStore,
+    //BNNN: jumps to NNN + V0, or to XNN + VX
+    Jump,
+}
+
+impl Quirk {
+    fn name(&self) -> &'static str {
+        match self {
+            Quirk::Shift => "shift",
+            Quirk::LoadStore => "load/store",
+            Quirk::Jump => "jump",
+        }
+    }
+}
+
+struct Report {
+    //Instructions newer than CHIP-8, with the platform they need
+    platform: Vec<(u16, u16, Platform)>,
+    quirks: Vec<(u16, Quirk, String)>,
+    //FX33/FX55 writing over code: address, opcode and the range written
+    self_modifying: Vec<(u16, u16, u16, u16)>,
+    //Longest chain of nested calls from the entry point
+    deepest_calls: Vec<u16>,
+    //Call cycles, each listed from the first subroutine back to itself
+    recursion: BTreeSet<Vec<u16>>,
+    invalid: Vec<(u16, u16)>,
+}
+
+fn opcode_at(rom_data: &[u8], base: u16, address: u16) -> u16 {
+    let offset = (address - base) as usize;
+    ((rom_data[offset] as u16) << 8) | rom_data[offset + 1] as u16
+}
+
+//Octo name of an instruction added by SCHIP or XO-CHIP.
+pub fn extension_name(opcode: u16) -> String {
+    let x = (opcode >> 8) & 0xF;
+    let y = (opcode >> 4) & 0xF;
+    match opcode {
+        0x00C0..=0x00CF => format!("scroll-down {}", opcode & 0xF),
+        0x00D0..=0x00DF => format!("scroll-up {}", opcode & 0xF),
+        0x00FB => String::from("scroll-right"),
+        0x00FC => String::from("scroll-left"),
+        0x00FD => String::from("exit"),
+        0x00FE => String::from("lores"),
+        0x00FF => String::from("hires"),
+        0xF000 => String::from("i := long"),
+        0xF002 => String::from("audio"),
+        _ => match (opcode >> 12, opcode & 0xF, opcode & 0xFF) {
+            (0x5, 0x2, _) => format!("save v{:X} - v{:X}", x, y),
+            (0x5, 0x3, _) => format!("load v{:X} - v{:X}", x, y),
+            (0xF, _, 0x30) => format!("i := bighex v{:X}", x),
+            (0xF, _, 0x75) => format!("saveflags v{:X}", x),
+            (0xF, _, 0x85) => format!("loadflags v{:X}", x),
+            (0xF, _, 0x01) => format!("plane {}", x),
+            (0xF, _, 0x3A) => format!("pitch := v{:X}", x),
+            _ => format!("{:04X}", opcode),
+        },
+    }
+}
+
+//First instruction after a load or store that uses I before it is set again, following
+//straight-line code and jumps. Calls end the search since the subroutine may set I itself.
+fn next_use_of_i(analysis: &Analysis, address: u16) -> Option<u16> {
+    let mut address = address.wrapping_add(2);
+    let mut visited = BTreeSet::new();
+
+    for _ in 0..LOOKAHEAD {
+        if !visited.insert(address) {
+            return None;
+        }
+        let instruction = analysis.instructions.get(&address)?;
+        match instruction {
+            Instruction::Draw(..)
+            | Instruction::AddRegisterToIregister(_)
+            | Instruction::RegisterToDecimal(_)
+            | Instruction::RegisterDumpToMemory(_)
+            | Instruction::RegisterLoadFromMemory(_) => return Some(address),
+            Instruction::SetIToAddr(_) | Instruction::SetIregisterToSprite(_) => return None,
+            _ => (),
+        }
+        address = match analysis::flow(instruction) {
+            Flow::Next | Flow::Skip => address.wrapping_add(analysis::length(instruction)),
+            Flow::Jump(target) => target,
+            Flow::Call(_) | Flow::Table(_) | Flow::Return => return None,
+        };
+    }
+    None
+}
+
+fn quirks(analysis: &Analysis) -> Vec<(u16, Quirk, String)> {
+    let mut quirks = Vec::new();
+
+    for (address, instruction) in &analysis.instructions {
+        match *instruction {
+            Instruction::SetRegisterAsShiftRight(x, y)
+            | Instruction::SetRegisterAsShiftLeft(x, y)
+                if x != y =>
+            {
+                let operator = match instruction {
+                    Instruction::SetRegisterAsShiftRight(..) => ">>",
+                    _ => "<<",
+                };
+                quirks.push((
+                    *address,
+                    Quirk::Shift,
+                    format!(
+                        "V{:X} := V{:X} {operator} 1 on CHIP-8, V{:X} {operator}= 1 on SCHIP",
+                        x, y, x
+                    ),
+                ));
+            }
+            Instruction::RegisterDumpToMemory(x) | Instruction::RegisterLoadFromMemory(x) => {
+                if let Some(user) = next_use_of_i(analysis, *address) {
+                    quirks.push((
+                        *address,
+                        Quirk::LoadStore,
+                        format!(
+                            "I is used at {:#05X}, {} bytes further on CHIP-8 than on SCHIP",
+                            user,
+                            x + 1
+                        ),
+                    ));
+                }
+            }
+            Instruction::SetIToAddrPlusV0(table) if table > 0xFF => {
+                quirks.push((
+                    *address,
+                    Quirk::Jump,
+                    format!(
+                        "jumps to {:#05X} + V0 on CHIP-8, {:#05X} + V{:X} on SCHIP",
+                        table,
+                        table,
+                        table >> 8
+                    ),
+                ));
+            }
+            _ => (),
+        }
+    }
+    quirks
+}
+
+fn self_modifying(rom_data: &[u8], analysis: &Analysis) -> Vec<(u16, u16, u16, u16)> {
+    let mut writes = Vec::new();
+
+    for (address, target) in &analysis.stores {
+        let length = match analysis.instructions.get(address) {
+            Some(Instruction::RegisterToDecimal(_)) => 3,
+            Some(Instruction::RegisterDumpToMemory(x)) => *x as u16 + 1,
+            _ => continue,
+        };
+        let end = target.wrapping_add(length - 1);
+        if (*target..=end).any(|written| analysis.is_code(written)) {
+            writes.push((
+                *address,
+                opcode_at(rom_data, analysis.base, *address),
+                *target,
+                end,
+            ));
+        }
+    }
+    writes
+}
+
+//Subroutines called by the code reachable from entry without going through a return.
+fn callees(analysis: &Analysis, entry: u16) -> BTreeSet<u16> {
+    analysis
+        .function_body(entry)
+        .iter()
+        .filter_map(
+            |address| match analysis::flow(&analysis.instructions[address]) {
+                Flow::Call(target) => Some(target),
+                _ => None,
+            },
+        )
+        .collect()
+}
+
+//Longest chain of calls made below function. Calls back into a function on the current path
+//are recorded as recursion instead of being followed.
+fn deepest_chain(
+    calls: &BTreeMap<u16, BTreeSet<u16>>,
+    function: u16,
+    path: &mut Vec<u16>,
+    memo: &mut BTreeMap<u16, Vec<u16>>,
+    recursion: &mut BTreeSet<Vec<u16>>,
+) -> Vec<u16> {
+    if let Some(chain) = memo.get(&function) {
+        return chain.clone();
+    }
+
+    path.push(function);
+    let mut deepest = Vec::new();
+    for callee in calls.get(&function).into_iter().flatten() {
+        if let Some(position) = path.iter().position(|caller| caller == callee) {
+            let mut cycle = path[position..].to_vec();
+            cycle.push(*callee);
+            recursion.insert(cycle);
+            continue;
+        }
+        let mut chain = vec![*callee];
+        chain.extend(deepest_chain(calls, *callee, path, memo, recursion));
+        if chain.len() > deepest.len() {
+            deepest = chain;
+        }
+    }
+    path.pop();
+
+    memo.insert(function, deepest.clone());
+    deepest
+}
+
+fn lint(rom_data: &[u8], analysis: &Analysis) -> Report {
+    let platform = analysis
+        .instructions
+        .keys()
+        .map(|address| {
+            let opcode = opcode_at(rom_data, analysis.base, *address);
+            (*address, opcode, decoder::required_platform(opcode))
+        })
+        .filter(|(_, _, platform)| *platform > Platform::Chip8)
+        .collect();
+
+    let mut calls = BTreeMap::new();
+    for function in analysis.call_targets.iter().chain([&analysis.base]) {
+        calls.insert(*function, callees(analysis, *function));
+    }
+    let mut recursion = BTreeSet::new();
+    let deepest_calls = deepest_chain(
+        &calls,
+        analysis.base,
+        &mut Vec::new(),
+        &mut BTreeMap::new(),
+        &mut recursion,
+    );
+
+    Report {
+        platform,
+        quirks: quirks(analysis),
+        self_modifying: self_modifying(rom_data, analysis),
+        deepest_calls,
+        recursion,
+        invalid: analysis
+            .invalid
+            .iter()
+            .map(|address| (*address, opcode_at(rom_data, analysis.base, *address)))
+            .collect(),
+    }
+}
+
+fn chain_text(chain: &[u16]) -> String {
+    let chain: Vec<String> = chain
+        .iter()
+        .map(|address| format!("{:#05X}", address))
+        .collect();
+    chain.join(" -> ")
+}
+
+fn print_report(report: &Report, base: u16) {
+    let platform = report
+        .platform
+        .iter()
+        .map(|(_, _, platform)| *platform)
+        .max()
+        .unwrap_or(Platform::Chip8);
+
+    println!("Platform: {platform}");
+    for (address, opcode, needs) in &report.platform {
+        println!(
+            "  {:#05X}  {:04X}  {:<18} needs {}",
+            address,
+            opcode,
+            extension_name(*opcode),
+            needs
+        );
+    }
+
+    println!("Quirk-dependent code:");
+    if report.quirks.is_empty() {
+        println!("  none");
+    }
+    for (address, quirk, detail) in &report.quirks {
+        println!("  {:#05X}  {:<10}  {}", address, quirk.name(), detail);
+    }
+
+    println!("Self-modifying code:");
+    if report.self_modifying.is_empty() {
+        println!("  none found");
+    }
+    for (address, opcode, start, end) in &report.self_modifying {
+        println!(
+            "  {:#05X}  {:04X}  writes {:#05X}-{:#05X}, which holds code",
+            address, opcode, start, end
+        );
+    }
+
+    println!("Call stack:");
+    let depth = report.deepest_calls.len();
+    if depth == 0 {
+        println!("  no subroutine calls");
+    } else {
+        let mut chain = vec![base];
+        chain.extend(&report.deepest_calls);
+        println!(
+            "  {} of {STACK_SIZE} entries used by {}",
+            depth,
+            chain_text(&chain)
+        );
+    }
+    if depth > STACK_SIZE {
+        println!("  the stack overflows on this path");
+    }
+    for cycle in &report.recursion {
+        println!(
+            "  recursion, depth depends on run time data: {}",
+            chain_text(cycle)
+        );
+    }
+
+    if !report.invalid.is_empty() {
+        println!("Invalid opcodes reached:");
+        for (address, opcode) in &report.invalid {
+            println!("  {:#05X}  {:04X}", address, opcode);
+        }
+    }
+
+    println!();
+    let quirks: BTreeSet<Quirk> = report.quirks.iter().map(|(_, quirk, _)| *quirk).collect();
+    match platform {
+        Platform::Schip => println!(
+            "Recommended profile: SCHIP (shift ignores VY, load/store leaves I, BXNN adds VX)"
+        ),
+        _ => println!(
+            "Recommended profile: {platform} (shift uses VY, load/store advances I, BNNN adds V0)"
+        ),
+    }
+    if quirks.is_empty() {
+        println!("  no quirk-dependent code found, any {platform} profile should work");
+    } else {
+        let names: Vec<&str> = quirks.iter().map(Quirk::name).collect();
+        println!(
+            "  if the ROM misbehaves, toggle these quirks first: {}",
+            names.join(", ")
+        );
+    }
+}
+
+//Usage: disassembler lint <rom> [--base <address>]
+//Checks the code reachable from the entry point for anything that makes the ROM run
+//differently between interpreters and prints a compatibility report. Only reachable code is
+//looked at, so code reached through computed jumps other than jump tables can be missed.
+pub fn run(args: &[String]) {
+    let usage = || {
+        eprintln!("usage: disassembler lint <rom> [--base <address>]");
+        process::exit(2);
+    };
+
+    let (rom_path, base) = match args {
+        [rom_path] => (rom_path, 0x200),
+        [rom_path, flag, base] | [flag, base, rom_path] if flag == "--base" => {
+            match crate::parse_number(base) {
+                Ok(base) if base <= 0xFFF => (rom_path, base),
+                _ => usage(),
+            }
+        }
+        _ => usage(),
+    };
+
+    let rom_data = crate::load_rom(rom_path, base);
+    let analysis = analysis::analyze(&rom_data, base);
+    let report = lint(&rom_data, &analysis);
+
+    println!(
+        "Compatibility report for {} ({} bytes, {} instructions reached)",
+        rom_path,
+        rom_data.len(),
+        analysis.instructions.len()
+    );
+    println!();
+    print_report(&report, base);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lint_rom(rom: &[u8]) -> Report {
+        lint(rom, &analysis::analyze(rom, 0x200))
+    }
+
+    #[test]
+    fn finds_platform_quirks_and_writes_into_code() {
+        #[rustfmt::skip]
+        let rom = [
+            0x00, 0xFF, //hires
+            0x81, 0x26, //V1 := V2 >> 1
+            0xA2, 0x00, //LD I, 0x200
+            0xF1, 0x55, //LD [I], V1, over the hires
+            0xD0, 0x15, //DRW V0, V1, 5 from I
+            0xB3, 0x00, //JP V0, 0x300
+        ];
+        let report = lint_rom(&rom);
+
+        assert_eq!(report.platform, [(0x200, 0x00FF, Platform::Schip)]);
+        let quirks: Vec<(u16, &str)> = report
+            .quirks
+            .iter()
+            .map(|(address, quirk, _)| (*address, quirk.name()))
+            .collect();
+        assert_eq!(
+            quirks,
+            [(0x202, "shift"), (0x206, "load/store"), (0x20A, "jump")]
+        );
+        assert_eq!(
+            report.quirks[1].2,
+            "I is used at 0x208, 2 bytes further on CHIP-8 than on SCHIP"
+        );
+        assert_eq!(report.self_modifying, [(0x206, 0xF155, 0x200, 0x201)]);
+        assert!(report.invalid.is_empty());
+    }
+
+    #[test]
+    fn leaves_plain_code_alone() {
+        #[rustfmt::skip]
+        let rom = [
+            0x81, 0x16, //V1 >>= 1
+            0xA2, 0x0A, //LD I, 0x20A
+            0xF1, 0x55, //LD [I], V1, into data
+            0xA2, 0x0A, //LD I, 0x20A before I is used again
+            0x12, 0x08, //JP 0x208
+            0x00, 0x00, //Data
+        ];
+        let report = lint_rom(&rom);
+
+        assert!(report.platform.is_empty());
+        assert!(report.quirks.is_empty());
+        assert!(report.self_modifying.is_empty());
+        assert!(report.deepest_calls.is_empty());
+    }
+
+    #[test]
+    fn follows_call_chains_and_recursion() {
+        #[rustfmt::skip]
+        let rom = [
+            0x22, 0x06, //CALL 0x206
+            0x81, 0x28, //Invalid
+            0x00, 0x00, //Data
+            0x22, 0x0A, //0x206: CALL 0x20A
+            0x00, 0xEE, //RET
+            0x22, 0x06, //0x20A: CALL 0x206
+            0x00, 0xEE, //RET
+        ];
+        let report = lint_rom(&rom);
+
+        assert_eq!(report.deepest_calls, [0x206, 0x20A]);
+        assert_eq!(
+            report.recursion,
+            BTreeSet::from([vec![0x206, 0x20A, 0x206]])
+        );
+        assert_eq!(report.invalid, [(0x202, 0x8128)]);
+
+        //Every subroutine calls the next one, one more than the stack holds
+        let mut rom = Vec::new();
+        for call in 1..=STACK_SIZE as u16 + 1 {
+            rom.extend((0x2200 + call * 2).to_be_bytes());
+        }
+        rom.extend([0x00, 0xEE]);
+        assert_eq!(lint_rom(&rom).deepest_calls.len(), STACK_SIZE + 1);
+    }
+}
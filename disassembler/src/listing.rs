@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use chip_8_emulator::cpu::decoder::{self, Instruction};
+
+use crate::analysis::Analysis;
+use crate::octo;
+
+//Data bytes per line outside of sprites.
+const DATA_BYTES_PER_LINE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Octo,
+    Json,
+}
+
+pub struct Line {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    //None for bytes that are data rather than an instruction.
+    pub instruction: Option<Instruction>,
+    pub comment: Option<String>,
+}
+
+impl Line {
+    fn mnemonic(&self) -> String {
+        match &self.instruction {
+            Some(instruction) => instruction.to_string(),
+            None => data_directive(&self.bytes),
+        }
+    }
+}
+
+fn data_directive(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:#04X}", byte)).collect();
+    format!("DB {}", bytes.join(", "))
+}
+
+//Decodes every pair of bytes as an instruction. A trailing odd byte and opcodes that do not
+//decode are listed as data.
+pub fn linear(rom_data: &[u8], base: u16) -> Vec<Line> {
+    rom_data
+        .chunks(2)
+        .enumerate()
+        .map(|(i, bytes)| {
+            let instruction = match bytes {
+                [high, low] => match decoder::decode(((*high as u16) << 8) | *low as u16) {
+                    Instruction::Unknown(_) => None,
+                    instruction => Some(instruction),
+                },
+                _ => None,
+            };
+
+            Line {
+                address: base.wrapping_add((i * 2) as u16),
+                bytes: bytes.to_vec(),
+                instruction,
+                comment: None,
+            }
+        })
+        .collect()
+}
+
+//One row of a sprite drawn with '#' for lit pixels.
+pub fn sprite_art(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .flat_map(|byte| (0..8).map(move |bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' }))
+        .collect()
+}
+
+//Lists the instructions found by the analysis and everything else as data. Data drawn as a
+//sprite gets one row per line with the sprite shown in a comment.
+pub fn recursive(rom_data: &[u8], analysis: &Analysis) -> Vec<Line> {
+    let base = analysis.base;
+
+    //Bytes per sprite row for every address that belongs to a sprite
+    let mut sprite_rows: BTreeMap<u16, usize> = BTreeMap::new();
+    for (address, height) in &analysis.sprites {
+        let (rows, width) = if *height == 0 {
+            (16, 2)
+        } else {
+            (*height as u16, 1)
+        };
+        for row in 0..rows {
+            sprite_rows.insert(address + row * width as u16, width);
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < rom_data.len() {
+        let address = base + offset as u16;
+
+        if let Some(instruction) = analysis.instructions.get(&address) {
+            lines.push(Line {
+                address,
+                bytes: rom_data[offset..offset + 2].to_vec(),
+                instruction: Some(*instruction),
+                comment: None,
+            });
+            offset += 2;
+            continue;
+        }
+
+        if let Some(width) = sprite_rows.get(&address) {
+            let end = (offset + width).min(rom_data.len());
+            let bytes = rom_data[offset..end].to_vec();
+            lines.push(Line {
+                address,
+                comment: Some(sprite_art(&bytes)),
+                bytes,
+                instruction: None,
+            });
+            offset = end;
+            continue;
+        }
+
+        //Plain data runs until the next instruction, sprite or referenced address
+        let mut end = offset + 1;
+        while end < rom_data.len() && end - offset < DATA_BYTES_PER_LINE {
+            let next = base + end as u16;
+            if analysis.is_code(next)
+                || sprite_rows.contains_key(&next)
+                || analysis.data_references.contains(&next)
+                || analysis.jump_targets.contains(&next)
+            {
+                break;
+            }
+            end += 1;
+        }
+        lines.push(Line {
+            address,
+            bytes: rom_data[offset..end].to_vec(),
+            instruction: None,
+            comment: None,
+        });
+        offset = end;
+    }
+
+    lines
+}
+
+pub fn write(lines: &[Line], format: Format, output: &mut dyn Write) -> io::Result<()> {
+    match format {
+        Format::Text => write_text(lines, output),
+        Format::Octo => octo::write_program(lines, output),
+        Format::Json => write_json(lines, output),
+    }
+}
+
+pub fn hex_bytes(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    bytes.join(" ")
+}
+
+fn write_text(lines: &[Line], output: &mut dyn Write) -> io::Result<()> {
+    for line in lines {
+        let text = format!(
+            "{:#06X}  {:<5}  {}",
+            line.address,
+            hex_bytes(&line.bytes),
+            line.mnemonic()
+        );
+        match &line.comment {
+            Some(comment) => writeln!(output, "{:<40} ; {}", text, comment)?,
+            None => writeln!(output, "{}", text)?,
+        }
+    }
+    Ok(())
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::from("\"");
+    for character in text.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            character if (character as u32) < 0x20 => {
+                escaped.push_str(&format!("\\u{:04x}", character as u32))
+            }
+            character => escaped.push(character),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn write_json(lines: &[Line], output: &mut dyn Write) -> io::Result<()> {
+    writeln!(output, "[")?;
+    for (i, line) in lines.iter().enumerate() {
+        let bytes: Vec<String> = line.bytes.iter().map(|byte| byte.to_string()).collect();
+        writeln!(
+            output,
+            "  {{\"address\": {}, \"bytes\": [{}], \"kind\": {}, \"mnemonic\": {}, \"comment\": {}}}{}",
+            line.address,
+            bytes.join(", "),
+            json_string(if line.instruction.is_some() {
+                "code"
+            } else {
+                "data"
+            }),
+            json_string(&line.mnemonic()),
+            line.comment
+                .as_deref()
+                .map(json_string)
+                .unwrap_or_else(|| String::from("null")),
+            if i + 1 < lines.len() { "," } else { "" }
+        )?;
+    }
+    writeln!(output, "]")
+}
@@ -1,109 +1,171 @@
-use std::error::Error;
-use std::fs;
+use std::env;
 use std::fs::File;
-use std::io::Write;
+use std::io::{self, BufWriter, Write};
 use std::process;
 
-//TODO: Read from main args.
-fn main() {
-    //Read file containing ROM
-    let rom_data = read_rom("15.ch8").unwrap_or_else(|err| {
-        println!("Problem reading rom_file: {err}");
-        process::exit(1);
-    });
+use chip_8_emulator::cpu::utils;
+
+mod analysis;
+mod cfg;
+mod decompile;
+mod diff;
+mod lint;
+mod listing;
+mod octo;
+mod png;
+mod rip;
+
+use listing::Format;
 
-    //Create dump file
-    let mut output = File::create("dump.txt").unwrap();
-
-    for i in (0..rom_data.len()).step_by(2) {
-        let opcode_1: u8 = rom_data[i] >> 4;
-        let opcode_2: u8 = (rom_data[i] << 4) >> 4;
-        let opcode_3: u8 = rom_data[i + 1] >> 4;
-        let opcode_4: u8 = (rom_data[i + 1] << 4) >> 4;
-
-        //Decode and execute
-        match opcode_1 {
-            0 => match (opcode_2, opcode_4) {
-                (0x1..=0x8, 0x1..=0x8) => output.write_fmt(format_args!(
-                    "call {}",
-                    opcode_to_address(opcode_2, opcode_3, opcode_4)
-                )),
-                (0x0, 0xE) => output.write_fmt(format_args!("return")),
-                (0x0, 0x0) => output.write_fmt(format_args!("clscr")),
-                _ => panic!("unsupported operation!"),
-            },
-
-            0x1 => self.goto(Cpu::opcode_to_address(opcode_2, opcode_3, opcode_4)),
-            0x2 => self.call_sub(Cpu::opcode_to_address(opcode_2, opcode_3, opcode_4)),
-            0x3 => self.skip_if_register_equals(opcode_2, Cpu::opcode_to_value(opcode_3, opcode_4)),
-            0x4 => {
-                self.skip_if_register_nequals(opcode_2, Cpu::opcode_to_value(opcode_3, opcode_4))
+const USAGE: &str =
+    "usage: disassembler <rom> [-o <out>] [--format text|octo|json] [--base <address>] [--linear]
+                    [--cfg <out.dot>]
+       disassembler diff <our trace> <reference trace>
+       disassembler lint <rom> [--base <address>]
+       disassembler decompile <rom> [-o <out>] [--base <address>]
+       disassembler rip <rom> [-o <sheet.png>] [--trace <trace>] [--base <address>] [--scale <pixels>]";
+
+struct Options {
+    rom_path: String,
+    output_path: Option<String>,
+    format: Format,
+    base: u16,
+    //Decode every byte pair instead of following the control flow.
+    linear: bool,
+    //Where to write the control flow graph. Without -o the listing is then skipped.
+    cfg_path: Option<String>,
+}
+
+fn parse_number(text: &str) -> Result<u16, String> {
+    let result = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => text.parse::<u16>(),
+    };
+    result.map_err(|_| format!("invalid number '{text}'"))
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut rom_path = None;
+    let mut output_path = None;
+    let mut format = Format::Text;
+    let mut base = 0x200;
+    let mut linear = false;
+    let mut cfg_path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value for {arg}"))
+        };
+
+        match arg.as_str() {
+            "-o" | "--output" => output_path = Some(value()?.clone()),
+            "--format" => {
+                format = match value()?.as_str() {
+                    "text" => Format::Text,
+                    "octo" => Format::Octo,
+                    "json" => Format::Json,
+                    other => return Err(format!("unknown format '{other}'")),
+                }
+            }
+            "--base" => {
+                base = parse_number(value()?)?;
+                if base > 0xFFF {
+                    return Err(String::from("base address must be below 0x1000"));
+                }
             }
-            0x5 => self.skip_if_register_equals_register(opcode_2, opcode_3),
-            0x6 => self.set_register_to_value(opcode_2, Cpu::opcode_to_value(opcode_3, opcode_4)),
-            0x7 => self.add_to_register(opcode_2, Cpu::opcode_to_value(opcode_3, opcode_4)),
-            0x8 => match opcode_4 {
-                0 => self.set_reigster_as_register(opcode_2, opcode_3),
-                1 => self.set_register_as_bitwise_or_register(opcode_2, opcode_3),
-                2 => self.set_register_as_bitwise_and_register(opcode_2, opcode_3),
-                3 => self.set_register_as_bitwise_xor_register(opcode_2, opcode_3),
-                4 => self.set_register_as_addition(opcode_2, opcode_3),
-                5 => self.set_register_as_subtraction(opcode_2, opcode_3),
-                6 => self.set_register_as_shift_right(opcode_2),
-                7 => self.set_register_as_b_sub_a(opcode_2, opcode_3),
-                14 => self.set_register_as_shift_left(opcode_2),
-                _ => panic!("unsupported operation!"),
-            },
-            0x9 => self.skip_if_register_nequal_register(opcode_2, opcode_3),
-            0xA => self.set_i_to_addr(Cpu::opcode_to_address(opcode_2, opcode_3, opcode_4)),
-            0xB => self.set_i_to_addr_plus_v0(Cpu::opcode_to_address(opcode_2, opcode_3, opcode_4)),
-            0xC => self.set_register_as_bitwise_and_with_random(
-                opcode_2,
-                Cpu::opcode_to_value(opcode_3, opcode_4),
-            ),
-            0xD => self.draw(opcode_2, opcode_3, opcode_4),
-            0xE => match (opcode_3, opcode_4) {
-                (0x9, 0xE) => self.skip_if_key_pressed(opcode_2),
-                (0xA, 0x1) => self.skip_if_key_npressed(opcode_2),
-                _ => panic!(
-                    "unsupported operation! {:#x}{:#x}{:#x}{:#x}",
-                    opcode_1, opcode_2, opcode_3, opcode_4
-                ),
-            },
-            0xF => match (opcode_3, opcode_4) {
-                (0x0, 0x7) => self.set_register_to_tregister(opcode_2),
-                (0x0, 0xA) => self.set_register_to_key(opcode_2),
-                (0x1, 0x5) => self.set_tregister_to_register(opcode_2),
-                (0x1, 0x8) => self.set_sregister_to_register(opcode_2),
-                (0x1, 0xE) => self.add_register_to_iregister(opcode_2),
-                (0x2, 0x9) => self.set_iregister_to_sprite(opcode_2),
-                (0x3, 0x3) => self.register_to_decimal(opcode_2),
-                (0x5, 0x5) => self.register_dump_to_memory(opcode_2),
-                (0x6, 0x5) => self.register_load_from_memory(opcode_2),
-                _ => panic!("unsupported operation!"),
-            },
-            _ => panic!("unsupported operation!"),
+            "--linear" => linear = true,
+            "--cfg" => cfg_path = Some(value()?.clone()),
+            flag if flag.starts_with('-') => return Err(format!("unknown option '{flag}'")),
+            path if rom_path.is_none() => rom_path = Some(path.to_string()),
+            extra => return Err(format!("unexpected argument '{extra}'")),
         }
     }
+
+    Ok(Options {
+        rom_path: rom_path.ok_or_else(|| String::from("missing rom path"))?,
+        output_path,
+        format,
+        base,
+        linear,
+        cfg_path,
+    })
 }
 
-fn opcode_to_address(opcode_1: u8, opcode_2: u8, opcode_3: u8) -> u16 {
-    let mut address = 0;
-    address |= (opcode_1 as u16) << 8;
-    address |= (opcode_2 as u16) << 4;
-    address |= opcode_3 as u16;
-    address
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    match args.get(1).map(String::as_str) {
+        Some("diff") => diff::run(&args[2..]),
+        Some("lint") => lint::run(&args[2..]),
+        Some("decompile") => decompile::run(&args[2..]),
+        Some("rip") => rip::run(&args[2..]),
+        _ => {
+            let options = parse_options(&args[1..]).unwrap_or_else(|err| {
+                eprintln!("{err}\n{USAGE}");
+                process::exit(2);
+            });
+            disassemble(&options);
+        }
+    }
 }
 
-fn opcode_to_value(opcode_1: u8, opcode_2: u8) -> u8 {
-    let mut value: u8 = 0;
-    value |= (opcode_1) << 4;
-    value |= opcode_2;
-    value
+//Reads the ROM, exiting if it is empty or does not fit in memory at base.
+fn load_rom(path: &str, base: u16) -> Vec<u8> {
+    let rom_data = utils::read_rom(path).unwrap_or_else(|err| {
+        eprintln!("Problem reading rom_file: {err}");
+        process::exit(1);
+    });
+
+    if rom_data.is_empty() {
+        eprintln!("{path} is empty");
+        process::exit(1);
+    }
+    if base as usize + rom_data.len() > 0x1000 {
+        eprintln!("ROM does not fit in memory when loaded at {:#05X}", base);
+        process::exit(1);
+    }
+    rom_data
 }
 
-fn read_rom(path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
-    let rom_data = fs::read(path)?;
+fn disassemble(options: &Options) {
+    let rom_data = load_rom(&options.rom_path, options.base);
 
-    Ok(rom_data)
+    let analysis = analysis::analyze(&rom_data, options.base);
+
+    if let Some(path) = &options.cfg_path {
+        let result = File::create(path).and_then(|file| {
+            let mut output = BufWriter::new(file);
+            cfg::write_dot(&analysis, &mut output).and_then(|_| output.flush())
+        });
+        if let Err(err) = result {
+            eprintln!("Problem writing {path}: {err}");
+            process::exit(1);
+        }
+        if options.output_path.is_none() {
+            return;
+        }
+    }
+
+    //Write to the dump file if one was given, stdout otherwise
+    let mut output: Box<dyn Write> = match &options.output_path {
+        Some(path) => Box::new(BufWriter::new(File::create(path).unwrap_or_else(|err| {
+            eprintln!("Problem creating {path}: {err}");
+            process::exit(1);
+        }))),
+        None => Box::new(BufWriter::new(io::stdout())),
+    };
+
+    let lines = if options.linear {
+        listing::linear(&rom_data, options.base)
+    } else {
+        listing::recursive(&rom_data, &analysis)
+    };
+    if let Err(err) =
+        listing::write(&lines, options.format, &mut output).and_then(|_| output.flush())
+    {
+        eprintln!("Problem writing disassembly: {err}");
+        process::exit(1);
+    }
 }
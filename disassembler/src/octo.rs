@@ -0,0 +1,258 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+use chip_8_emulator::cpu::decoder::Instruction;
+
+use crate::listing::{self, Line};
+
+//Labels given to the lines of a listing and the backward jumps written as loop/again.
+struct Labels {
+    names: BTreeMap<u16, String>,
+    //Loop start addresses, and the addresses of the jumps closing them.
+    loop_starts: BTreeSet<u16>,
+    loop_ends: BTreeSet<u16>,
+}
+
+impl Labels {
+    fn operand(&self, address: u16) -> String {
+        match self.names.get(&address) {
+            Some(name) => name.clone(),
+            None => format!("{:#05X}", address),
+        }
+    }
+}
+
+fn find_labels(lines: &[Line]) -> Labels {
+    let starts: BTreeSet<u16> = lines.iter().map(|line| line.address).collect();
+    let entry = lines.first().map(|line| line.address);
+
+    //Count references so single-use backward jumps can become loops
+    let mut references: BTreeMap<u16, usize> = BTreeMap::new();
+    for instruction in lines.iter().filter_map(|line| line.instruction.as_ref()) {
+        if let Some(target) = instruction.target_address() {
+            *references.entry(target).or_default() += 1;
+        }
+    }
+
+    //loop/again pairs have to nest, accept them innermost first
+    let mut loops: Vec<(u16, u16)> = Vec::new();
+    for line in lines {
+        if let Some(Instruction::Goto(target)) = line.instruction {
+            let is_loop = target <= line.address
+                && Some(target) != entry
+                && references.get(&target) == Some(&1)
+                && starts.contains(&target)
+                && loops
+                    .iter()
+                    .all(|(start, end)| target > *end || target <= *start);
+            if is_loop {
+                loops.push((target, line.address));
+            }
+        }
+    }
+
+    let mut names = BTreeMap::new();
+    if let Some(entry) = entry {
+        names.insert(entry, String::from("main"));
+    }
+    for line in lines {
+        let (target, prefix) = match line.instruction {
+            Some(Instruction::CallSub(target)) => (target, "sub"),
+            Some(Instruction::Goto(target)) | Some(Instruction::SetIToAddrPlusV0(target)) => {
+                (target, "label")
+            }
+            Some(Instruction::SetIToAddr(target)) => (target, "data"),
+            _ => continue,
+        };
+        let is_loop = loops.iter().any(|(start, _)| *start == target);
+        if starts.contains(&target) && !is_loop {
+            let name = format!("{prefix}-{:03X}", target);
+            //Calls win over jumps, jumps over data
+            names
+                .entry(target)
+                .and_modify(|existing: &mut String| {
+                    if existing != "main" && prefix == "sub" {
+                        *existing = name.clone();
+                    }
+                })
+                .or_insert(name);
+        }
+    }
+
+    Labels {
+        names,
+        loop_starts: loops.iter().map(|(start, _)| *start).collect(),
+        loop_ends: loops.iter().map(|(_, end)| *end).collect(),
+    }
+}
+
+//Writes a listing as an Octo program that assembles back to the same bytes: jump, call and
+//I targets get labels, single-use backward jumps become loop/again and everything that is not
+//an instruction Octo can express is written as bytes.
+pub fn write_program(lines: &[Line], output: &mut dyn Write) -> io::Result<()> {
+    let labels = find_labels(lines);
+
+    if let Some(first) = lines.first() {
+        if first.address != 0x200 {
+            writeln!(output, ":org {:#05X}", first.address)?;
+        }
+    }
+
+    let mut indent = 0;
+    for line in lines {
+        if let Some(name) = labels.names.get(&line.address) {
+            if name != "main" {
+                writeln!(output)?;
+            }
+            writeln!(output, ": {name}")?;
+        }
+        if labels.loop_starts.contains(&line.address) {
+            writeln!(output, "{}loop", "  ".repeat(indent + 1))?;
+            indent += 1;
+        }
+
+        let statement = if labels.loop_ends.contains(&line.address) {
+            indent -= 1;
+            Some(String::from("again"))
+        } else {
+            line.instruction
+                .as_ref()
+                .and_then(|instruction| statement(instruction, &labels))
+        };
+        let statement = statement.unwrap_or_else(|| {
+            let bytes: Vec<String> = line
+                .bytes
+                .iter()
+                .map(|byte| format!("{:#04X}", byte))
+                .collect();
+            bytes.join(" ")
+        });
+
+        let text = format!("{}{}", "  ".repeat(indent + 1), statement);
+        writeln!(
+            output,
+            "{:<30} # {:#06X}: {}{}",
+            text,
+            line.address,
+            listing::hex_bytes(&line.bytes),
+            line.comment
+                .as_ref()
+                .map(|comment| format!(" {comment}"))
+                .unwrap_or_default()
+        )?;
+    }
+    Ok(())
+}
+
+//Octo source for a single instruction, or None when Octo has no syntax for it and it has to
+//be written out as raw bytes.
+fn statement(instruction: &Instruction, labels: &Labels) -> Option<String> {
+    let text = match *instruction {
+        Instruction::Call(_) | Instruction::Unknown(_) => return None,
+        Instruction::ClearScreen => String::from("clear"),
+        Instruction::ReturnFromSubroutine => String::from("return"),
+        Instruction::Goto(address) => format!("jump {}", labels.operand(address)),
+        Instruction::CallSub(address) => match labels.names.get(&address) {
+            Some(name) => name.clone(),
+            None => format!(":call {:#05X}", address),
+        },
+        //Octo conditions describe when the next instruction runs, the opposite of the skip
+        Instruction::SkipIfRegisterEquals(x, value) => {
+            format!("if v{:x} != {:#04X} then", x, value)
+        }
+        Instruction::SkipIfRegisterNequals(x, value) => {
+            format!("if v{:x} == {:#04X} then", x, value)
+        }
+        Instruction::SkipIfRegisterEqualsRegister(x, y) => format!("if v{:x} != v{:x} then", x, y),
+        Instruction::SetRegisterToValue(x, value) => format!("v{:x} := {:#04X}", x, value),
+        Instruction::AddToRegister(x, value) => format!("v{:x} += {:#04X}", x, value),
+        Instruction::SetRegisterAsRegister(x, y) => format!("v{:x} := v{:x}", x, y),
+        Instruction::SetRegisterAsBitwiseOrRegister(x, y) => format!("v{:x} |= v{:x}", x, y),
+        Instruction::SetRegisterAsBitwiseAndRegister(x, y) => format!("v{:x} &= v{:x}", x, y),
+        Instruction::SetRegisterAsBitwiseXorRegister(x, y) => format!("v{:x} ^= v{:x}", x, y),
+        Instruction::SetRegisterAsAddition(x, y) => format!("v{:x} += v{:x}", x, y),
+        Instruction::SetRegisterAsSubtraction(x, y) => format!("v{:x} -= v{:x}", x, y),
+        Instruction::SetRegisterAsShiftRight(x, y) => format!("v{:x} >>= v{:x}", x, y),
+        Instruction::SetRegisterAsBSubA(x, y) => format!("v{:x} =- v{:x}", x, y),
+        Instruction::SetRegisterAsShiftLeft(x, y) => format!("v{:x} <<= v{:x}", x, y),
+        Instruction::SkipIfRegisterNequalRegister(x, y) => format!("if v{:x} == v{:x} then", x, y),
+        Instruction::SetIToAddr(address) => format!("i := {}", labels.operand(address)),
+        Instruction::SetIToAddrPlusV0(address) => format!("jump0 {}", labels.operand(address)),
+        Instruction::SetRegisterAsBitwiseAndWithRandom(x, value) => {
+            format!("v{:x} := random {:#04X}", x, value)
+        }
+        Instruction::Draw(x, y, height) => format!("sprite v{:x} v{:x} {}", x, y, height),
+        Instruction::SkipIfKeyPressed(x) => format!("if v{:x} -key then", x),
+        Instruction::SkipIfKeyNpressed(x) => format!("if v{:x} key then", x),
+        Instruction::SetRegisterToTregister(x) => format!("v{:x} := delay", x),
+        Instruction::SetRegisterToKey(x) => format!("v{:x} := key", x),
+        Instruction::SetTregisterToRegister(x) => format!("delay := v{:x}", x),
+        Instruction::SetSregisterToRegister(x) => format!("buzzer := v{:x}", x),
+        Instruction::AddRegisterToIregister(x) => format!("i += v{:x}", x),
+        Instruction::SetIregisterToSprite(x) => format!("i := hex v{:x}", x),
+        Instruction::RegisterToDecimal(x) => format!("bcd v{:x}", x),
+        Instruction::RegisterDumpToMemory(x) => format!("save v{:x}", x),
+        Instruction::RegisterLoadFromMemory(x) => format!("load v{:x}", x),
+    };
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis;
+    use chip_8_emulator::cpu::decoder::Platform;
+    use chip_8_emulator::octo;
+
+    //Disassembles a ROM into Octo and compiles the result again.
+    fn round_trip(rom_data: &[u8]) -> Vec<u8> {
+        let analysis = analysis::analyze(rom_data, 0x200);
+        let lines = listing::recursive(rom_data, &analysis);
+        let mut source = Vec::new();
+        write_program(&lines, &mut source).unwrap();
+        let source = String::from_utf8(source).unwrap();
+
+        octo::compile(&source, "test.8o", Platform::Chip8)
+            .unwrap_or_else(|err| panic!("{err} in\n{source}"))
+            .rom
+    }
+
+    #[test]
+    fn compiled_program_comes_back_the_same() {
+        let source = "
+            : main
+              i := box
+              v0 := 0
+              loop
+                sprite v0 v1 2
+                v0 += 8
+                if v0 == 64 then v1 += 1
+                next-row
+              again
+
+            : next-row
+              v2 := random 0xFF
+              if v2 != 0 begin
+                v3 := v2
+                v3 >>= v3
+              end
+              return
+
+            : box
+              0xF0 0x90
+        ";
+        let rom_data = octo::compile(source, "test.8o", Platform::Chip8)
+            .unwrap()
+            .rom;
+
+        assert_eq!(round_trip(&rom_data), rom_data);
+    }
+
+    #[test]
+    fn data_and_odd_addresses_survive() {
+        //JP 0x205, three data bytes, then CLS and a jump to itself at an odd address
+        let rom_data = [0x12, 0x05, 0xAB, 0xCD, 0xEF, 0x00, 0xE0, 0x12, 0x07];
+
+        assert_eq!(round_trip(&rom_data), rom_data);
+    }
+}
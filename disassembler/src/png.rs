@@ -0,0 +1,180 @@
+//Minimal PNG writer for 8-bit grayscale images. The image data goes into stored (uncompressed)
+//deflate blocks, which keeps the encoder small at the cost of file size.
+
+use std::io::{self, Write};
+
+const SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+//Largest payload of a stored deflate block.
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+pub struct GrayImage {
+    pub width: usize,
+    pub height: usize,
+    //Row-major, one byte per pixel
+    pub pixels: Vec<u8>,
+}
+
+impl GrayImage {
+    pub fn new(width: usize, height: usize, background: u8) -> GrayImage {
+        GrayImage {
+            width,
+            height,
+            pixels: vec![background; width * height],
+        }
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, value: u8) {
+        if x < self.width && y < self.height {
+            self.pixels[y * self.width + x] = value;
+        }
+    }
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in bytes {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+//zlib stream holding data in stored blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    //Deflate, 32K window, no preset dictionary, check bits making the header a multiple of 31
+    let mut stream = vec![0x78, 0x01];
+
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        stream.extend([0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let length = block.len() as u16;
+        stream.push(last as u8);
+        stream.extend(length.to_le_bytes());
+        stream.extend((!length).to_le_bytes());
+        stream.extend(block);
+    }
+
+    stream.extend(adler32(data).to_be_bytes());
+    stream
+}
+
+fn write_chunk(output: &mut dyn Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    output.write_all(&(data.len() as u32).to_be_bytes())?;
+    let mut checked = kind.to_vec();
+    checked.extend(data);
+    output.write_all(&checked)?;
+    output.write_all(&crc32(&checked).to_be_bytes())
+}
+
+pub fn write(image: &GrayImage, output: &mut dyn Write) -> io::Result<()> {
+    output.write_all(SIGNATURE)?;
+
+    let mut header = Vec::new();
+    header.extend((image.width as u32).to_be_bytes());
+    header.extend((image.height as u32).to_be_bytes());
+    //Bit depth 8, grayscale, deflate, adaptive filtering, no interlace
+    header.extend([8, 0, 0, 0, 0]);
+    write_chunk(output, b"IHDR", &header)?;
+
+    //Every scanline starts with its filter type, 0 for none
+    let mut scanlines = Vec::with_capacity((image.width + 1) * image.height);
+    for row in image.pixels.chunks(image.width.max(1)).take(image.height) {
+        scanlines.push(0);
+        scanlines.extend(row);
+    }
+    write_chunk(output, b"IDAT", &zlib_stored(&scanlines))?;
+    write_chunk(output, b"IEND", &[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Data of the stored blocks in a zlib stream, checked against its Adler-32.
+    fn inflate_stored(stream: &[u8]) -> Vec<u8> {
+        assert_eq!(stream[..2], [0x78, 0x01]);
+        let mut data = Vec::new();
+        let mut position = 2;
+        loop {
+            let last = stream[position];
+            let length = u16::from_le_bytes([stream[position + 1], stream[position + 2]]);
+            let check = u16::from_le_bytes([stream[position + 3], stream[position + 4]]);
+            assert_eq!(check, !length);
+            position += 5;
+            data.extend(&stream[position..position + length as usize]);
+            position += length as usize;
+            if last == 1 {
+                break;
+            }
+        }
+        assert_eq!(stream[position..], adler32(&data).to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn checksums_match_known_values() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn splits_stored_blocks() {
+        let data: Vec<u8> = (0..MAX_STORED_BLOCK + 10).map(|i| i as u8).collect();
+        let stream = zlib_stored(&data);
+        assert_eq!(stream[2], 0);
+        assert_eq!(inflate_stored(&stream), data);
+        assert!(inflate_stored(&zlib_stored(&[])).is_empty());
+    }
+
+    #[test]
+    fn writes_chunks_that_read_back() {
+        let mut image = GrayImage::new(3, 2, 0x40);
+        image.set(0, 0, 0xFF);
+        image.set(2, 1, 0x00);
+        image.set(3, 0, 0x00);
+        let mut output = Vec::new();
+        write(&image, &mut output).unwrap();
+
+        assert_eq!(output[..8], *SIGNATURE);
+        let mut chunks = Vec::new();
+        let mut position = 8;
+        while position < output.len() {
+            let length = u32::from_be_bytes(output[position..position + 4].try_into().unwrap());
+            let checked = &output[position + 4..position + 8 + length as usize];
+            let end = position + 12 + length as usize;
+            let crc = u32::from_be_bytes(output[end - 4..end].try_into().unwrap());
+            assert_eq!(crc, crc32(checked));
+            chunks.push((checked[..4].to_vec(), checked[4..].to_vec()));
+            position = end;
+        }
+
+        let kinds: Vec<&[u8]> = chunks.iter().map(|(kind, _)| kind.as_slice()).collect();
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 3, 0, 0, 0, 2, 8, 0, 0, 0, 0]);
+        assert_eq!(
+            inflate_stored(&chunks[1].1),
+            [0, 0xFF, 0x40, 0x40, 0, 0x40, 0x40, 0x00]
+        );
+        assert!(chunks[2].1.is_empty());
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::process;
+
+use chip_8_emulator::cpu::decoder::{self, Platform};
+use chip_8_emulator::cpu::Cpu;
+use chip_8_emulator::trace::{self, Location};
+
+use crate::analysis::{self, Analysis};
+use crate::listing;
+use crate::png::{self, GrayImage};
+
+const USAGE: &str =
+    "usage: disassembler rip <rom> [-o <sheet.png>] [--trace <trace>] [--base <address>]
+                      [--scale <pixels>]";
+
+//Sprites per row of the sheet.
+const SHEET_COLUMNS: usize = 8;
+
+//Gray levels used on the sheet
+const GAP: u8 = 0x40;
+const UNLIT: u8 = 0x00;
+const LIT: u8 = 0xFF;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Found {
+    Statically,
+    InTrace,
+    Both,
+}
+
+impl Found {
+    fn name(&self) -> &'static str {
+        match self {
+            Found::Statically => "static",
+            Found::InTrace => "trace",
+            Found::Both => "static and trace",
+        }
+    }
+}
+
+//Sprites found, keyed by address, height and bytes.
+type Sprites = BTreeMap<(u16, u8, Vec<u8>), Found>;
+
+//Rows and bytes per row of a sprite drawn by DXYN with the given height. 0 is SCHIP's 16x16.
+fn sprite_size(height: u8) -> (usize, usize) {
+    if height == 0 {
+        (16, 2)
+    } else {
+        (height as usize, 1)
+    }
+}
+
+//Whether DXY0 draws a 16x16 sprite. Only SCHIP and later do, on CHIP-8 it draws nothing, so
+//the ROM has to use some other extension before its DXY0 are taken for sprites.
+fn has_large_sprites(analysis: &Analysis) -> bool {
+    analysis
+        .instructions
+        .values()
+        .any(|instruction| decoder::required_platform(instruction.encode()) > Platform::Chip8)
+}
+
+//Memory as Cpu sets it up: the font at the bottom and the ROM at base.
+fn memory_image(rom_data: &[u8], base: u16) -> Vec<u8> {
+    let mut memory = Cpu::new(Vec::new()).get_memory().to_vec();
+    memory[base as usize..base as usize + rom_data.len()].copy_from_slice(rom_data);
+    memory
+}
+
+fn read_sprite(memory: &[u8], address: u16, height: u8) -> Vec<u8> {
+    let (rows, width) = sprite_size(height);
+    (0..rows * width)
+        .map(|offset| memory[(address as usize + offset) & 0xFFF])
+        .collect()
+}
+
+//Sprites drawn while the trace was recorded, read from memory as it was at that moment so
+//sprites built at run time are found too. I and memory are rebuilt from the changes listed in
+//the trace, starting from the state Cpu::new leaves them in.
+fn traced_sprites(
+    records: &[trace::TraceRecord],
+    mut memory: Vec<u8>,
+    large_sprites: bool,
+) -> Vec<(u16, u8, Vec<u8>)> {
+    let mut sprites = Vec::new();
+    let mut i_register = 0;
+
+    for record in records {
+        if record.opcode & 0xF000 == 0xD000 {
+            let height = (record.opcode & 0xF) as u8;
+            if height != 0 || large_sprites {
+                sprites.push((i_register, height, read_sprite(&memory, i_register, height)));
+            }
+        }
+        for change in &record.changes {
+            match change.location {
+                Location::IRegister => i_register = change.value,
+                Location::Memory(address) => memory[address as usize & 0xFFF] = change.value as u8,
+                _ => (),
+            }
+        }
+    }
+    sprites
+}
+
+//Sprites drawn by the reachable code, and the ones drawn in the trace when there is one.
+fn collect_sprites(rom_data: &[u8], base: u16, records: Option<&[trace::TraceRecord]>) -> Sprites {
+    let analysis = analysis::analyze(rom_data, base);
+    let large_sprites = has_large_sprites(&analysis);
+    let memory = memory_image(rom_data, base);
+
+    //The same bytes drawn from the same address are listed once
+    let mut sprites = BTreeMap::new();
+    for (address, height) in &analysis.sprites {
+        if *height == 0 && !large_sprites {
+            continue;
+        }
+        let data = read_sprite(&memory, *address, *height);
+        sprites.insert((*address, *height, data), Found::Statically);
+    }
+    for sprite in traced_sprites(records.unwrap_or_default(), memory, large_sprites) {
+        sprites
+            .entry(sprite)
+            .and_modify(|found| {
+                if *found == Found::Statically {
+                    *found = Found::Both
+                }
+            })
+            .or_insert(Found::InTrace);
+    }
+    sprites
+}
+
+fn write_sheet(sprites: &Sprites, scale: usize, path: &str) -> std::io::Result<()> {
+    //Every cell fits a 16x16 sprite plus a gap
+    let cell = 17 * scale;
+    let rows = sprites.len().div_ceil(SHEET_COLUMNS).max(1);
+    let mut image = GrayImage::new(SHEET_COLUMNS * cell + scale, rows * cell + scale, GAP);
+
+    for (index, (_, height, data)) in sprites.keys().enumerate() {
+        let (sprite_rows, width) = sprite_size(*height);
+        let left = (index % SHEET_COLUMNS) * cell + scale;
+        let top = (index / SHEET_COLUMNS) * cell + scale;
+
+        for row in 0..sprite_rows {
+            for column in 0..width * 8 {
+                let byte = data[row * width + column / 8];
+                let value = if byte & (0x80 >> (column % 8)) != 0 {
+                    LIT
+                } else {
+                    UNLIT
+                };
+                for y in 0..scale {
+                    for x in 0..scale {
+                        image.set(left + column * scale + x, top + row * scale + y, value);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut output = BufWriter::new(File::create(path)?);
+    png::write(&image, &mut output)?;
+    output.flush()
+}
+
+fn write_listing(sprites: &Sprites, path: &str) -> std::io::Result<()> {
+    let mut output = BufWriter::new(File::create(path)?);
+
+    for (index, ((address, height, data), found)) in sprites.iter().enumerate() {
+        let (rows, width) = sprite_size(*height);
+        let size = if *height == 0 {
+            String::from("16x16")
+        } else {
+            format!("8x{height}")
+        };
+        writeln!(
+            output,
+            "sprite {index} at {:#05X}, {size}, found {} (column {}, row {})",
+            address,
+            found.name(),
+            index % SHEET_COLUMNS,
+            index / SHEET_COLUMNS
+        )?;
+        for row in data.chunks(width).take(rows) {
+            writeln!(output, "  {}", listing::sprite_art(row))?;
+        }
+        writeln!(output)?;
+    }
+    output.flush()
+}
+
+//Usage: disassembler rip <rom> [-o <sheet.png>] [--trace <trace>] [--base <address>]
+//                        [--scale <pixels>]
+//Collects the sprites drawn by ANNN ... DXYN sequences in the reachable code, and the ones
+//drawn in a trace when one is given, into a PNG sheet. A text listing with the address and
+//size of each sprite goes next to the sheet. Sprites are numbered by address, left to right.
+//DXY0 only counts as a 16x16 sprite in ROMs that use SCHIP or XO-CHIP instructions.
+pub fn run(args: &[String]) {
+    let usage = |err: String| -> ! {
+        eprintln!("{err}\n{USAGE}");
+        process::exit(2);
+    };
+
+    let mut rom_path = None;
+    let mut sheet_path = None;
+    let mut trace_path = None;
+    let mut base = 0x200;
+    let mut scale = 4;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| usage(format!("missing value for {arg}")))
+        };
+        match arg.as_str() {
+            "-o" | "--output" => sheet_path = Some(value().clone()),
+            "--trace" => trace_path = Some(value().clone()),
+            "--base" => {
+                base = match crate::parse_number(value()) {
+                    Ok(base) if base <= 0xFFF => base,
+                    Ok(_) => usage(String::from("base address must be below 0x1000")),
+                    Err(err) => usage(err),
+                }
+            }
+            "--scale" => {
+                scale = match crate::parse_number(value()) {
+                    Ok(scale) if (1..=32).contains(&scale) => scale as usize,
+                    _ => usage(String::from("scale must be between 1 and 32")),
+                }
+            }
+            flag if flag.starts_with('-') => usage(format!("unknown option '{flag}'")),
+            path if rom_path.is_none() => rom_path = Some(path.to_string()),
+            extra => usage(format!("unexpected argument '{extra}'")),
+        }
+    }
+    let rom_path = rom_path.unwrap_or_else(|| usage(String::from("missing rom path")));
+    let sheet_path = sheet_path.unwrap_or_else(|| {
+        Path::new(&rom_path)
+            .with_extension("png")
+            .to_string_lossy()
+            .into_owned()
+    });
+    let listing_path = Path::new(&sheet_path)
+        .with_extension("txt")
+        .to_string_lossy()
+        .into_owned();
+
+    let rom_data = crate::load_rom(&rom_path, base);
+    let records = trace_path.map(|path| {
+        trace::read_trace(&path).unwrap_or_else(|err| {
+            eprintln!("Problem reading trace {path}: {err}");
+            process::exit(1);
+        })
+    });
+    let sprites = collect_sprites(&rom_data, base, records.as_deref());
+
+    if let Err(err) = write_sheet(&sprites, scale, &sheet_path) {
+        eprintln!("Problem writing {sheet_path}: {err}");
+        process::exit(1);
+    }
+    if let Err(err) = write_listing(&sprites, &listing_path) {
+        eprintln!("Problem writing {listing_path}: {err}");
+        process::exit(1);
+    }
+    println!(
+        "{} sprites written to {sheet_path} and {listing_path}",
+        sprites.len()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip_8_emulator::trace::{Change, TraceRecord};
+
+    //Draws an 8x5 sprite, then a DXY0 one, with 00E0 or SCHIP's 00FF in between.
+    fn rom(between: [u8; 2]) -> Vec<u8> {
+        #[rustfmt::skip]
+        let mut rom = vec![
+            0xA2, 0x0C, //LD I, 0x20C
+            0xD0, 0x15, //DRW V0, V1, 5
+            0xA2, 0x11, //LD I, 0x211
+            0xD0, 0x10, //DRW V0, V1, 0
+            between[0], between[1],
+            0x12, 0x0A, //JP 0x20A
+            0xF0, 0x90, 0x90, 0x90, 0xF0,
+        ];
+        rom.extend((0..32).map(|row| row as u8));
+        rom
+    }
+
+    fn record(opcode: u16, changes: Vec<Change>) -> TraceRecord {
+        TraceRecord {
+            cycle: 0,
+            program_counter: 0x200,
+            opcode,
+            changes,
+        }
+    }
+
+    #[test]
+    fn takes_dxy0_for_16x16_sprites_only_past_chip_8() {
+        let small = (0x20C, 5, vec![0xF0, 0x90, 0x90, 0x90, 0xF0]);
+        let large = (0x211, 0, (0..32).collect::<Vec<u8>>());
+
+        let sprites = collect_sprites(&rom([0x00, 0xE0]), 0x200, None);
+        assert!(sprites.keys().eq([&small]));
+
+        let sprites = collect_sprites(&rom([0x00, 0xFF]), 0x200, None);
+        assert!(sprites.keys().eq([&small, &large]));
+    }
+
+    #[test]
+    fn reads_traced_sprites_from_memory_at_the_time() {
+        let change = |location, value| Change { location, value };
+        let records = [
+            record(0xA20C, vec![change(Location::IRegister, 0x20C)]),
+            record(0xD015, Vec::new()),
+            //A sprite written at run time, then overwritten after it was drawn
+            record(0xA300, vec![change(Location::IRegister, 0x300)]),
+            record(0xF055, vec![change(Location::Memory(0x300), 0x3C)]),
+            record(0xD011, vec![change(Location::Register(0xF), 0)]),
+            record(0xF055, vec![change(Location::Memory(0x300), 0xFF)]),
+            record(0xD010, Vec::new()),
+        ];
+
+        let sprites = collect_sprites(&rom([0x00, 0xE0]), 0x200, Some(&records));
+        let found: Vec<_> = sprites
+            .iter()
+            .map(|((address, height, data), found)| (*address, *height, data.clone(), *found))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (0x20C, 5, vec![0xF0, 0x90, 0x90, 0x90, 0xF0], Found::Both),
+                (0x300, 1, vec![0x3C], Found::InTrace),
+            ]
+        );
+    }
+}
@@ -1,14 +1,41 @@
 // use std::error::Error;
 // use std::fs;
 
+mod ansi_client;
+#[cfg(feature = "ncurses")]
+mod call_stack;
+//Only the ncurses debugger uses all of these
+#[cfg_attr(not(feature = "ncurses"), allow(dead_code))]
+mod command;
 mod debug_client;
+#[cfg_attr(not(feature = "ncurses"), allow(dead_code))]
+mod debug_info;
+#[cfg(feature = "ncurses")]
+mod disassembly;
+mod emulator;
+mod key_hold;
+mod keymap;
+#[cfg(feature = "ncurses")]
+mod keypad_panel;
+mod kitty_keyboard;
+mod layout;
+mod loader;
+#[cfg(feature = "ncurses")]
 mod ncurses_client;
+mod options;
+mod persistence;
+mod raw_terminal;
+mod renderer;
+mod theme;
 
+pub use ansi_client::AnsiClient;
 pub use debug_client::DebugClient;
+#[cfg(feature = "ncurses")]
 pub use ncurses_client::NcursesClient;
+pub use options::{Frontend, Options};
 
 pub trait EmulatorClient {
-    fn build(rom_path: Option<&String>) -> Self;
+    fn build(options: &Options) -> Self;
     fn update(&mut self);
     //TODO: have a reset method for resetting the emulator.
 }
@@ -0,0 +1,321 @@
+//Client drawing with plain ANSI escape sequences instead of ncurses. It only needs a tty in raw
+//mode, so it runs where curses can't, like minimal containers without terminfo and serial
+//consoles. There is no debug view or keypad panel, just the emulator screen.
+
+use std::collections::VecDeque;
+use std::env;
+use std::fmt::Write;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::emulator::{Emulator, Interface};
+use super::key_hold::{InputEvent, Key};
+use super::kitty_keyboard;
+use super::layout::{self, Layout, Rect};
+use super::loader;
+use super::persistence;
+use super::raw_terminal::{self, RawTerminal};
+use super::renderer::{Cell, RenderMode, ScreenDiff};
+use super::theme::Rgb;
+use super::EmulatorClient;
+use super::Options;
+
+//Alternate screen with the cursor hidden, and back.
+const ENTER_SCREEN: &str = "\x1b[?1049h\x1b[?25l";
+const LEAVE_SCREEN: &str = "\x1b[0m\x1b[2J\x1b[?25h\x1b[?1049l";
+
+//Colours the terminal can show.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorSupport {
+    TrueColor,
+    Colors256,
+    Colors8,
+}
+
+impl ColorSupport {
+    //Terminals that do 24-bit colour say so in $COLORTERM, the others only show it in $TERM.
+    fn detect() -> ColorSupport {
+        let variable = |name: &str| env::var(name).unwrap_or_default();
+        let colorterm = variable("COLORTERM");
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorSupport::TrueColor
+        } else if variable("TERM").contains("256color") {
+            ColorSupport::Colors256
+        } else {
+            ColorSupport::Colors8
+        }
+    }
+
+    //SGR parameters setting the foreground and the background to a colour.
+    fn get_parameters(self, rgb: Rgb) -> (String, String) {
+        match self {
+            ColorSupport::TrueColor => {
+                let color = format!("2;{};{};{}", rgb.red, rgb.green, rgb.blue);
+                (format!("38;{color}"), format!("48;{color}"))
+            }
+            ColorSupport::Colors256 => {
+                let color = rgb.to_256_colors();
+                (format!("38;5;{color}"), format!("48;5;{color}"))
+            }
+            ColorSupport::Colors8 => {
+                let color = rgb.to_8_colors();
+                (format!("3{color}"), format!("4{color}"))
+            }
+        }
+    }
+}
+
+//Whether the locale lets us print more than ASCII. The first of these variables that is set
+//decides, as in the C library.
+fn is_utf8_locale() -> bool {
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .is_some_and(|value| {
+            let value = value.to_lowercase();
+            value.contains("utf-8") || value.contains("utf8")
+        })
+}
+
+//Escape sequence moving the cursor to a line and column counted from 0.
+fn move_to(output: &mut String, line: i32, column: i32) {
+    let _ = write!(output, "\x1b[{};{}H", line + 1, column + 1);
+}
+
+pub struct AnsiClient {
+    emulator: Emulator,
+    interface: AnsiInterface,
+}
+
+//Terminal side of the client: drawing and reading keys.
+struct AnsiInterface {
+    terminal: RawTerminal,
+    //False when the terminal only shows ASCII, where pixels are drawn as coloured spaces
+    unicode: bool,
+    //SGR parameters for the foreground and background of every palette index
+    colors: Vec<(String, String)>,
+    //Render mode picked by the user, which the layout falls back from when it does not fit
+    preferred_mode: RenderMode,
+    //Lines and columns the layout was made for
+    size: (i32, i32),
+    //None when the terminal is too small for the emulator
+    layout: Option<Layout>,
+    screen: ScreenDiff,
+    //Bytes of a key sequence still being received
+    input: Vec<u8>,
+    //Whether the kitty keyboard protocol is on, so releases are reported
+    kitty: bool,
+}
+
+impl AnsiInterface {
+    //Switches the terminal to raw mode and the alternate screen.
+    fn build(options: &Options, emulator: &mut Emulator) -> AnsiInterface {
+        let terminal = RawTerminal::enable().unwrap_or_else(|err| {
+            eprintln!("The ANSI frontend needs a terminal: {err}");
+            process::exit(1);
+        });
+        raw_terminal::write(ENTER_SCREEN);
+        let kitty = enable_kitty_keyboard();
+        emulator.set_reports_release(kitty);
+        raw_terminal::restore_on_panic(move || leave_screen(kitty));
+
+        let color_support = ColorSupport::detect();
+        let unicode = is_utf8_locale();
+
+        let mut interface = AnsiInterface {
+            terminal,
+            unicode,
+            colors: persistence::palette(&options.theme)
+                .into_iter()
+                .map(|rgb| color_support.get_parameters(rgb))
+                .collect(),
+            preferred_mode: if unicode {
+                options.render_mode
+            } else {
+                RenderMode::Block
+            },
+            size: (0, 0),
+            layout: None,
+            screen: ScreenDiff::default(),
+            input: Vec::new(),
+            kitty,
+        };
+        interface.layout();
+        interface
+    }
+
+    //Fits the emulator screen to the terminal and draws its border. Called at start, when
+    //the terminal size changes and when the render mode changes.
+    fn layout(&mut self) {
+        let (lines, columns) = raw_terminal::get_size();
+        self.size = (lines, columns);
+        //Other modes need characters ASCII does not have
+        self.layout = layout::arrange(lines, columns, self.preferred_mode, false)
+            .filter(|layout| self.unicode || layout.render_mode == RenderMode::Block);
+        self.screen.clear();
+
+        let mut output = String::from("\x1b[0m\x1b[2J");
+        match self.layout {
+            Some(layout) => self.draw_border(&mut output, &layout.emulator),
+            None => {
+                let (min_lines, min_columns) = if self.unicode {
+                    layout::minimum_size()
+                } else {
+                    layout::get_minimum_size(RenderMode::Block)
+                };
+                let message = [
+                    String::from("Terminal too small"),
+                    format!("{columns}x{lines}, needs {min_columns}x{min_lines}"),
+                ];
+                for (row, text) in message.iter().enumerate() {
+                    let x = (columns - text.len() as i32).max(0) / 2;
+                    move_to(&mut output, (lines / 2 - 1 + row as i32).max(0), x);
+                    output.extend(text.chars().take(columns as usize));
+                }
+            }
+        }
+        raw_terminal::write(&output);
+    }
+
+    fn draw_border(&self, output: &mut String, rect: &Rect) {
+        let (corners, horizontal, vertical) = if self.unicode {
+            (['┌', '┐', '└', '┘'], '─', '│')
+        } else {
+            (['+'; 4], '-', '|')
+        };
+        let line: String = std::iter::repeat_n(horizontal, rect.width as usize - 2).collect();
+
+        move_to(output, rect.y, rect.x);
+        let _ = write!(output, "{}{line}{}", corners[0], corners[1]);
+        for row in 1..rect.height - 1 {
+            move_to(output, rect.y + row, rect.x);
+            output.push(vertical);
+            move_to(output, rect.y + row, rect.x + rect.width - 1);
+            output.push(vertical);
+        }
+        move_to(output, rect.y + rect.height - 1, rect.x);
+        let _ = write!(output, "{}{line}{}", corners[2], corners[3]);
+    }
+}
+
+impl Interface for AnsiInterface {
+    //Nothing tells us when the terminal is resized, so the size is checked every time.
+    fn read_input(&mut self, _emulator: &Emulator) -> Vec<InputEvent> {
+        raw_terminal::read_input(&mut self.input);
+        let mut events = kitty_keyboard::decode(&mut self.input);
+        if raw_terminal::get_size() != self.size {
+            events.push(InputEvent::Press(Key::Resize));
+        }
+        events
+    }
+
+    //There is no debugger or keypad panel, only the render mode and the layout to change.
+    fn handle_event(&mut self, _emulator: &mut Emulator, event: InputEvent) -> bool {
+        let (InputEvent::Press(input) | InputEvent::Repeat(input)) = event else {
+            return false;
+        };
+        match input {
+            Key::Function(7) if self.unicode => {
+                //Next render mode
+                self.preferred_mode = self.preferred_mode.next();
+                self.layout();
+            }
+            Key::Resize => self.layout(),
+            _ => return false,
+        }
+        true
+    }
+
+    //Draws the cells that changed since the last frame, in a single write.
+    fn render(&mut self, emulator: &mut Emulator) {
+        let Some(layout) = self.layout else {
+            return;
+        };
+        let (pixels, dirty_rows) = emulator.take_frame();
+        let spans = self
+            .screen
+            .update(&pixels, &dirty_rows, layout.render_mode, layout.scale);
+
+        let mut output = String::new();
+        //Colours last set, which stay on after moving the cursor
+        let mut colors = None;
+        for span in spans {
+            //Plus one for the border
+            move_to(
+                &mut output,
+                layout.emulator.y + 1 + span.row as i32,
+                layout.emulator.x + 1 + span.column as i32,
+            );
+            for mut cell in span.cells {
+                if !self.unicode && cell.character != ' ' {
+                    //Lit blocks become spaces in the pixel colour
+                    cell = Cell {
+                        character: ' ',
+                        foreground: 0,
+                        background: cell.foreground,
+                    };
+                }
+                if colors != Some((cell.foreground, cell.background)) {
+                    colors = Some((cell.foreground, cell.background));
+                    let (foreground, _) = &self.colors[cell.foreground as usize];
+                    let (_, background) = &self.colors[cell.background as usize];
+                    let _ = write!(output, "\x1b[{foreground};{background}m");
+                }
+                output.push(cell.character);
+            }
+        }
+        if !output.is_empty() {
+            output.push_str("\x1b[0m");
+            raw_terminal::write(&output);
+        }
+    }
+
+    fn shutdown(&mut self) {
+        leave_screen(self.kitty);
+        self.terminal.restore();
+    }
+}
+
+//Undoes what the client turned on, before the terminal leaves raw mode.
+fn leave_screen(kitty: bool) {
+    if kitty {
+        kitty_keyboard::disable();
+    }
+    raw_terminal::write(LEAVE_SCREEN);
+}
+
+//Asks the terminal for the kitty keyboard protocol, reading the answer from raw stdin.
+fn enable_kitty_keyboard() -> bool {
+    let mut answer = VecDeque::new();
+    let deadline = Instant::now() + kitty_keyboard::QUERY_TIMEOUT;
+    kitty_keyboard::negotiate(|| loop {
+        if let Some(byte) = answer.pop_front() {
+            return Some(byte);
+        }
+        if Instant::now() >= deadline {
+            return None;
+        }
+        let mut bytes = Vec::new();
+        raw_terminal::read_input(&mut bytes);
+        answer.extend(bytes);
+        thread::sleep(Duration::from_millis(1));
+    })
+}
+
+impl EmulatorClient for AnsiClient {
+    fn build(options: &Options) -> Self {
+        let (rom_data, _) = loader::load_program(options);
+        let mut emulator = Emulator::build(options, rom_data);
+        let interface = AnsiInterface::build(options, &mut emulator);
+        AnsiClient {
+            emulator,
+            interface,
+        }
+    }
+
+    fn update(&mut self) {
+        self.emulator.run(&mut self.interface)
+    }
+}
@@ -0,0 +1,69 @@
+use crate::cpu::decoder::{self, Instruction};
+use crate::cpu::Cpu;
+
+pub struct Frame {
+    pub return_address: u16,
+    //Address of the CALL that created this frame.
+    pub call_site: u16,
+    //Entry point of the called subroutine, if the call site still holds a CALL.
+    pub callee: Option<u16>,
+}
+
+//Live frames of the call stack, innermost first. Entries above the stack pointer are stale
+//and are not reported.
+pub fn frames(cpu: &Cpu) -> Vec<Frame> {
+    let live = (cpu.get_stack_pointer() as usize).min(cpu.get_stack().len());
+
+    cpu.get_stack()[..live]
+        .iter()
+        .rev()
+        .map(|return_address| {
+            let call_site = return_address.wrapping_sub(2) & 0xFFF;
+            let callee = match decoder::decode(cpu.get_instruction_at(call_site)) {
+                Instruction::CallSub(address) => Some(address),
+                _ => None,
+            };
+
+            Frame {
+                return_address: *return_address,
+                call_site,
+                callee,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(cpu: &Cpu) -> Vec<(u16, u16, Option<u16>)> {
+        frames(cpu)
+            .iter()
+            .map(|frame| (frame.return_address, frame.call_site, frame.callee))
+            .collect()
+    }
+
+    #[test]
+    fn lists_nested_calls_innermost_first() {
+        //0x200 calls 0x204, which calls 0x208, which returns
+        let mut cpu = Cpu::new(vec![
+            0x22, 0x04, 0x12, 0x02, 0x22, 0x08, 0x00, 0xEE, 0x00, 0xEE,
+        ]);
+        cpu.clock();
+        cpu.clock();
+        assert_eq!(
+            summary(&cpu),
+            [(0x206, 0x204, Some(0x208)), (0x202, 0x200, Some(0x204))]
+        );
+
+        //The entry left above the stack pointer by the return is stale
+        cpu.clock();
+        assert_eq!(cpu.get_stack()[1], 0x206);
+        assert_eq!(summary(&cpu), [(0x202, 0x200, Some(0x204))]);
+
+        //A return address that does not follow a CALL has no callee
+        cpu.set_stack_entry(0, 0x20A).unwrap();
+        assert_eq!(summary(&cpu), [(0x20A, 0x208, None)]);
+    }
+}
@@ -0,0 +1,231 @@
+use crate::cpu::Cpu;
+use crate::symbols::SymbolMap;
+
+//Commands typed in the debugger console. Syntax:
+//  set v0-vf|i|dt|st|pc|sp <value>
+//  set stack <index> <address>
+//  poke <address> <byte> [byte...]
+//  pixel <x> <y> <0|1>
+//  break <address|label|file:line>
+//Numbers are decimal unless prefixed with 0x.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    SetRegister(u8, u8),
+    SetIRegister(u16),
+    SetTRegister(u8),
+    SetSRegister(u8),
+    SetProgramCounter(u16),
+    SetStackPointer(u8),
+    SetStackEntry(u8, u16),
+    Poke(u16, Vec<u8>),
+    Pixel(u8, u8, bool),
+    //Breakpoints live in the client, so the target is resolved there.
+    Break(String),
+}
+
+pub fn parse_number(text: &str) -> Result<u16, String> {
+    let result = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => text.parse::<u16>(),
+    };
+    result.map_err(|_| format!("invalid number '{text}'"))
+}
+
+//Turns a number, a label or a file:line location into an address.
+pub fn resolve_address(text: &str, symbols: &SymbolMap) -> Result<u16, String> {
+    if let Ok(address) = parse_number(text) {
+        if address > 0xFFF {
+            return Err(String::from("address must be between 0x000 and 0xFFF"));
+        }
+        return Ok(address);
+    }
+    if let Some(address) = symbols.address_of(text) {
+        return Ok(address);
+    }
+    if let Some((file, line)) = text.rsplit_once(':') {
+        if let Ok(line) = line.parse::<usize>() {
+            return symbols
+                .address_of_line(file, line)
+                .ok_or_else(|| format!("no code at or after {text}"));
+        }
+    }
+    Err(format!("unknown label '{text}'"))
+}
+
+fn parse_byte(text: &str) -> Result<u8, String> {
+    let value = parse_number(text)?;
+    u8::try_from(value).map_err(|_| format!("'{text}' does not fit in a byte"))
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let index = text.strip_prefix(['v', 'V'])?;
+    match u8::from_str_radix(index, 16) {
+        Ok(register) if index.len() == 1 => Some(register),
+        _ => None,
+    }
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        match words.as_slice() {
+            ["set", "stack", index, address] => Ok(Command::SetStackEntry(
+                parse_byte(index)?,
+                parse_number(address)?,
+            )),
+            ["set", target, value] => match target.to_lowercase().as_str() {
+                "i" => Ok(Command::SetIRegister(parse_number(value)?)),
+                "dt" => Ok(Command::SetTRegister(parse_byte(value)?)),
+                "st" => Ok(Command::SetSRegister(parse_byte(value)?)),
+                "pc" => Ok(Command::SetProgramCounter(parse_number(value)?)),
+                "sp" => Ok(Command::SetStackPointer(parse_byte(value)?)),
+                other => match parse_register(other) {
+                    Some(register) => Ok(Command::SetRegister(register, parse_byte(value)?)),
+                    None => Err(format!("unknown register '{other}'")),
+                },
+            },
+            ["poke", address, bytes @ ..] if !bytes.is_empty() => Ok(Command::Poke(
+                parse_number(address)?,
+                bytes
+                    .iter()
+                    .map(|byte| parse_byte(byte))
+                    .collect::<Result<Vec<u8>, String>>()?,
+            )),
+            ["pixel", x, y, lit] => Ok(Command::Pixel(
+                parse_byte(x)?,
+                parse_byte(y)?,
+                parse_byte(lit)? != 0,
+            )),
+            ["break", target] => Ok(Command::Break(target.to_string())),
+            [] => Err(String::from("empty command")),
+            _ => Err(format!("unknown command '{line}'")),
+        }
+    }
+
+    pub fn execute(&self, cpu: &mut Cpu) -> Result<(), String> {
+        let result = match self {
+            Command::SetRegister(register, value) => cpu.set_gp_register(*register, *value),
+            Command::SetIRegister(value) => cpu.set_i_register(*value),
+            Command::SetTRegister(value) => {
+                cpu.set_t_register(*value);
+                Ok(())
+            }
+            Command::SetSRegister(value) => {
+                cpu.set_s_register(*value);
+                Ok(())
+            }
+            Command::SetProgramCounter(address) => cpu.set_program_counter(*address),
+            Command::SetStackPointer(value) => cpu.set_stack_pointer(*value),
+            Command::SetStackEntry(index, address) => cpu.set_stack_entry(*index, *address),
+            Command::Poke(address, bytes) => bytes
+                .iter()
+                .enumerate()
+                .try_for_each(|(i, byte)| cpu.write_memory(address.wrapping_add(i as u16), *byte)),
+            Command::Pixel(x, y, lit) => cpu.set_pixel(*x, *y, *lit),
+            Command::Break(_) => Err("breakpoints are set by the client"),
+        };
+
+        result.map_err(String::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_addresses_labels_and_lines() {
+        let mut symbols = SymbolMap::default();
+        symbols.add_label(0x20A, "draw-player");
+        symbols.add_line(0x200, "src/game.asm", 3);
+        symbols.add_line(0x20A, "src/game.asm", 12);
+
+        assert_eq!(resolve_address("0x2A4", &symbols), Ok(0x2A4));
+        assert_eq!(resolve_address("0X2a4", &symbols), Ok(0x2A4));
+        assert_eq!(resolve_address("512", &symbols), Ok(0x200));
+        assert_eq!(resolve_address("draw-player", &symbols), Ok(0x20A));
+        assert_eq!(resolve_address("game.asm:4", &symbols), Ok(0x20A));
+        assert_eq!(resolve_address("src/game.asm:3", &symbols), Ok(0x200));
+
+        assert_eq!(
+            resolve_address("0x1000", &symbols),
+            Err(String::from("address must be between 0x000 and 0xFFF"))
+        );
+        assert_eq!(
+            resolve_address("game.asm:13", &symbols),
+            Err(String::from("no code at or after game.asm:13"))
+        );
+        assert_eq!(
+            resolve_address("draw-enemy", &symbols),
+            Err(String::from("unknown label 'draw-enemy'"))
+        );
+    }
+
+    #[test]
+    fn parses_commands() {
+        let commands = [
+            ("set v3 0x2A", Command::SetRegister(3, 0x2A)),
+            ("set VF 255", Command::SetRegister(0xF, 255)),
+            ("set i 0x300", Command::SetIRegister(0x300)),
+            ("set DT 60", Command::SetTRegister(60)),
+            ("set st 2", Command::SetSRegister(2)),
+            ("set pc 0x20A", Command::SetProgramCounter(0x20A)),
+            ("set sp 1", Command::SetStackPointer(1)),
+            ("set stack 0 0x204", Command::SetStackEntry(0, 0x204)),
+            (
+                "  poke 0x300 1 0x02 3 ",
+                Command::Poke(0x300, vec![1, 2, 3]),
+            ),
+            ("pixel 63 31 1", Command::Pixel(63, 31, true)),
+            ("pixel 0 0 0", Command::Pixel(0, 0, false)),
+            ("break main", Command::Break(String::from("main"))),
+        ];
+        for (line, command) in commands {
+            assert_eq!(Command::parse(line), Ok(command), "{line}");
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_commands() {
+        let errors = [
+            ("", "empty command"),
+            ("jump 0x200", "unknown command 'jump 0x200'"),
+            ("poke 0x300", "unknown command 'poke 0x300'"),
+            ("set vg 1", "unknown register 'vg'"),
+            ("set v10 1", "unknown register 'v10'"),
+            ("set v0 256", "'256' does not fit in a byte"),
+            ("set i zz", "invalid number 'zz'"),
+            ("set pc 0x10000", "invalid number '0x10000'"),
+        ];
+        for (line, error) in errors {
+            assert_eq!(Command::parse(line), Err(String::from(error)), "{line}");
+        }
+    }
+
+    #[test]
+    fn executes_on_the_cpu() {
+        let mut cpu = Cpu::new(Vec::new());
+        for line in ["set va 7", "set i 0x123", "set pc 0x300", "poke 0xFFE 1 2"] {
+            Command::parse(line).unwrap().execute(&mut cpu).unwrap();
+        }
+        assert_eq!(cpu.get_gp_registers()[0xA], 7);
+        assert_eq!(cpu.get_i_register(), 0x123);
+        assert_eq!(cpu.get_program_counter(), 0x300);
+        assert_eq!(cpu.get_memory()[0xFFE..], [1, 2]);
+
+        //Values the Cpu refuses come back as errors, after the bytes that fit were written
+        let poke = Command::parse("poke 0xFFF 3 4").unwrap();
+        assert!(poke.execute(&mut cpu).is_err());
+        assert_eq!(cpu.get_memory()[0xFFF], 3);
+        for line in ["set i 0x1000", "set pc 0xFFF", "set sp 17", "pixel 64 0 1"] {
+            assert!(
+                Command::parse(line).unwrap().execute(&mut cpu).is_err(),
+                "{line}"
+            );
+        }
+        assert!(Command::Break(String::from("main"))
+            .execute(&mut cpu)
+            .is_err());
+    }
+}
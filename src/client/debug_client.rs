@@ -1,32 +1,41 @@
 use std::process;
 
+use super::loader;
 use super::EmulatorClient;
-use crate::cpu::utils;
+use super::Options;
 use crate::cpu::Cpu;
+use crate::trace::Tracer;
 
 pub struct DebugClient {
     cpu: Cpu,
+    tracer: Option<Tracer>,
 }
 
 impl EmulatorClient for DebugClient {
-    fn build(mut rom_path: Option<&String>) -> Self {
-        let rom_data = if let Some(path) = rom_path.take() {
-            utils::read_rom(path.as_str()).unwrap_or_else(|err| {
-                eprintln!("Problem reading rom_file: {err}");
+    fn build(options: &Options) -> Self {
+        let (rom_data, _) = loader::load_program(options);
+
+        let tracer = options.trace.as_ref().map(|trace| {
+            Tracer::create(trace).unwrap_or_else(|err| {
+                eprintln!("Problem creating trace file: {err}");
                 process::exit(1);
             })
-        } else {
-            eprintln!("Please specify the path to a valid rom in the command!");
-            process::exit(1);
-        };
+        });
 
         DebugClient {
             cpu: Cpu::new(rom_data),
+            tracer,
         }
     }
     fn update(&mut self) {
         loop {
-            self.cpu.clock();
+            match self.tracer.as_mut() {
+                Some(tracer) => tracer.step(&mut self.cpu).unwrap_or_else(|err| {
+                    eprintln!("Problem writing trace file: {err}");
+                    process::exit(1);
+                }),
+                None => self.cpu.clock(),
+            }
         }
     }
 }
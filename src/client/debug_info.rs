@@ -0,0 +1,41 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::symbols::{SourceLocation, SymbolMap};
+
+//Labels and source lines of the running program, when it was built from source.
+#[derive(Default)]
+pub struct DebugInfo {
+    pub symbols: SymbolMap,
+    //Lines of every source file the line map mentions and that could be read.
+    sources: HashMap<String, Vec<String>>,
+}
+
+impl DebugInfo {
+    //Reads the source files named in the symbol map. Relative paths are tried from the current
+    //directory first, then from base_directory, where the symbol file was found.
+    pub fn new(symbols: SymbolMap, base_directory: &Path) -> DebugInfo {
+        let mut sources = HashMap::new();
+
+        for (_, location) in symbols.lines() {
+            let file = &location.file;
+            if sources.contains_key(file) {
+                continue;
+            }
+            let text = fs::read_to_string(file)
+                .or_else(|_| fs::read_to_string(base_directory.join(file)))
+                .unwrap_or_default();
+            sources.insert(file.clone(), text.lines().map(String::from).collect());
+        }
+
+        DebugInfo { symbols, sources }
+    }
+
+    pub fn source_text(&self, location: &SourceLocation) -> Option<&str> {
+        self.sources
+            .get(&location.file)?
+            .get(location.line.checked_sub(1)?)
+            .map(|text| text.trim())
+    }
+}
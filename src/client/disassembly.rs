@@ -0,0 +1,109 @@
+use std::collections::BTreeSet;
+
+use crate::cpu::decoder::{self, Instruction};
+use crate::cpu::Cpu;
+use crate::symbols::SymbolMap;
+
+pub struct DisassemblyLine {
+    pub address: u16,
+    pub opcode: u16,
+    //Mnemonic, with the target address replaced by its label when there is one.
+    pub text: String,
+    //Label defined at this address.
+    pub label: Option<String>,
+    pub is_current: bool,
+    pub is_breakpoint: bool,
+    pub is_cursor: bool,
+}
+
+#[derive(Default)]
+pub struct DisassemblyView {
+    //None while the view is following the program counter.
+    cursor: Option<u16>,
+    //Addresses we jumped away from, so following a jump can be undone.
+    history: Vec<u16>,
+}
+
+impl DisassemblyView {
+    pub fn get_cursor(&self, cpu: &Cpu) -> u16 {
+        self.cursor.unwrap_or(cpu.get_program_counter())
+    }
+
+    pub fn is_following(&self) -> bool {
+        self.cursor.is_none()
+    }
+
+    //Moves the cursor by the given number of instructions, detaching the view from the PC.
+    pub fn scroll(&mut self, cpu: &Cpu, instructions: i32) {
+        let offset = (instructions * 2) as i16 as u16;
+        self.cursor = Some(self.get_cursor(cpu).wrapping_add(offset) & 0xFFF);
+    }
+
+    //If the instruction under the cursor references an address, move the view there.
+    pub fn follow_jump(&mut self, cpu: &Cpu) -> bool {
+        let cursor = self.get_cursor(cpu);
+
+        match decoder::decode(cpu.get_instruction_at(cursor)).target_address() {
+            Some(target) => {
+                self.history.push(cursor);
+                self.cursor = Some(target);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn back(&mut self) {
+        if let Some(address) = self.history.pop() {
+            self.cursor = Some(address);
+        }
+    }
+
+    pub fn follow_program_counter(&mut self) {
+        self.cursor = None;
+        self.history.clear();
+    }
+
+    //Disassembles count instructions centered around the cursor.
+    pub fn lines(
+        &self,
+        cpu: &Cpu,
+        breakpoints: &BTreeSet<u16>,
+        symbols: &SymbolMap,
+        count: usize,
+    ) -> Vec<DisassemblyLine> {
+        let cursor = self.get_cursor(cpu);
+        let program_counter = cpu.get_program_counter();
+        let start = cursor.saturating_sub((count / 2 * 2) as u16);
+
+        (0..count)
+            .map(|i| start + (i * 2) as u16)
+            .filter(|address| *address < 0x1000)
+            .map(|address| {
+                let opcode = cpu.get_instruction_at(address);
+                DisassemblyLine {
+                    address,
+                    opcode,
+                    text: symbolic(&decoder::decode(opcode), symbols),
+                    label: symbols.label_at(address).map(String::from),
+                    is_current: address == program_counter,
+                    is_breakpoint: breakpoints.contains(&address),
+                    is_cursor: !self.is_following() && address == cursor,
+                }
+            })
+            .collect()
+    }
+}
+
+//Instruction text with its target address replaced by the label there, if there is one.
+fn symbolic(instruction: &Instruction, symbols: &SymbolMap) -> String {
+    let text = instruction.to_string();
+    let target = instruction
+        .target_address()
+        .and_then(|target| Some((target, symbols.label_at(target)?)));
+
+    match target {
+        Some((target, label)) => text.replace(&format!("{:#05X}", target), label),
+        None => text,
+    }
+}
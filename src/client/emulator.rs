@@ -0,0 +1,258 @@
+//The part of a client that does not depend on the terminal: the Cpu, the keypad, tracing,
+//pausing and stepping, and the 60Hz display frames. Frontends read input and draw through the
+//Interface trait, and the run loop does the rest.
+
+use std::collections::BTreeSet;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::key_hold::{InputEvent, Key, KeyHold};
+use super::keymap::{self, Keymap};
+use super::persistence::PersistenceFilter;
+#[cfg(feature = "ncurses")]
+use super::persistence::PersistenceMode;
+use super::Options;
+use crate::cpu::{Cpu, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::trace::Tracer;
+
+//Display frames come at 60Hz like the timers.
+const FRAME_TIME: Duration = Duration::from_micros(16_667);
+
+//Pause between two turns of the run loop.
+const LOOP_DELAY: Duration = Duration::from_millis(2);
+
+//What a frontend does for the run loop.
+pub trait Interface {
+    //Input since the last call.
+    fn read_input(&mut self, emulator: &Emulator) -> Vec<InputEvent>;
+
+    //Takes the input the frontend has its own use for, like debugger keys, layout changes and
+    //mouse clicks. Returns false for the input left to the common controls.
+    fn handle_event(&mut self, emulator: &mut Emulator, event: InputEvent) -> bool;
+
+    fn render(&mut self, emulator: &mut Emulator);
+
+    //Puts the terminal back the way it was, right before the process exits.
+    fn shutdown(&mut self);
+}
+
+pub struct Emulator {
+    cpu: Cpu,
+    paused: bool,
+    single_step: bool,
+    breakpoints: BTreeSet<u16>,
+    tracer: Option<Tracer>,
+    keymap: Keymap,
+    key_hold: KeyHold,
+    //Whether the terminal reports key releases, so keys can stay down until then
+    reports_release: bool,
+    persistence: Option<PersistenceFilter>,
+    //When the current display frame started
+    frame_start: Instant,
+}
+
+impl Emulator {
+    //Loads the keymap and opens the trace file, exiting on errors before the frontend has
+    //touched the terminal.
+    pub fn build(options: &Options, rom_data: Vec<u8>) -> Emulator {
+        let keymap = keymap::load_keymap(options);
+
+        let tracer = options.trace.as_ref().map(|trace| {
+            Tracer::create(trace).unwrap_or_else(|err| {
+                eprintln!("Problem creating trace file: {err}");
+                process::exit(1);
+            })
+        });
+
+        Emulator {
+            cpu: Cpu::new(rom_data),
+            paused: true,
+            single_step: false,
+            breakpoints: BTreeSet::new(),
+            tracer,
+            keymap,
+            key_hold: KeyHold::new(options.key_hold_time),
+            reports_release: false,
+            persistence: options.persistence_frames.map(|frames| {
+                PersistenceFilter::new(
+                    frames,
+                    options.persistence_mode,
+                    SCREEN_WIDTH * SCREEN_HEIGHT,
+                )
+            }),
+            frame_start: Instant::now(),
+        }
+    }
+
+    pub fn set_reports_release(&mut self, reports_release: bool) {
+        self.reports_release = reports_release;
+    }
+
+    //Pixels to show, through the persistence filter, and the rows of the screen that changed
+    //since the last call. Fading pixels change anywhere, so with persistence every row has.
+    pub fn take_frame(&mut self) -> (Vec<u8>, [bool; SCREEN_HEIGHT]) {
+        let screen = self.cpu.get_screen();
+        let frame = match &self.persistence {
+            Some(filter) => (filter.apply(screen), [true; SCREEN_HEIGHT]),
+            None => (screen.to_vec(), *self.cpu.get_dirty_rows()),
+        };
+        self.cpu.clear_dirty_rows();
+        frame
+    }
+
+    //CHIP-8 key bound to a terminal key, if there is one.
+    fn keypad_key(&self, input: Key) -> Option<u8> {
+        match input {
+            Key::Char(input) => self.keymap.get_key(input),
+            _ => None,
+        }
+    }
+
+    fn press_key(&mut self, input: Key) {
+        if let Some(key) = self.keypad_key(input) {
+            self.key_hold
+                .press(&mut self.cpu, key, self.reports_release);
+        }
+    }
+
+    fn release_key(&mut self, input: Key) {
+        if let Some(key) = self.keypad_key(input) {
+            self.key_hold.release(&mut self.cpu, key);
+        }
+    }
+
+    fn exit(&mut self, interface: &mut impl Interface, code: i32) -> ! {
+        interface.shutdown();
+
+        //process::exit skips destructors, so buffered trace output has to be flushed here
+        if let Some(tracer) = self.tracer.as_mut() {
+            if let Err(err) = tracer.flush() {
+                eprintln!("Problem writing trace file: {err}");
+            }
+        }
+        process::exit(code);
+    }
+
+    fn step(&mut self, interface: &mut impl Interface) {
+        match self.tracer.as_mut() {
+            Some(tracer) => {
+                if let Err(err) = tracer.step(&mut self.cpu) {
+                    self.tracer = None;
+                    interface.shutdown();
+                    eprintln!("Problem writing trace file: {err}");
+                    process::exit(1);
+                }
+            }
+            None => self.cpu.clock(),
+        }
+    }
+
+    //Emulator controls sit on the function keys so every other key can be bound to the keypad.
+    fn input_action(&mut self, interface: &mut impl Interface, input: Key) {
+        match input {
+            //Raw terminals send Ctrl-C as a key instead of stopping us
+            Key::Function(10) | Key::Control('c') => self.exit(interface, 0), // Quit
+            Key::Function(2) => self.paused = !self.paused,                   // Pause/Un-pause
+            Key::Function(3) => self.single_step = !self.single_step,
+            _ => self.press_key(input),
+        }
+    }
+
+    pub fn run(&mut self, interface: &mut impl Interface) -> ! {
+        loop {
+            thread::sleep(LOOP_DELAY);
+
+            //Read user input
+            let mut step_requested = false;
+            for event in interface.read_input(self) {
+                if interface.handle_event(self, event) {
+                    continue;
+                }
+                match event {
+                    InputEvent::Press(input) | InputEvent::Repeat(input) => {
+                        step_requested |= input == Key::Function(4);
+                        self.input_action(interface, input);
+                    }
+                    InputEvent::Release(input) => self.release_key(input),
+                    InputEvent::MouseDown { .. } | InputEvent::MouseUp => (),
+                }
+            }
+            self.key_hold.update(&mut self.cpu);
+
+            //Draw once per frame, or after every step when stepping through by hand
+            let new_frame = self.frame_start.elapsed() >= FRAME_TIME;
+            if new_frame {
+                self.frame_start = Instant::now();
+                if let Some(filter) = self.persistence.as_mut() {
+                    filter.update(self.cpu.get_screen());
+                }
+            }
+            if new_frame || self.single_step {
+                interface.render(self);
+            }
+
+            //F4 executes one instruction in single step mode
+            if !self.paused && (!self.single_step || step_requested) {
+                self.step(interface);
+
+                //Stop on breakpoints and faults so the state can be inspected
+                if self.breakpoints.contains(&self.cpu.get_program_counter())
+                    || self.cpu.is_faulted()
+                {
+                    self.paused = true;
+                }
+            }
+        }
+    }
+}
+
+//The debugger, the keypad panel and the colour check of the ncurses frontend.
+#[cfg(feature = "ncurses")]
+impl Emulator {
+    pub fn get_cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn get_cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    pub fn get_keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+
+    pub fn get_breakpoints(&self) -> &BTreeSet<u16> {
+        &self.breakpoints
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn toggle_breakpoint(&mut self, address: u16) {
+        if !self.breakpoints.remove(&address) {
+            self.breakpoints.insert(address);
+        }
+    }
+
+    pub fn is_single_step(&self) -> bool {
+        self.single_step
+    }
+
+    //Fading pixels need colours, without them they can only stay lit.
+    pub fn set_persistence_mode(&mut self, mode: PersistenceMode) {
+        if let Some(filter) = self.persistence.as_mut() {
+            filter.set_mode(mode);
+        }
+    }
+
+    //Presses a CHIP-8 key until release is called, as the mouse does.
+    pub fn press(&mut self, key: u8) {
+        self.key_hold.press(&mut self.cpu, key, true);
+    }
+
+    pub fn release(&mut self, key: u8) {
+        self.key_hold.release(&mut self.cpu, key);
+    }
+}
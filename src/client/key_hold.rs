@@ -0,0 +1,90 @@
+use std::time::{Duration, Instant};
+
+use crate::cpu::Cpu;
+
+//How long a key stays down after its last press or auto-repeat when the terminal does not
+//report releases. Longer times smooth over the pause before auto-repeat starts, shorter ones
+//make taps shorter.
+pub const DEFAULT_HOLD_TIME: Duration = Duration::from_millis(200);
+
+//Terminal key, whichever frontend read it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    //Letter typed with Ctrl held, in lower case
+    Control(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    Enter,
+    Backspace,
+    //Function key by number, F1 being 1
+    Function(u8),
+    //The terminal changed size
+    Resize,
+}
+
+//Input read from the terminal. Mouse positions are screen coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    Press(Key),
+    Repeat(Key),
+    Release(Key),
+    MouseDown { y: i32, x: i32 },
+    MouseUp,
+}
+
+#[derive(Clone, Copy)]
+enum Hold {
+    Released,
+    //Pressed in a terminal without release events, released at the given time
+    Until(Instant),
+    //Pressed in a terminal that will tell us when it is released
+    UntilReleased,
+}
+
+//Tracks which CHIP-8 keys are down and passes presses and releases on to the Cpu. Terminals
+//that report releases get exact key state, the others get each key held for a while after
+//the last time it was seen.
+pub struct KeyHold {
+    hold_time: Duration,
+    keys: [Hold; 16],
+}
+
+impl KeyHold {
+    pub fn new(hold_time: Duration) -> KeyHold {
+        KeyHold {
+            hold_time,
+            keys: [Hold::Released; 16],
+        }
+    }
+
+    pub fn press(&mut self, cpu: &mut Cpu, key: u8, reports_release: bool) {
+        cpu.set_key(key);
+        self.keys[key as usize] = if reports_release {
+            Hold::UntilReleased
+        } else {
+            Hold::Until(Instant::now() + self.hold_time)
+        };
+    }
+
+    pub fn release(&mut self, cpu: &mut Cpu, key: u8) {
+        cpu.release_key(key);
+        self.keys[key as usize] = Hold::Released;
+    }
+
+    //Releases the keys whose hold time ran out.
+    pub fn update(&mut self, cpu: &mut Cpu) {
+        let now = Instant::now();
+        for key in 0..16 {
+            if let Hold::Until(release) = self.keys[key] {
+                if release <= now {
+                    self.release(cpu, key as u8);
+                }
+            }
+        }
+    }
+}
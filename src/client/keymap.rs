@@ -0,0 +1,241 @@
+//Mapping from terminal keys to the 16 keys of the CHIP-8 keypad.
+//
+//The default layout puts the keypad on the left of a QWERTY keyboard:
+//
+//  1 2 3 C      1 2 3 4
+//  4 5 6 D  ->  Q W E R
+//  7 8 9 E      A S D F
+//  A 0 B F      Z X C V
+//
+//Keymap files change it with one '<terminal key> = <chip-8 key>' binding per line, the CHIP-8
+//key in hexadecimal. Bindings under a '[<rom file name>]' header only apply to that ROM and
+//override the ones at the top of the file. Letters are not case sensitive, 'space' stands for
+//the space bar and '#' starts a comment.
+//
+//  w = 5
+//  [pong.ch8]
+//  k = 1
+//  j = 4
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use super::Options;
+
+const DEFAULT_LAYOUT: [(char, u8); 16] = [
+    ('1', 0x1),
+    ('2', 0x2),
+    ('3', 0x3),
+    ('4', 0xC),
+    ('q', 0x4),
+    ('w', 0x5),
+    ('e', 0x6),
+    ('r', 0xD),
+    ('a', 0x7),
+    ('s', 0x8),
+    ('d', 0x9),
+    ('f', 0xE),
+    ('z', 0xA),
+    ('x', 0x0),
+    ('c', 0xB),
+    ('v', 0xF),
+];
+
+pub struct Keymap {
+    bindings: BTreeMap<char, u8>,
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap {
+            bindings: DEFAULT_LAYOUT.into_iter().collect(),
+        }
+    }
+}
+
+impl Keymap {
+    pub fn bind(&mut self, input: char, key: u8) {
+        self.bindings.insert(input.to_ascii_lowercase(), key & 0xF);
+    }
+
+    //CHIP-8 key bound to a terminal key, if any.
+    pub fn get_key(&self, input: char) -> Option<u8> {
+        self.bindings.get(&input.to_ascii_lowercase()).copied()
+    }
+
+    //First terminal key bound to a CHIP-8 key, if any.
+    #[cfg(feature = "ncurses")]
+    pub fn get_input(&self, key: u8) -> Option<char> {
+        self.bindings
+            .iter()
+            .find(|(_, bound)| **bound == key)
+            .map(|(input, _)| *input)
+    }
+
+    //Applies the bindings of a keymap file on top of the current ones: first the ones outside
+    //any section, then the ones in the section named after rom_name.
+    pub fn apply(&mut self, text: &str, rom_name: &str) -> Result<(), String> {
+        let mut global = Vec::new();
+        let mut for_rom = Vec::new();
+        let mut section: Option<&str> = None;
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+            {
+                section = Some(name.trim());
+                continue;
+            }
+
+            let binding =
+                parse_binding(line).map_err(|err| format!("line {}: {err}", number + 1))?;
+            match section {
+                None => global.push(binding),
+                Some(name) if name == rom_name => for_rom.push(binding),
+                Some(_) => (),
+            }
+        }
+
+        for (input, key) in global.into_iter().chain(for_rom) {
+            self.bind(input, key);
+        }
+        Ok(())
+    }
+}
+
+fn parse_binding(line: &str) -> Result<(char, u8), String> {
+    let (input, key) = line
+        .split_once('=')
+        .ok_or_else(|| String::from("expected '<terminal key> = <chip-8 key>'"))?;
+
+    let input = match input.trim() {
+        "space" => ' ',
+        input => {
+            let mut characters = input.chars();
+            match (characters.next(), characters.next()) {
+                (Some(character), None) if !character.is_control() => character,
+                _ => return Err(format!("'{input}' is not a single key")),
+            }
+        }
+    };
+    let key = u8::from_str_radix(key.trim(), 16)
+        .ok()
+        .filter(|key| *key <= 0xF)
+        .ok_or_else(|| format!("'{}' is not a CHIP-8 key (0-F)", key.trim()))?;
+
+    Ok((input, key))
+}
+
+//~/.config/chip_8_emulator/keymap.conf, or the same under $XDG_CONFIG_HOME.
+fn default_keymap_path() -> Option<PathBuf> {
+    let config = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config.join("chip_8_emulator").join("keymap.conf"))
+}
+
+//Keymap for the ROM being run: the default layout changed by the file given with --keymap,
+//or by the default keymap file when there is one. Exits if the file cannot be used.
+pub fn load_keymap(options: &Options) -> Keymap {
+    let mut keymap = Keymap::default();
+
+    let path = match &options.keymap_path {
+        Some(path) => PathBuf::from(path),
+        None => match default_keymap_path().filter(|path| path.exists()) {
+            Some(path) => path,
+            None => return keymap,
+        },
+    };
+
+    let rom_name = options
+        .rom_path
+        .as_deref()
+        .and_then(|rom_path| Path::new(rom_path).file_name())
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let result = fs::read_to_string(&path)
+        .map_err(|err| err.to_string())
+        .and_then(|text| keymap.apply(&text, &rom_name));
+    if let Err(err) = result {
+        eprintln!("Problem reading keymap {}: {err}", path.display());
+        process::exit(1);
+    }
+    keymap
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEYMAP: &str = "\
+# Moves the paddle keys for everything
+W = 6
+space = f
+
+[pong.ch8]
+w = 1   # right paddle up
+k = 4
+[tetris.ch8]
+j = 7
+";
+
+    #[test]
+    fn rom_section_overrides_the_top_of_the_file() {
+        let mut keymap = Keymap::default();
+        keymap.apply(KEYMAP, "pong.ch8").unwrap();
+
+        assert_eq!(keymap.get_key('w'), Some(0x1));
+        assert_eq!(keymap.get_key('k'), Some(0x4));
+        assert_eq!(keymap.get_key(' '), Some(0xF));
+        //Other ROMs' sections are skipped, the default layout stays
+        assert_eq!(keymap.get_key('j'), None);
+        assert_eq!(keymap.get_key('x'), Some(0x0));
+    }
+
+    #[test]
+    fn top_of_the_file_applies_to_other_roms() {
+        let mut keymap = Keymap::default();
+        keymap.apply(KEYMAP, "brix.ch8").unwrap();
+
+        assert_eq!(keymap.get_key('W'), Some(0x6));
+        assert_eq!(keymap.get_key('k'), None);
+        assert_eq!(keymap.get_key('j'), None);
+    }
+
+    #[test]
+    fn parses_bindings() {
+        assert_eq!(parse_binding("space = f"), Ok((' ', 0xF)));
+        assert_eq!(parse_binding(" ; =A "), Ok((';', 0xA)));
+        assert_eq!(
+            parse_binding("ab = 1"),
+            Err(String::from("'ab' is not a single key"))
+        );
+        assert_eq!(
+            parse_binding("w = 10"),
+            Err(String::from("'10' is not a CHIP-8 key (0-F)"))
+        );
+        assert_eq!(
+            parse_binding("w 5"),
+            Err(String::from("expected '<terminal key> = <chip-8 key>'"))
+        );
+    }
+
+    #[test]
+    fn reports_errors_in_any_section() {
+        let mut keymap = Keymap::default();
+        let err = keymap.apply("w = 5\n[other.ch8]\nw = g\n", "pong.ch8");
+        assert_eq!(
+            err,
+            Err(String::from("line 3: 'g' is not a CHIP-8 key (0-F)"))
+        );
+    }
+}
@@ -0,0 +1,80 @@
+//On-screen CHIP-8 keypad. Shows which keys the program sees as held and which terminal key each
+//one is bound to, and lets keys be pressed with the mouse.
+
+use ncurses::*;
+
+use super::keymap::Keymap;
+use super::layout::{KEYPAD_HEIGHT, KEYPAD_WIDTH};
+
+//Keys in the order they sit on the COSMAC VIP keypad.
+const LAYOUT: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+//The panel size in layout is made of these
+const CELL_WIDTH: i32 = 6;
+//One line for the CHIP-8 key, one for the terminal key bound to it
+const CELL_HEIGHT: i32 = 2;
+
+pub struct KeypadPanel {
+    window: WINDOW,
+}
+
+impl KeypadPanel {
+    pub fn new(y: i32, x: i32) -> KeypadPanel {
+        let panel = KeypadPanel {
+            window: newwin(KEYPAD_HEIGHT, KEYPAD_WIDTH, y, x),
+        };
+        panel.move_to(y, x);
+        panel
+    }
+
+    //Moves the panel and draws its frame again.
+    pub fn move_to(&self, y: i32, x: i32) {
+        mvwin(self.window, y, x);
+        werase(self.window);
+        box_(self.window, 0, 0);
+        mvwaddstr(self.window, 0, 2, " keypad ");
+    }
+
+    pub fn render(&self, keyboard: &[bool; 16], keymap: &Keymap) {
+        for (row, keys) in LAYOUT.iter().enumerate() {
+            for (column, key) in keys.iter().enumerate() {
+                let y = 1 + row as i32 * CELL_HEIGHT;
+                let x = 1 + column as i32 * CELL_WIDTH;
+                let input = match keymap.get_input(*key) {
+                    Some(' ') => String::from("spc"),
+                    Some(input) => input.to_string(),
+                    None => String::new(),
+                };
+
+                let attributes = if keyboard[*key as usize] {
+                    A_REVERSE()
+                } else {
+                    A_NORMAL()
+                };
+                wattron(self.window, attributes);
+                mvwaddstr(self.window, y, x, &format!("  {:X}   ", key));
+                wattroff(self.window, attributes);
+                mvwaddstr(self.window, y + 1, x, &format!("  {:<4}", input));
+            }
+        }
+        wrefresh(self.window);
+    }
+
+    //CHIP-8 key under a screen position, if any.
+    pub fn key_at(&self, y: i32, x: i32) -> Option<u8> {
+        let (mut y, mut x) = ([y], [x]);
+        if !wmouse_trafo(self.window, &mut y, &mut x, false) {
+            return None;
+        }
+        let (row, column) = ((y[0] - 1) / CELL_HEIGHT, (x[0] - 1) / CELL_WIDTH);
+        if y[0] < 1 || x[0] < 1 || row > 3 || column > 3 {
+            return None;
+        }
+        Some(LAYOUT[row as usize][column as usize])
+    }
+}
@@ -0,0 +1,334 @@
+//Support for the kitty keyboard protocol, which unlike plain terminal input reports key
+//releases. See https://sw.kovidgoyal.net/kitty/keyboard-protocol/
+//
+//We ask for flags 11: disambiguated escape codes (1), event types (2) and every key as an
+//escape code (8), so letters arrive as 'CSI 119;1:3u' (w released) instead of plain text.
+
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "ncurses")]
+use ncurses::{getch, timeout, ERR};
+
+use super::key_hold::{InputEvent, Key};
+
+const QUERY: &str = "\x1b[?u";
+//Primary device attributes, answered by every terminal so we know when to stop waiting
+const DEVICE_ATTRIBUTES: &str = "\x1b[c";
+const PUSH_FLAGS: &str = "\x1b[>11u";
+const POP_FLAGS: &str = "\x1b[<u";
+//With keypad off ncurses no longer decodes mouse reports, so we ask for the SGR format and do
+//it ourselves
+const SGR_MOUSE_ON: &str = "\x1b[?1006h";
+const SGR_MOUSE_OFF: &str = "\x1b[?1006l";
+
+//How long we wait for the terminal to answer the query.
+pub const QUERY_TIMEOUT: Duration = Duration::from_millis(200);
+
+fn send(sequence: &str) {
+    let mut stdout = io::stdout();
+    //Nothing useful can be done if the terminal is gone
+    let _ = stdout
+        .write_all(sequence.as_bytes())
+        .and_then(|_| stdout.flush());
+}
+
+//Asks the terminal whether it speaks the protocol and turns it on if it does. Has to be
+//called after initscr, with keypad off so the answers are not mistaken for keys.
+#[cfg(feature = "ncurses")]
+pub fn enable() -> bool {
+    timeout(QUERY_TIMEOUT.as_millis() as i32);
+    negotiate(|| match getch() {
+        ERR => None,
+        byte => Some(byte as u8),
+    })
+}
+
+//Same as enable for clients reading the terminal themselves. read_byte gives the next byte of
+//input, or None when nothing came within QUERY_TIMEOUT.
+pub fn negotiate(mut read_byte: impl FnMut() -> Option<u8>) -> bool {
+    send(QUERY);
+    send(DEVICE_ATTRIBUTES);
+
+    let mut answer = Vec::new();
+    let deadline = Instant::now() + QUERY_TIMEOUT;
+    //The device attributes answer looks like CSI ? ... c and comes last
+    while Instant::now() < deadline && !(answer.starts_with(b"\x1b[?") && answer.ends_with(b"c")) {
+        match read_byte() {
+            None => break,
+            Some(byte) => answer.push(byte),
+        }
+        //The protocol answer, CSI ? <flags> u, comes first when it is supported
+        if answer.starts_with(b"\x1b[?") && answer.ends_with(b"u") {
+            resume();
+            return true;
+        }
+        //Drop anything that is not part of an answer
+        if !b"\x1b[?".starts_with(&answer[..answer.len().min(3)]) {
+            answer.clear();
+        }
+    }
+    false
+}
+
+pub fn disable() {
+    send(POP_FLAGS);
+    send(SGR_MOUSE_OFF);
+}
+
+//Turns the protocol back on after disable.
+pub fn resume() {
+    send(PUSH_FLAGS);
+    send(SGR_MOUSE_ON);
+}
+
+//Turns a protocol key code into a key.
+fn key_code(number: u32, terminator: u8) -> Option<Key> {
+    let key = match (terminator, number) {
+        (b'u', 13) => Key::Enter,
+        (b'u', 127) => Key::Backspace,
+        (b'u', number) => return char::from_u32(number).map(Key::Char),
+        (b'A', _) => Key::Up,
+        (b'B', _) => Key::Down,
+        (b'C', _) => Key::Right,
+        (b'D', _) => Key::Left,
+        (b'P', _) => Key::Function(1),
+        (b'Q', _) => Key::Function(2),
+        (b'R', _) => Key::Function(3),
+        (b'S', _) => Key::Function(4),
+        (b'~', 5) => Key::PageUp,
+        (b'~', 6) => Key::PageDown,
+        (b'~', 13) => Key::Function(3),
+        (b'~', 15) => Key::Function(5),
+        (b'~', 17) => Key::Function(6),
+        (b'~', 18) => Key::Function(7),
+        (b'~', 19) => Key::Function(8),
+        (b'~', 20) => Key::Function(9),
+        (b'~', 21) => Key::Function(10),
+        (b'~', 23) => Key::Function(11),
+        (b'~', 24) => Key::Function(12),
+        _ => return None,
+    };
+    Some(key)
+}
+
+//Key for a byte of plain terminal input. Ctrl and a letter come as the control characters from
+//1 to 26, except for those that are also Tab, Enter and Backspace.
+fn key_byte(byte: u8) -> Key {
+    match byte {
+        b'\r' | b'\n' => Key::Enter,
+        0x7F | 0x08 => Key::Backspace,
+        0x01..=0x1A if byte != b'\t' => Key::Control((b'a' + byte - 1) as char),
+        byte => Key::Char(byte as char),
+    }
+}
+
+//Parses 'CSI <number>[:<alternates>] [; <modifiers>[:<event>] ...] <terminator>' after the CSI.
+fn parse_sequence(parameters: &[u8], terminator: u8) -> Option<InputEvent> {
+    let parameters = std::str::from_utf8(parameters).ok()?;
+    let mut fields = parameters.split(';');
+
+    let number = match fields.next()?.split(':').next()? {
+        "" => 1,
+        number => number.parse().ok()?,
+    };
+    let mut modifiers = fields.next().unwrap_or("1").split(':');
+    //Modifier bits plus one, Ctrl being 4
+    let control = modifiers
+        .next()
+        .and_then(|bits| bits.parse::<u32>().ok())
+        .is_some_and(|bits| bits.saturating_sub(1) & 4 != 0);
+    let event = modifiers.next().unwrap_or("1");
+
+    let key = match key_code(number, terminator)? {
+        Key::Char(letter) if control && letter.is_ascii_alphabetic() => {
+            Key::Control(letter.to_ascii_lowercase())
+        }
+        key => key,
+    };
+    match event {
+        "2" => Some(InputEvent::Repeat(key)),
+        "3" => Some(InputEvent::Release(key)),
+        _ => Some(InputEvent::Press(key)),
+    }
+}
+
+//Parses an SGR mouse report, 'CSI < <button>;<x>;<y>' ended by M on press and m on release.
+//Only the left button matters.
+fn parse_mouse(parameters: &[u8], terminator: u8) -> Option<InputEvent> {
+    let parameters = std::str::from_utf8(parameters).ok()?;
+    let mut fields = parameters.split(';').map(|field| field.parse::<i32>().ok());
+    let (button, x, y) = (fields.next()??, fields.next()??, fields.next()??);
+
+    //Other buttons, wheel and motion reports; the bits in between are modifier keys
+    if button & !0b11100 != 0 {
+        return None;
+    }
+    match terminator {
+        b'M' => Some(InputEvent::MouseDown { y: y - 1, x: x - 1 }),
+        _ => Some(InputEvent::MouseUp),
+    }
+}
+
+//Decodes the complete key sequences at the start of buffer and removes them, leaving a
+//sequence that is still being received for next time.
+pub fn decode(buffer: &mut Vec<u8>) -> Vec<InputEvent> {
+    let mut events = Vec::new();
+    let mut start = 0;
+
+    while start < buffer.len() {
+        let rest = &buffer[start..];
+        match rest {
+            //Mouse report in the old format, three bytes after CSI M
+            [0x1b, b'[', b'M', ..] if rest.len() < 6 => break,
+            [0x1b, b'[', b'M', button, x, y, ..] => {
+                //Left button press or release of any button, ignoring modifier keys
+                match button.wrapping_sub(32) & !0b11100 {
+                    0 => events.push(InputEvent::MouseDown {
+                        y: *y as i32 - 33,
+                        x: *x as i32 - 33,
+                    }),
+                    3 => events.push(InputEvent::MouseUp),
+                    _ => (),
+                }
+                start += 6;
+            }
+            //F1 to F5 on the Linux console, which would otherwise end a CSI at the second [
+            [0x1b, b'[', b'['] => break,
+            [0x1b, b'[', b'[', letter, ..] => {
+                if (b'A'..=b'E').contains(letter) {
+                    events.push(InputEvent::Press(Key::Function(letter - b'A' + 1)));
+                }
+                start += 4;
+            }
+            [0x1b, b'[', ..] => {
+                //Parameters run up to the first byte in the final byte range
+                match rest[2..]
+                    .iter()
+                    .position(|byte| (0x40..=0x7E).contains(byte))
+                {
+                    Some(end) => {
+                        let (parameters, terminator) = (&rest[2..2 + end], rest[2 + end]);
+                        events.extend(match parameters.strip_prefix(b"<") {
+                            Some(parameters) => parse_mouse(parameters, terminator),
+                            None => parse_sequence(parameters, terminator),
+                        });
+                        start += 3 + end;
+                    }
+                    None => break,
+                }
+            }
+            //F1 to F4 as some terminals still send them
+            [0x1b, b'O', terminator, ..] => {
+                events.extend(key_code(1, *terminator).map(InputEvent::Press));
+                start += 3;
+            }
+            [0x1b] | [0x1b, b'O'] => break,
+            [byte, ..] => {
+                if *byte != 0x1b {
+                    events.push(InputEvent::Press(key_byte(*byte)));
+                }
+                start += 1;
+            }
+            [] => break,
+        }
+    }
+
+    buffer.drain(..start);
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Decodes input arriving in the given pieces, returning the events and what is left over.
+    fn decode_pieces(pieces: &[&[u8]]) -> (Vec<InputEvent>, Vec<u8>) {
+        let mut buffer = Vec::new();
+        let mut events = Vec::new();
+        for piece in pieces {
+            buffer.extend_from_slice(piece);
+            events.extend(decode(&mut buffer));
+        }
+        (events, buffer)
+    }
+
+    #[test]
+    fn decodes_protocol_keys() {
+        let (events, rest) =
+            decode_pieces(&[b"\x1b[119u\x1b[119;1:2u\x1b[119;1:3u\x1b[21~\x1b[1;5A"]);
+        assert_eq!(
+            events,
+            [
+                InputEvent::Press(Key::Char('w')),
+                InputEvent::Repeat(Key::Char('w')),
+                InputEvent::Release(Key::Char('w')),
+                InputEvent::Press(Key::Function(10)),
+                InputEvent::Press(Key::Up),
+            ]
+        );
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn decodes_control_letters() {
+        let (events, _) = decode_pieces(&[b"\x1b[99;5u\x1b[99;5:3u\x1b[65;2u\x03\t\x08\x7f"]);
+        assert_eq!(
+            events,
+            [
+                InputEvent::Press(Key::Control('c')),
+                InputEvent::Release(Key::Control('c')),
+                InputEvent::Press(Key::Char('A')),
+                InputEvent::Press(Key::Control('c')),
+                InputEvent::Press(Key::Char('\t')),
+                InputEvent::Press(Key::Backspace),
+                InputEvent::Press(Key::Backspace),
+            ]
+        );
+    }
+
+    #[test]
+    fn waits_for_split_sequences() {
+        let (events, rest) = decode_pieces(&[b"a\x1b", b"[119;1", b":3"]);
+        assert_eq!(events, [InputEvent::Press(Key::Char('a'))]);
+        assert_eq!(rest, b"\x1b[119;1:3");
+
+        let (events, rest) = decode_pieces(&[b"\x1b", b"[119;1", b":3", b"u\r"]);
+        assert_eq!(
+            events,
+            [
+                InputEvent::Release(Key::Char('w')),
+                InputEvent::Press(Key::Enter)
+            ]
+        );
+        assert!(rest.is_empty());
+
+        let (events, rest) = decode_pieces(&[b"\x1b", b"O", b"Q", b"\x1b[", b"[", b"E"]);
+        assert_eq!(
+            events,
+            [
+                InputEvent::Press(Key::Function(2)),
+                InputEvent::Press(Key::Function(5)),
+            ]
+        );
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn decodes_mouse_reports() {
+        let (events, rest) =
+            decode_pieces(&[b"\x1b[<0;10;5M\x1b[<64;1;1M", b"\x1b[<0;10;5m\x1b[M"]);
+        assert_eq!(
+            events,
+            [InputEvent::MouseDown { y: 4, x: 9 }, InputEvent::MouseUp]
+        );
+        //The old format always has three more bytes
+        assert_eq!(rest, b"\x1b[M");
+
+        let (events, _) = decode_pieces(&[b"\x1b[M", &[32, 33 + 9, 33 + 4], b"\x1b[M#!!"]);
+        assert_eq!(
+            events,
+            [InputEvent::MouseDown { y: 4, x: 9 }, InputEvent::MouseUp]
+        );
+    }
+}
@@ -0,0 +1,237 @@
+//Placement of the windows in the terminal. The emulator screen gets the most room: it is drawn
+//in the render mode the user picked, scaled up as far as the terminal allows, or in a smaller
+//mode when it does not fit at all. The debug view and the keypad go where there is room left.
+
+use super::renderer::RenderMode;
+use crate::cpu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+//The debug window is laid out for this size.
+pub const DEBUG_HEIGHT: i32 = 34;
+pub const DEBUG_WIDTH: i32 = 66;
+//The keypad panel has four rows of two lines and four columns of six, plus its border.
+pub const KEYPAD_HEIGHT: i32 = 4 * 2 + 2;
+pub const KEYPAD_WIDTH: i32 = 4 * 6 + 2;
+
+//Largest number of cells per pixel in each direction.
+const MAX_SCALE: usize = 4;
+
+//Render modes from the one taking the most room to the one taking the least.
+const MODES_BY_SIZE: [RenderMode; 3] = [
+    RenderMode::Block,
+    RenderMode::HalfBlock,
+    RenderMode::Braille,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub y: i32,
+    pub x: i32,
+    pub height: i32,
+    pub width: i32,
+}
+
+impl Rect {
+    fn fits(&self, lines: i32, columns: i32) -> bool {
+        self.y + self.height <= lines && self.x + self.width <= columns
+    }
+
+    fn overlaps(&self, other: &Rect) -> bool {
+        self.y < other.y + other.height
+            && other.y < self.y + self.height
+            && self.x < other.x + other.width
+            && other.x < self.x + self.width
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub render_mode: RenderMode,
+    //Cells per pixel in each direction
+    pub scale: usize,
+    pub emulator: Rect,
+    //None when the panel does not fit
+    pub debug: Option<Rect>,
+    pub keypad: Option<Rect>,
+}
+
+//Emulator window for a way of drawing the screen, border included.
+fn emulator_rect(render_mode: RenderMode, scale: usize) -> Rect {
+    let (columns, rows) = render_mode.get_size(SCREEN_WIDTH * scale, SCREEN_HEIGHT * scale);
+    Rect {
+        y: 0,
+        x: 0,
+        height: rows as i32 + 2,
+        width: columns as i32 + 2,
+    }
+}
+
+//Smallest terminal the emulator can run in, as lines and columns.
+pub fn minimum_size() -> (i32, i32) {
+    get_minimum_size(MODES_BY_SIZE[MODES_BY_SIZE.len() - 1])
+}
+
+//Smallest terminal a render mode fits in, as lines and columns.
+pub fn get_minimum_size(render_mode: RenderMode) -> (i32, i32) {
+    let smallest = emulator_rect(render_mode, 1);
+    (smallest.height, smallest.width)
+}
+
+//Ways of drawing the screen from the biggest: every scale of the preferred mode, then the
+//smaller modes.
+fn candidates(preferred: RenderMode) -> Vec<(RenderMode, usize)> {
+    let mut candidates: Vec<_> = (1..=MAX_SCALE)
+        .rev()
+        .map(|scale| (preferred, scale))
+        .collect();
+    let smaller = MODES_BY_SIZE
+        .iter()
+        .skip_while(|mode| **mode != preferred)
+        .skip(1);
+    candidates.extend(smaller.map(|mode| (*mode, 1)));
+    candidates
+}
+
+//Spots for a panel next to the emulator window: on its right, and centred under it.
+fn spots_beside(emulator: &Rect, height: i32, width: i32) -> [Rect; 2] {
+    let right = Rect {
+        y: 0,
+        x: emulator.width + 1,
+        height,
+        width,
+    };
+    let below = Rect {
+        y: emulator.height,
+        x: ((emulator.width - width) / 2).max(0),
+        height,
+        width,
+    };
+    [right, below]
+}
+
+fn place_debug(emulator: &Rect, lines: i32, columns: i32) -> Option<Rect> {
+    spots_beside(emulator, DEBUG_HEIGHT, DEBUG_WIDTH)
+        .into_iter()
+        .find(|spot| spot.fits(lines, columns))
+}
+
+//The keypad goes under the screen when there is room, and on its right otherwise.
+fn place_keypad(emulator: &Rect, debug: Option<&Rect>, lines: i32, columns: i32) -> Option<Rect> {
+    let [right, below] = spots_beside(emulator, KEYPAD_HEIGHT, KEYPAD_WIDTH);
+    [below, right]
+        .into_iter()
+        .find(|spot| spot.fits(lines, columns) && !debug.is_some_and(|debug| spot.overlaps(debug)))
+}
+
+//Layout for a terminal of the given size, None when even the smallest screen does not fit.
+pub fn arrange(lines: i32, columns: i32, preferred: RenderMode, debug: bool) -> Option<Layout> {
+    let fitting: Vec<_> = candidates(preferred)
+        .into_iter()
+        .map(|(render_mode, scale)| (render_mode, scale, emulator_rect(render_mode, scale)))
+        .filter(|(_, _, emulator)| emulator.fits(lines, columns))
+        .collect();
+
+    //A smaller screen is better than no debug view when it is on
+    let with_debug = fitting
+        .iter()
+        .find(|(_, _, emulator)| debug && place_debug(emulator, lines, columns).is_some());
+    let (render_mode, scale, emulator) = *with_debug.or(fitting.first())?;
+
+    let debug = if debug {
+        place_debug(&emulator, lines, columns)
+    } else {
+        None
+    };
+    let keypad = place_keypad(&emulator, debug.as_ref(), lines, columns);
+
+    Some(Layout {
+        render_mode,
+        scale,
+        emulator,
+        debug,
+        keypad,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(y: i32, x: i32, height: i32, width: i32) -> Rect {
+        Rect {
+            y,
+            x,
+            height,
+            width,
+        }
+    }
+
+    #[test]
+    fn scales_up_in_large_terminals() {
+        let layout = arrange(70, 140, RenderMode::Block, false).unwrap();
+        assert_eq!((layout.render_mode, layout.scale), (RenderMode::Block, 2));
+        assert_eq!(layout.emulator, rect(0, 0, 66, 130));
+
+        let layout = arrange(200, 300, RenderMode::Braille, false).unwrap();
+        assert_eq!((layout.render_mode, layout.scale), (RenderMode::Braille, 4));
+    }
+
+    #[test]
+    fn falls_back_to_smaller_modes() {
+        //An 80x24 terminal is too short for a block per pixel
+        let layout = arrange(24, 80, RenderMode::Block, false).unwrap();
+        assert_eq!(
+            (layout.render_mode, layout.scale),
+            (RenderMode::HalfBlock, 1)
+        );
+        assert_eq!(layout.emulator, rect(0, 0, 18, 66));
+        assert_eq!(layout.keypad, None);
+
+        let layout = arrange(12, 40, RenderMode::HalfBlock, false).unwrap();
+        assert_eq!((layout.render_mode, layout.scale), (RenderMode::Braille, 1));
+
+        //The preferred mode is the biggest one used, at the largest scale that fits
+        let layout = arrange(24, 80, RenderMode::Braille, false).unwrap();
+        assert_eq!((layout.render_mode, layout.scale), (RenderMode::Braille, 2));
+    }
+
+    #[test]
+    fn gives_up_below_the_minimum_size() {
+        assert_eq!(minimum_size(), (10, 34));
+        assert_eq!(get_minimum_size(RenderMode::Block), (34, 66));
+        assert!(arrange(9, 200, RenderMode::Block, false).is_none());
+        assert!(arrange(200, 33, RenderMode::Block, false).is_none());
+        assert!(arrange(10, 34, RenderMode::Block, false).is_some());
+    }
+
+    #[test]
+    fn places_the_panels_where_there_is_room() {
+        let layout = arrange(40, 140, RenderMode::Block, false).unwrap();
+        assert_eq!(
+            layout.keypad,
+            Some(rect(0, 67, KEYPAD_HEIGHT, KEYPAD_WIDTH))
+        );
+
+        let layout = arrange(44, 66, RenderMode::Block, false).unwrap();
+        assert_eq!(
+            layout.keypad,
+            Some(rect(34, 20, KEYPAD_HEIGHT, KEYPAD_WIDTH))
+        );
+
+        //The debug view takes the right, and the keypad does not fit anywhere else
+        let layout = arrange(40, 140, RenderMode::Block, true).unwrap();
+        assert_eq!(layout.debug, Some(rect(0, 67, DEBUG_HEIGHT, DEBUG_WIDTH)));
+        assert_eq!(layout.keypad, None);
+    }
+
+    #[test]
+    fn shrinks_the_screen_for_the_debug_view() {
+        let layout = arrange(36, 110, RenderMode::Block, true).unwrap();
+        assert_eq!((layout.render_mode, layout.scale), (RenderMode::Braille, 1));
+        assert_eq!(layout.debug, Some(rect(0, 35, DEBUG_HEIGHT, DEBUG_WIDTH)));
+
+        //When nothing leaves room for it the screen stays as big as it can be
+        let layout = arrange(24, 80, RenderMode::Block, true).unwrap();
+        assert_eq!(layout.render_mode, RenderMode::HalfBlock);
+        assert_eq!(layout.debug, None);
+    }
+}
@@ -0,0 +1,97 @@
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use super::debug_info::DebugInfo;
+use super::Options;
+use crate::cpu::decoder::Platform;
+use crate::cpu::utils;
+use crate::octo::{self, Program};
+use crate::symbols::SymbolMap;
+
+//Room between 0x200 and the end of memory.
+const MAX_PROGRAM_SIZE: usize = 0x1000 - 0x200;
+
+//Reads the program named on the command line, compiling it first when it is Octo source
+//(.8o). Labels and source lines come from the compiler, or from a <rom>.sym file next to a
+//ROM. Exits with an error message when there is nothing we can run, and after writing the
+//ROM when --compile is given.
+pub fn load_program(options: &Options) -> (Vec<u8>, DebugInfo) {
+    let path = options.rom_path.as_deref().unwrap_or_else(|| {
+        eprintln!("Please specify the path to a valid rom in the command!");
+        process::exit(1);
+    });
+    let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+
+    let is_octo = Path::new(path)
+        .extension()
+        .map(|extension| extension == "8o")
+        .unwrap_or(false);
+
+    let (rom_data, symbols) = if is_octo {
+        let source = fs::read_to_string(path).unwrap_or_else(|err| {
+            eprintln!("Problem reading {path}: {err}");
+            process::exit(1);
+        });
+        let program = octo::compile(&source, path, options.platform).unwrap_or_else(|err| {
+            eprintln!("{path}:{err}");
+            process::exit(1);
+        });
+        if let Some(compile_path) = &options.compile_path {
+            write_program(&program, compile_path);
+        }
+        //The interpreter only runs plain CHIP-8
+        if options.platform != Platform::Chip8 {
+            eprintln!(
+                "The emulator only runs CHIP-8 programs, use --compile to write the {} ROM",
+                options.platform
+            );
+            process::exit(1);
+        }
+        (program.rom, program.symbols)
+    } else if options.compile_path.is_some() {
+        eprintln!("Only Octo sources (.8o) can be compiled");
+        process::exit(1);
+    } else {
+        let rom_data = utils::read_rom(path).unwrap_or_else(|err| {
+            eprintln!("Problem reading rom_file: {err}");
+            process::exit(1);
+        });
+        (rom_data, read_symbols(path))
+    };
+
+    if rom_data.len() > MAX_PROGRAM_SIZE {
+        eprintln!("{path} does not fit in memory");
+        process::exit(1);
+    }
+    (rom_data, DebugInfo::new(symbols, directory))
+}
+
+//Writes a compiled program and its symbols next to it, the way the assembler does, then exits.
+fn write_program(program: &Program, rom_path: &str) -> ! {
+    if let Err(err) = fs::write(rom_path, &program.rom) {
+        eprintln!("Problem writing {rom_path}: {err}");
+        process::exit(1);
+    }
+    let symbols_path = Path::new(rom_path).with_extension("sym");
+    let symbols_path = symbols_path.display().to_string();
+    if let Err(err) = program.symbols.write(&symbols_path) {
+        eprintln!("Problem writing {symbols_path}: {err}");
+        process::exit(1);
+    }
+    process::exit(0);
+}
+
+//Symbols written by the assembler next to the ROM, if there are any.
+fn read_symbols(rom_path: &str) -> SymbolMap {
+    let symbols_path = Path::new(rom_path).with_extension("sym");
+    if !symbols_path.exists() {
+        return SymbolMap::default();
+    }
+
+    let symbols_path = symbols_path.display().to_string();
+    SymbolMap::read(&symbols_path).unwrap_or_else(|err| {
+        eprintln!("Problem reading {symbols_path}: {err}");
+        process::exit(1);
+    })
+}
@@ -1,56 +1,152 @@
-use std::process;
-
 extern crate ncurses;
 
+use super::call_stack;
+use super::command::{self, Command};
+use super::debug_info::DebugInfo;
+use super::disassembly::DisassemblyView;
+use super::emulator::{Emulator, Interface};
+use super::key_hold::{InputEvent, Key};
+use super::keypad_panel::KeypadPanel;
+use super::kitty_keyboard;
+use super::layout::{self, Layout, Rect};
+use super::loader;
+use super::persistence::{self, PersistenceMode};
+use super::renderer::{Cell, RenderMode, ScreenDiff};
+use super::theme::Theme;
 use super::EmulatorClient;
-use crate::cpu::utils;
-use crate::Cpu;
+use super::Options;
+use crate::cpu::{Cpu, StackFault};
 
 use ncurses::*;
-use std::{thread, time};
+
+//Number of instructions shown in the disassembly panel.
+const DISASSEMBLY_LINES: usize = 20;
+
+//Colour pair used to flag stack faults.
+const FAULT_COLOR_PAIR: i16 = 1;
+
+//Entries in the palette: the theme colours, then the shades of fading pixels.
+const PALETTE_SIZE: i16 = (persistence::FADE_COLORS + persistence::FADE_LEVELS) as i16;
+//First of the colour pairs for every foreground and background combination of the palette.
+const PIXEL_COLOR_PAIRS: i16 = 2;
+
+//Terminal colours redefined to the theme colours where the terminal allows it. They are the
+//start of the colour cube in 256 colour terminals, so nothing else here uses them.
+const THEME_COLORS: i16 = 16;
 
 pub struct NcursesClient {
-    cpu: Cpu,
+    emulator: Emulator,
+    interface: NcursesInterface,
+}
+
+//Terminal side of the client: the windows, the debugger and reading keys and the mouse.
+struct NcursesInterface {
     emulator_window: WINDOW,
     debug_window: WINDOW,
-    paused: bool,
+    //Render mode picked by the user, which the layout falls back from when it does not fit
+    preferred_mode: RenderMode,
+    //None when the terminal is too small for the emulator
+    layout: Option<Layout>,
+    screen: ScreenDiff,
     debug: bool,
-    single_step: bool,
+    disassembly: DisassemblyView,
+    debug_info: DebugInfo,
+    keypad_panel: KeypadPanel,
+    //Key held down with the mouse
+    mouse_key: Option<u8>,
+    //Bytes of a key sequence still being received, when the kitty keyboard protocol is on
+    kitty_input: Option<Vec<u8>>,
 }
 
-impl NcursesClient {
-    fn shutdown(&mut self) {
-        endwin();
-    }
+impl NcursesInterface {
+    //Fits the windows to the terminal. Called at start, when the terminal is resized and
+    //when a change needs room for a window.
+    fn layout(&mut self) {
+        let (mut lines, mut columns) = (0, 0);
+        getmaxyx(stdscr(), &mut lines, &mut columns);
+        self.layout = layout::arrange(lines, columns, self.preferred_mode, self.debug);
+        self.screen.clear();
+        clear();
+
+        let Some(layout) = self.layout else {
+            let (min_lines, min_columns) = layout::minimum_size();
+            let message = [
+                String::from("Terminal too small"),
+                format!("{columns}x{lines}, needs {min_columns}x{min_lines}"),
+            ];
+            for (row, text) in message.iter().enumerate() {
+                let x = (columns - text.len() as i32).max(0) / 2;
+                mvaddnstr(lines / 2 - 1 + row as i32, x, text, columns);
+            }
+            refresh();
+            return;
+        };
+        if self.debug && layout.debug.is_none() {
+            mvaddnstr(
+                lines - 1,
+                0,
+                "The terminal is too small for the debug view",
+                columns,
+            );
+        }
+        refresh();
 
-    fn render(&self) {
-        let screen = self.cpu.get_screen();
+        place_window(self.emulator_window, &layout.emulator);
+        if let Some(debug) = layout.debug {
+            place_window(self.debug_window, &debug);
+        }
+        if let Some(keypad) = layout.keypad {
+            self.keypad_panel.move_to(keypad.y, keypad.x);
+        }
+    }
 
-        for y in 0..32 {
-            for x in 0..64 {
-                let pixel;
-                if screen[x + (y * 64)] == 1 {
-                    pixel = char::from_u32(0x2588).unwrap();
-                } else {
-                    pixel = ' ';
-                }
+    //Draws cells from a position on, one run of characters per colour change.
+    fn draw_cells(&self, row: usize, mut column: usize, cells: &[Cell]) {
+        for run in
+            cells.chunk_by(|a, b| (a.foreground, a.background) == (b.foreground, b.background))
+        {
+            let text: String = run.iter().map(|cell| cell.character).collect();
+            let pair = PIXEL_COLOR_PAIRS
+                + run[0].foreground as i16 * PALETTE_SIZE
+                + run[0].background as i16;
+
+            wattron(self.emulator_window, COLOR_PAIR(pair));
+            //plus one otherwise we will draw on top of the window borders
+            //thanks ncurses for being like that
+            mvwaddstr(
+                self.emulator_window,
+                row as i32 + 1,
+                column as i32 + 1,
+                &text,
+            );
+            wattroff(self.emulator_window, COLOR_PAIR(pair));
+            column += run.len();
+        }
+    }
 
-                mvwaddstr(
-                    self.emulator_window,
-                    //plus one otherwise we will draw on top of the window borders
-                    //thanks ncurses for being like that
-                    (y + 1) as i32,
-                    (x + 1) as i32,
-                    format!("{}", pixel).as_ref(),
-                );
-            }
+    //Draws the cells that changed since the last frame.
+    fn render_screen(&mut self, emulator: &mut Emulator, layout: &Layout) {
+        let (pixels, dirty_rows) = emulator.take_frame();
+        let spans = self
+            .screen
+            .update(&pixels, &dirty_rows, layout.render_mode, layout.scale);
+        for span in spans {
+            self.draw_cells(span.row, span.column, &span.cells);
         }
+    }
+
+    fn render_windows(&mut self, emulator: &mut Emulator) {
+        let Some(layout) = self.layout else {
+            return;
+        };
+        self.render_screen(emulator, &layout);
+        let cpu = emulator.get_cpu();
 
         //-------------- Debug info --------------
 
-        if self.debug {
+        if self.debug && layout.debug.is_some() {
             //Print gp_register contents
-            let registers = self.cpu.get_gp_registers();
+            let registers = cpu.get_gp_registers();
             for i in 0..16 {
                 mvwaddstr(
                     self.debug_window,
@@ -65,42 +161,27 @@ impl NcursesClient {
                 self.debug_window,
                 18,
                 1,
-                format!("i_reg: 0x{:x}", self.cpu.get_i_register()).as_ref(),
+                format!("i_reg: 0x{:x}", cpu.get_i_register()).as_ref(),
             );
 
             mvwaddstr(
                 self.debug_window,
                 19,
                 1,
-                format!("t_reg: 0x{:x}", self.cpu.get_t_register()).as_ref(),
+                format!("t_reg: 0x{:x}", cpu.get_t_register()).as_ref(),
             );
 
             mvwaddstr(
                 self.debug_window,
                 20,
                 1,
-                format!("i_reg: 0x{:x}", self.cpu.get_s_register()).as_ref(),
-            );
-            //Print stack and stack pointer
-            let stack_pointer = self.cpu.get_stack_pointer();
-            let stack = self.cpu.get_stack();
-            for i in 0..16 {
-                mvwaddstr(
-                    self.debug_window,
-                    1 + i,
-                    17,
-                    format!("Stack {i}: 0x{:x}", stack[i as usize]).as_ref(),
-                );
-            }
-            mvwaddstr(
-                self.debug_window,
-                18,
-                17,
-                format!("Stack Pointer: 0x{:x}", stack_pointer).as_ref(),
+                format!("i_reg: 0x{:x}", cpu.get_s_register()).as_ref(),
             );
+            //Print live call stack frames
+            self.render_call_stack(cpu);
 
             //Print program counter
-            let program_counter = self.cpu.get_program_counter();
+            let program_counter = cpu.get_program_counter();
             mvwaddstr(
                 self.debug_window,
                 2,
@@ -109,67 +190,417 @@ impl NcursesClient {
             );
 
             //Print current opcode
-            let instruction = self.cpu.get_current_instruction();
+            let instruction = cpu.get_current_instruction();
             mvwaddstr(
                 self.debug_window,
                 1,
                 35,
                 format!("instruction: 0x{:x}", instruction).as_ref(),
             );
+
+            //Print the source line the current instruction came from
+            let source = match self.debug_info.symbols.line_at(program_counter) {
+                Some(location) => match self.debug_info.source_text(location) {
+                    Some(text) => format!("{}: {}", location, text),
+                    None => location.to_string(),
+                },
+                None => String::new(),
+            };
+            mvwaddnstr(
+                self.debug_window,
+                3,
+                35,
+                format!("{:<30}", source).as_ref(),
+                30,
+            );
+
+            self.render_disassembly(emulator);
             wrefresh(self.debug_window);
         }
 
+        if layout.keypad.is_some() {
+            self.keypad_panel
+                .render(cpu.get_keyboard(), emulator.get_keymap());
+        }
+
         wrefresh(self.emulator_window);
         refresh();
     }
 
-    fn input_action(&mut self, input: i32) {
-        //Decode user input
-        match input as u8 {
-            b'q' => {
-                self.shutdown();
-                process::exit(0);
-            } // Quit
-            b' ' => self.paused = !self.paused, // Pause/Un-pause
-            b'd' => {
-                // Debug view or not
-                if self.debug {
-                    wclear(self.debug_window);
-                } else {
-                    box_(self.debug_window, 0, 0);
-                }
+    fn render_call_stack(&self, cpu: &Cpu) {
+        mvwaddstr(
+            self.debug_window,
+            1,
+            14,
+            format!(
+                "{:<20}",
+                format!("Call stack (SP={})", cpu.get_stack_pointer())
+            )
+            .as_ref(),
+        );
+
+        //One line per frame: call site > callee entry point
+        let frames = call_stack::frames(cpu);
+        for row in 0..16 {
+            let symbols = &self.debug_info.symbols;
+            let text = match frames.get(row) {
+                Some(frame) => match frame.callee {
+                    Some(callee) => match symbols.label_at(callee) {
+                        Some(label) => format!("{:03X}>{}", frame.call_site, label),
+                        None => format!("{:03X}>{:03X}", frame.call_site, callee),
+                    },
+                    None => format!(
+                        "{:03X}>? (ret {:03X})",
+                        frame.call_site, frame.return_address
+                    ),
+                },
+                None => String::new(),
+            };
+            mvwaddnstr(
+                self.debug_window,
+                2 + row as i32,
+                14,
+                format!("{:<20}", text).as_ref(),
+                20,
+            );
+        }
 
-                wrefresh(self.debug_window);
-                self.debug = !self.debug;
+        let fault = match (cpu.get_stack_fault(), cpu.get_unknown_opcode()) {
+            (Some(StackFault::Overflow), _) => "STACK OVERFLOW".to_string(),
+            (Some(StackFault::Underflow), _) => "STACK UNDERFLOW".to_string(),
+            (None, Some(opcode)) => format!("BAD OPCODE {:04X}", opcode),
+            (None, None) => String::new(),
+        };
+        wattron(self.debug_window, COLOR_PAIR(FAULT_COLOR_PAIR));
+        mvwaddstr(self.debug_window, 18, 14, format!("{:<20}", fault).as_ref());
+        wattroff(self.debug_window, COLOR_PAIR(FAULT_COLOR_PAIR));
+    }
+
+    fn render_disassembly(&self, emulator: &Emulator) {
+        let cpu = emulator.get_cpu();
+        let title = if self.disassembly.is_following() {
+            "Disassembly (following PC)"
+        } else {
+            "Disassembly"
+        };
+        mvwaddstr(self.debug_window, 4, 35, format!("{:<30}", title).as_ref());
+
+        let lines = self.disassembly.lines(
+            cpu,
+            emulator.get_breakpoints(),
+            &self.debug_info.symbols,
+            DISASSEMBLY_LINES,
+        );
+
+        //Labels get a row of their own above the instruction they name
+        let mut rows: Vec<(String, bool)> = Vec::new();
+        for line in &lines {
+            if let Some(label) = &line.label {
+                rows.push((format!("{label}:"), false));
             }
-            b's' => {
-                if self.single_step {
-                    //Non-blocking input
-                    timeout(10);
-                } else {
-                    //Blocking input
-                    timeout(-10);
-                }
+            //'>' marks the instruction about to be executed, '*' a breakpoint.
+            let text = format!(
+                "{}{} {:#06x} {:04X} {}",
+                if line.is_current { '>' } else { ' ' },
+                if line.is_breakpoint { '*' } else { ' ' },
+                line.address,
+                line.opcode,
+                line.text
+            );
+            rows.push((text, line.is_cursor));
+        }
 
-                self.single_step = !self.single_step;
+        //Drop rows from the top while the cursor is below the middle of the panel
+        let cursor = self.disassembly.get_cursor(cpu);
+        let focus = lines
+            .iter()
+            .take_while(|line| line.address <= cursor)
+            .map(|line| 1 + line.label.is_some() as usize)
+            .sum::<usize>();
+        let excess = rows.len().saturating_sub(DISASSEMBLY_LINES);
+        let skip = excess.min(focus.saturating_sub(DISASSEMBLY_LINES / 2));
+
+        for row in 0..DISASSEMBLY_LINES {
+            let (text, is_cursor) = rows.get(row + skip).cloned().unwrap_or_default();
+
+            if is_cursor {
+                wattron(self.debug_window, A_REVERSE());
             }
-            _ => (),
+            mvwaddnstr(
+                self.debug_window,
+                5 + row as i32,
+                35,
+                format!("{:<30}", text).as_ref(),
+                30,
+            );
+            if is_cursor {
+                wattroff(self.debug_window, A_REVERSE());
+            }
+        }
+    }
+
+    //Reads a debugger command on the last line of the debug window and runs it.
+    fn console(&mut self, emulator: &mut Emulator) {
+        let mut line = String::new();
+        mvwaddstr(self.debug_window, 32, 1, format!("{:<64}", ":").as_ref());
+
+        //Blocking, echoed input while the command is typed, as plain text
+        if self.kitty_input.is_some() {
+            kitty_keyboard::disable();
+        }
+        echo();
+        curs_set(CURSOR_VISIBILITY::CURSOR_VISIBLE);
+        timeout(-1);
+        mvwgetnstr(self.debug_window, 32, 2, &mut line, 62);
+        noecho();
+        curs_set(CURSOR_VISIBILITY::CURSOR_INVISIBLE);
+        if self.kitty_input.is_some() {
+            kitty_keyboard::resume();
+        }
+
+        let result = Command::parse(&line).and_then(|command| match command {
+            Command::Break(target) => {
+                let address = command::resolve_address(&target, &self.debug_info.symbols)?;
+                emulator.add_breakpoint(address);
+                Ok(format!("breakpoint at {:#05x}", address))
+            }
+            command => command
+                .execute(emulator.get_cpu_mut())
+                .map(|_| format!("ok: {line}")),
+        });
+        let message = match result {
+            Ok(message) => message,
+            Err(err) => format!("error: {err}"),
+        };
+        mvwaddnstr(
+            self.debug_window,
+            32,
+            1,
+            format!("{:<64}", message).as_ref(),
+            64,
+        );
+    }
+
+    fn mouse_down(&mut self, emulator: &mut Emulator, y: i32, x: i32) {
+        let keypad_shown = self.layout.is_some_and(|layout| layout.keypad.is_some());
+        if let Some(key) = self.keypad_panel.key_at(y, x).filter(|_| keypad_shown) {
+            emulator.press(key);
+            self.mouse_key = Some(key);
+        }
+    }
+
+    fn mouse_up(&mut self, emulator: &mut Emulator) {
+        if let Some(key) = self.mouse_key.take() {
+            emulator.release(key);
+        }
+    }
+
+    //Mouse events ncurses has queued.
+    fn read_mouse() -> Vec<InputEvent> {
+        let mut events = Vec::new();
+        let mut event = MEVENT {
+            id: 0,
+            x: 0,
+            y: 0,
+            z: 0,
+            bstate: 0,
+        };
+        while getmouse(&mut event) == OK {
+            if event.bstate & BUTTON1_PRESSED as mmask_t != 0 {
+                events.push(InputEvent::MouseDown {
+                    y: event.y,
+                    x: event.x,
+                });
+            }
+            if event.bstate & BUTTON1_RELEASED as mmask_t != 0 {
+                events.push(InputEvent::MouseUp);
+            }
+        }
+        events
+    }
+}
+
+impl Interface for NcursesInterface {
+    //Without the kitty keyboard protocol every key is a press. In single step mode the loop
+    //waits for keys, so nothing runs until one comes.
+    fn read_input(&mut self, emulator: &Emulator) -> Vec<InputEvent> {
+        let wait = if emulator.is_single_step() { -1 } else { 10 };
+        timeout(wait);
+
+        let Some(buffer) = self.kitty_input.as_mut() else {
+            return match getch() {
+                ERR => Vec::new(),
+                KEY_MOUSE => Self::read_mouse(),
+                input => key_from_code(input)
+                    .map(InputEvent::Press)
+                    .into_iter()
+                    .collect(),
+            };
+        };
+
+        //Sequences are several bytes long, so take everything that has arrived
+        let mut input = getch();
+        let mut resized = false;
+        timeout(0);
+        while input != ERR {
+            //ncurses reports resizes itself, whether keypad is on or not
+            match input {
+                KEY_RESIZE => resized = true,
+                byte => buffer.push(byte as u8),
+            }
+            input = getch();
+        }
+        timeout(wait);
+
+        let mut events = kitty_keyboard::decode(buffer);
+        if resized {
+            events.push(InputEvent::Press(Key::Resize));
+        }
+        events
+    }
+
+    fn handle_event(&mut self, emulator: &mut Emulator, event: InputEvent) -> bool {
+        let input = match event {
+            InputEvent::Press(input) | InputEvent::Repeat(input) => input,
+            InputEvent::Release(_) => return false,
+            InputEvent::MouseDown { y, x } => {
+                self.mouse_down(emulator, y, x);
+                return true;
+            }
+            InputEvent::MouseUp => {
+                self.mouse_up(emulator);
+                return true;
+            }
+        };
+
+        //Navigation keys for the disassembly panel
+        let cpu = emulator.get_cpu();
+        match input {
+            Key::Up => self.disassembly.scroll(cpu, -1),
+            Key::Down => self.disassembly.scroll(cpu, 1),
+            Key::PageUp => self.disassembly.scroll(cpu, -(DISASSEMBLY_LINES as i32)),
+            Key::PageDown => self.disassembly.scroll(cpu, DISASSEMBLY_LINES as i32),
+            Key::Enter => {
+                self.disassembly.follow_jump(cpu);
+            }
+            Key::Backspace => self.disassembly.back(),
+
+            //Debugger controls, the others are common to all frontends
+            Key::Function(1) => {
+                // Debug view or not
+                self.debug = !self.debug;
+                self.layout();
+            }
+            Key::Function(5) => {
+                // Toggle breakpoint at the disassembly cursor
+                let address = self.disassembly.get_cursor(cpu);
+                emulator.toggle_breakpoint(address);
+            }
+            Key::Function(6) => self.disassembly.follow_program_counter(), // Make disassembly follow PC again
+            Key::Function(7) => {
+                // Next render mode
+                self.preferred_mode = self.preferred_mode.next();
+                self.layout();
+            }
+            Key::Function(9)
+                if self.debug && self.layout.is_some_and(|layout| layout.debug.is_some()) =>
+            {
+                self.console(emulator) // Edit registers and memory
+            }
+            Key::Resize => self.layout(),
+            _ => return false,
+        }
+        true
+    }
+
+    fn render(&mut self, emulator: &mut Emulator) {
+        self.render_windows(emulator);
+    }
+
+    fn shutdown(&mut self) {
+        if self.kitty_input.is_some() {
+            kitty_keyboard::disable();
+        }
+        endwin();
+    }
+}
+
+//Key for a getch code, None for codes we have no use for.
+fn key_from_code(code: i32) -> Option<Key> {
+    let key = match code {
+        KEY_UP => Key::Up,
+        KEY_DOWN => Key::Down,
+        KEY_LEFT => Key::Left,
+        KEY_RIGHT => Key::Right,
+        KEY_PPAGE => Key::PageUp,
+        KEY_NPAGE => Key::PageDown,
+        KEY_ENTER | 0x0A | 0x0D => Key::Enter,
+        //Terminals send either DEL or ^H for Backspace, so Ctrl-H is Backspace too
+        KEY_BACKSPACE | 0x7F | 0x08 => Key::Backspace,
+        //Ctrl and a letter, other than Tab
+        0x01..=0x1A if code != 0x09 => Key::Control((b'a' + code as u8 - 1) as char),
+        KEY_RESIZE => Key::Resize,
+        KEY_F1..=KEY_F12 => Key::Function((code - KEY_F0) as u8),
+        code => {
+            return u32::try_from(code)
+                .ok()
+                .and_then(char::from_u32)
+                .map(Key::Char)
+        }
+    };
+    Some(key)
+}
+
+//Resizes and moves a window, leaving it blank with a border.
+fn place_window(window: WINDOW, rect: &Rect) {
+    //Resizing first so the window fits wherever it goes
+    wresize(window, rect.height, rect.width);
+    mvwin(window, rect.y, rect.x);
+    werase(window);
+    box_(window, 0, 0);
+}
+
+//Sets up the colour pairs for the theme. The colours are exact when the terminal lets us
+//change its colours, and the closest ones it has otherwise.
+fn init_palette(theme: &Theme) {
+    let palette = persistence::palette(theme);
+
+    let colors: Vec<i16> = if can_change_color() && COLORS() >= (THEME_COLORS + PALETTE_SIZE) as i32
+    {
+        //ncurses colour components go from 0 to 1000
+        let component = |value: u8| (value as i32 * 1000 / 255) as i16;
+        (0..PALETTE_SIZE)
+            .zip(palette)
+            .map(|(index, rgb)| {
+                let color = THEME_COLORS + index;
+                init_color(
+                    color,
+                    component(rgb.red),
+                    component(rgb.green),
+                    component(rgb.blue),
+                );
+                color
+            })
+            .collect()
+    } else if COLORS() >= 256 {
+        palette.iter().map(|rgb| rgb.to_256_colors()).collect()
+    } else {
+        palette.iter().map(|rgb| rgb.to_8_colors()).collect()
+    };
+
+    for (foreground, foreground_color) in colors.iter().enumerate() {
+        for (background, background_color) in colors.iter().enumerate() {
+            let pair = PIXEL_COLOR_PAIRS + foreground as i16 * PALETTE_SIZE + background as i16;
+            init_pair(pair, *foreground_color, *background_color);
         }
     }
 }
 
 impl EmulatorClient for NcursesClient {
-    fn build(mut rom_path: Option<&String>) -> Self {
+    fn build(options: &Options) -> Self {
         //TOOD: If path is Some, use it. Otherwise ask the user.
-        let rom_data = if let Some(path) = rom_path.take() {
-            utils::read_rom(path.as_str()).unwrap_or_else(|err| {
-                eprintln!("Problem reading rom_file: {err}");
-                process::exit(1);
-            })
-        } else {
-            eprintln!("Please specify the path to a valid rom in the command!");
-            process::exit(1);
-        };
+        let (rom_data, debug_info) = loader::load_program(options);
+        let mut emulator = Emulator::build(options, rom_data);
 
         //ncurses initialization
 
@@ -180,54 +611,59 @@ impl EmulatorClient for NcursesClient {
         //run of the mill initialization + non-blocking getch
         initscr();
         noecho();
+        //Separate press and release reports so keypad keys can be held with the mouse
+        mousemask((BUTTON1_PRESSED | BUTTON1_RELEASED) as mmask_t, None);
+        mouseinterval(0);
+
+        //Terminal answers to the kitty keyboard query must not be taken for keys
+        keypad(stdscr(), false);
+        let kitty = kitty_keyboard::enable();
+        //With the protocol on every key is an escape sequence we decode ourselves
+        keypad(stdscr(), !kitty);
+        emulator.set_reports_release(kitty);
         timeout(10);
         curs_set(CURSOR_VISIBILITY::CURSOR_INVISIBLE);
 
+        if has_colors() {
+            start_color();
+            init_pair(FAULT_COLOR_PAIR, COLOR_RED, COLOR_BLACK);
+            init_palette(&options.theme);
+        } else {
+            //Without colours fading pixels can only stay lit
+            emulator.set_persistence_mode(PersistenceMode::Hold);
+        }
+
         //Making layout for emulator
         //Will have 3 windows:
         //1) emulator output screen
-        //2) debug register screen
-        //3) debug memory output
-
-        //Emulator window (note: had to do dimension + 2 as otherwise border)
-        //is considered as a pixel
-        let emulator_window = newwin(34, 66, 0, 0);
-        box_(emulator_window, 0, 0);
-        refresh();
-        wrefresh(emulator_window);
-
-        //Debug Window
-        let debug_window = newwin(34, 66, 0, 67);
-        box_(debug_window, 0, 0);
-
-        NcursesClient {
-            cpu: Cpu::new(rom_data),
+        //2) debug registers, call stack and disassembly
+        //3) keypad
+        //layout() sizes and places them for the terminal
+        let emulator_window = newwin(1, 1, 0, 0);
+        let debug_window = newwin(layout::DEBUG_HEIGHT, layout::DEBUG_WIDTH, 0, 0);
+        let keypad_panel = KeypadPanel::new(0, 0);
+
+        let mut interface = NcursesInterface {
             emulator_window,
             debug_window,
-            paused: true,
+            preferred_mode: options.render_mode,
+            layout: None,
+            screen: ScreenDiff::default(),
             debug: false,
-            single_step: false,
+            disassembly: DisassemblyView::default(),
+            debug_info,
+            kitty_input: kitty.then(Vec::new),
+            keypad_panel,
+            mouse_key: None,
+        };
+        interface.layout();
+        NcursesClient {
+            emulator,
+            interface,
         }
     }
 
     fn update(&mut self) {
-        loop {
-            let ten_millis = time::Duration::from_millis(2);
-            thread::sleep(ten_millis);
-
-            //Read user input
-            let input = getch();
-            self.input_action(input);
-
-            //Only update emulator and render if not paused
-
-            self.render();
-
-            if (self.single_step && !self.paused && input as u8 == b'n')
-                || (!self.single_step && !self.paused)
-            {
-                self.cpu.clock();
-            }
-        }
+        self.emulator.run(&mut self.interface)
     }
 }
@@ -0,0 +1,170 @@
+use std::time::Duration;
+
+use super::command::parse_number;
+use super::key_hold::DEFAULT_HOLD_TIME;
+use super::persistence::PersistenceMode;
+use super::renderer::RenderMode;
+use super::theme::{Rgb, Theme};
+use crate::cpu::decoder::Platform;
+use crate::trace::{TraceFormat, TraceOptions};
+
+const USAGE: &str = "usage: chip_8_emulator <rom> [--trace <file>] [--trace-format text|binary] \
+[--trace-range <start>-<end>] [--keymap <file>] [--key-hold <ms>] \
+[--renderer block|half-block|braille] \
+[--theme classic|amber|green|lcd] [--foreground <#rrggbb>] [--background <#rrggbb>] \
+[--persistence <frames>] [--blend] [--frontend ncurses|ansi] \
+[--platform chip8|schip|xochip] [--compile <out.ch8>]";
+
+//A second at 60 frames per second.
+const MAX_PERSISTENCE_FRAMES: u16 = 60;
+
+//User interfaces the emulator can run in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frontend {
+    #[cfg(feature = "ncurses")]
+    Ncurses,
+    //Escape sequences written straight to the terminal, for terminals without terminfo
+    Ansi,
+}
+
+//Command line options shared by every client.
+pub struct Options {
+    pub rom_path: Option<String>,
+    pub trace: Option<TraceOptions>,
+    //Keymap file to use instead of the default one.
+    pub keymap_path: Option<String>,
+    //How long a key stays down after it was last seen, in terminals without release events.
+    pub key_hold_time: Duration,
+    pub render_mode: RenderMode,
+    //Screen colours, a built-in theme with the colours given on the command line
+    pub theme: Theme,
+    //Frames lit pixels stay visible after going off, to hide flicker
+    pub persistence_frames: Option<u8>,
+    pub persistence_mode: PersistenceMode,
+    pub frontend: Frontend,
+    //Machine Octo sources are compiled for
+    pub platform: Platform,
+    //Where to write the ROM compiled from an Octo source, instead of running it
+    pub compile_path: Option<String>,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut rom_path = None;
+        let mut trace_path = None;
+        let mut trace_format = TraceFormat::Text;
+        let mut trace_range = None;
+        let mut keymap_path = None;
+        let mut key_hold_time = DEFAULT_HOLD_TIME;
+        let mut render_mode = RenderMode::Block;
+        let mut theme = Theme::default();
+        let mut foreground = None;
+        let mut background = None;
+        let mut persistence_frames = None;
+        let mut persistence_mode = PersistenceMode::Hold;
+        #[cfg(feature = "ncurses")]
+        let mut frontend = Frontend::Ncurses;
+        #[cfg(not(feature = "ncurses"))]
+        let mut frontend = Frontend::Ansi;
+        let mut platform = Platform::Chip8;
+        let mut compile_path = None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("missing value for {arg}\n{USAGE}"))
+            };
+
+            match arg.as_str() {
+                "--trace" => trace_path = Some(value()?.clone()),
+                "--trace-format" => {
+                    trace_format = match value()?.as_str() {
+                        "text" => TraceFormat::Text,
+                        "binary" => TraceFormat::Binary,
+                        other => return Err(format!("unknown trace format '{other}'\n{USAGE}")),
+                    }
+                }
+                "--trace-range" => {
+                    let range = value()?;
+                    let (start, end) = range.split_once('-').ok_or_else(|| {
+                        format!("trace range must look like 0x200-0x2FF\n{USAGE}")
+                    })?;
+                    trace_range = Some(parse_number(start)?..=parse_number(end)?);
+                }
+                "--keymap" => keymap_path = Some(value()?.clone()),
+                "--key-hold" => {
+                    key_hold_time = Duration::from_millis(parse_number(value()?)? as u64)
+                }
+                "--renderer" => {
+                    let name = value()?;
+                    render_mode = RenderMode::parse(name)
+                        .ok_or_else(|| format!("unknown renderer '{name}'\n{USAGE}"))?;
+                }
+                "--theme" => {
+                    let name = value()?;
+                    theme = Theme::find(name)
+                        .ok_or_else(|| format!("unknown theme '{name}'\n{USAGE}"))?;
+                }
+                "--foreground" => foreground = Some(Rgb::parse(value()?)?),
+                "--background" => background = Some(Rgb::parse(value()?)?),
+                "--persistence" => {
+                    let frames = parse_number(value()?)?;
+                    if !(1..=MAX_PERSISTENCE_FRAMES).contains(&frames) {
+                        return Err(format!(
+                            "persistence must be between 1 and {MAX_PERSISTENCE_FRAMES} frames"
+                        ));
+                    }
+                    persistence_frames = Some(frames as u8);
+                }
+                "--blend" => persistence_mode = PersistenceMode::Blend,
+                "--frontend" => {
+                    frontend = match value()?.as_str() {
+                        #[cfg(feature = "ncurses")]
+                        "ncurses" => Frontend::Ncurses,
+                        #[cfg(not(feature = "ncurses"))]
+                        "ncurses" => {
+                            return Err(String::from("built without the ncurses frontend"))
+                        }
+                        "ansi" => Frontend::Ansi,
+                        other => return Err(format!("unknown frontend '{other}'\n{USAGE}")),
+                    }
+                }
+                "--platform" => {
+                    platform = value()?.parse().map_err(|err| format!("{err}\n{USAGE}"))?
+                }
+                "--compile" => compile_path = Some(value()?.clone()),
+                flag if flag.starts_with("--") => {
+                    return Err(format!("unknown option '{flag}'\n{USAGE}"))
+                }
+                path => rom_path = Some(path.to_string()),
+            }
+        }
+
+        //Colours given on their own win over the theme wherever they come on the line
+        if let Some(background) = background {
+            theme.palette[0] = background;
+        }
+        if let Some(foreground) = foreground {
+            theme.palette[1] = foreground;
+        }
+
+        Ok(Options {
+            rom_path,
+            trace: trace_path.map(|path| TraceOptions {
+                path,
+                format: trace_format,
+                range: trace_range,
+            }),
+            keymap_path,
+            key_hold_time,
+            render_mode,
+            theme,
+            persistence_frames,
+            persistence_mode,
+            frontend,
+            platform,
+            compile_path,
+        })
+    }
+}
@@ -0,0 +1,136 @@
+//Display filter against flicker. CHIP-8 programs move sprites by XOR-erasing and redrawing them,
+//so a pixel can be off for a frame while it is moving. The filter keeps pixels visible for a few
+//frames after they go off, like the slow phosphor of old screens, without touching the emulator.
+
+use super::theme::{Rgb, Theme};
+
+//Palette indices after the theme colours hold the colours of fading pixels, from the brightest
+//to the dimmest.
+pub const FADE_COLORS: u8 = 4;
+pub const FADE_LEVELS: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PersistenceMode {
+    //Pixels stay fully lit while they fade
+    Hold,
+    //Pixels fade towards the background, which needs colours
+    Blend,
+}
+
+pub struct PersistenceFilter {
+    frames: u8,
+    mode: PersistenceMode,
+    //Frames since each pixel was last lit
+    ages: Vec<u8>,
+    //Palette index each pixel had when it was last lit
+    colors: Vec<u8>,
+}
+
+impl PersistenceFilter {
+    //Filter keeping pixels for the given number of frames after they go off.
+    pub fn new(frames: u8, mode: PersistenceMode, pixel_count: usize) -> PersistenceFilter {
+        PersistenceFilter {
+            frames,
+            mode,
+            ages: vec![u8::MAX; pixel_count],
+            colors: vec![0; pixel_count],
+        }
+    }
+
+    #[cfg(feature = "ncurses")]
+    pub fn set_mode(&mut self, mode: PersistenceMode) {
+        self.mode = mode;
+    }
+
+    //Takes in the screen at the end of a frame.
+    pub fn update(&mut self, screen: &[u8]) {
+        for (pixel, value) in screen.iter().enumerate() {
+            if *value != 0 {
+                self.ages[pixel] = 0;
+                self.colors[pixel] = *value;
+            } else {
+                self.ages[pixel] = self.ages[pixel].saturating_add(1);
+            }
+        }
+    }
+
+    //Palette index to show for each pixel of the current screen.
+    pub fn apply(&self, screen: &[u8]) -> Vec<u8> {
+        screen
+            .iter()
+            .zip(self.ages.iter().zip(&self.colors))
+            .map(|(value, (age, color))| match *age {
+                _ if *value != 0 => *value,
+                0 => *color,
+                age if age > self.frames => 0,
+                //Only the colour of plain CHIP-8 pixels has fading shades
+                age if self.mode == PersistenceMode::Blend && *color == 1 => {
+                    FADE_COLORS + (age - 1) * FADE_LEVELS / self.frames
+                }
+                _ => *color,
+            })
+            .collect()
+    }
+}
+
+//Colours of every palette index: the theme colours, then the fading shades of the first plane
+//colour from the brightest.
+pub fn palette(theme: &Theme) -> Vec<Rgb> {
+    let mut palette = theme.palette.to_vec();
+    for level in 0..FADE_LEVELS as u16 {
+        let weight = 256 * (FADE_LEVELS as u16 - level) / (FADE_LEVELS as u16 + 1);
+        palette.push(theme.palette[1].mix(theme.palette[0], weight));
+    }
+    palette
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Palette index shown for one pixel lit in plane_color and then turned off, on every frame
+    //after it went off until it is gone.
+    fn fade(frames: u8, mode: PersistenceMode, plane_color: u8) -> Vec<u8> {
+        let mut filter = PersistenceFilter::new(frames, mode, 1);
+        filter.update(&[plane_color]);
+
+        let mut shown = vec![filter.apply(&[0])[0]];
+        for _ in 0..=frames {
+            filter.update(&[0]);
+            shown.push(filter.apply(&[0])[0]);
+        }
+        shown
+    }
+
+    #[test]
+    fn blend_steps_through_the_fading_shades() {
+        assert_eq!(fade(3, PersistenceMode::Blend, 1), [1, 4, 5, 6, 0]);
+        assert_eq!(fade(6, PersistenceMode::Blend, 1), [1, 4, 4, 5, 5, 6, 6, 0]);
+        assert_eq!(fade(1, PersistenceMode::Blend, 1), [1, 4, 0]);
+    }
+
+    #[test]
+    fn hold_and_other_planes_stay_lit() {
+        assert_eq!(fade(3, PersistenceMode::Hold, 1), [1, 1, 1, 1, 0]);
+        assert_eq!(fade(3, PersistenceMode::Blend, 2), [2, 2, 2, 2, 0]);
+    }
+
+    #[test]
+    fn lit_pixels_win_over_fading_ones() {
+        let mut filter = PersistenceFilter::new(3, PersistenceMode::Blend, 2);
+        filter.update(&[1, 1]);
+        filter.update(&[0, 0]);
+        assert_eq!(filter.apply(&[3, 0]), [3, 4]);
+    }
+
+    #[test]
+    fn palette_has_a_shade_per_fade_level() {
+        let theme = Theme::default();
+        let palette = palette(&theme);
+        assert_eq!(palette.len(), (FADE_COLORS + FADE_LEVELS) as usize);
+        assert_eq!(palette[..FADE_COLORS as usize], theme.palette);
+        //From the brightest to the dimmest
+        assert_eq!(palette[4], theme.palette[1].mix(theme.palette[0], 192));
+        assert_eq!(palette[6], theme.palette[1].mix(theme.palette[0], 64));
+    }
+}
@@ -0,0 +1,120 @@
+//Terminal handling without curses: raw mode through termios, the terminal size and input read
+//straight from stdin. Only needs a tty, so it works where there is no terminfo database, as in
+//minimal containers and on serial consoles.
+
+use std::io::{self, Write};
+use std::mem;
+use std::panic;
+use std::sync::Mutex;
+
+//Size used when the terminal does not know its own, as serial consoles often don't.
+const DEFAULT_LINES: i32 = 24;
+const DEFAULT_COLUMNS: i32 = 80;
+
+//Settings from before raw mode, for the panic hook, which has no RawTerminal to restore.
+static ORIGINAL: Mutex<Option<libc::termios>> = Mutex::new(None);
+
+//Puts the terminal in raw mode, keeping the previous settings to go back to when dropped.
+pub struct RawTerminal {
+    original: libc::termios,
+}
+
+impl RawTerminal {
+    //Raw mode with reads that never block: keys arrive one byte at a time, unechoed and
+    //without Ctrl-C or Ctrl-Z doing anything.
+    pub fn enable() -> io::Result<RawTerminal> {
+        //SAFETY: termios is plain data, filled in by tcgetattr before it is used
+        let mut original: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut raw = original;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        *ORIGINAL.lock().unwrap_or_else(|err| err.into_inner()) = Some(original);
+        Ok(RawTerminal { original })
+    }
+
+    pub fn restore(&self) {
+        set_attributes(&self.original);
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        self.restore();
+    }
+}
+
+fn set_attributes(attributes: &libc::termios) {
+    unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, attributes) };
+}
+
+//Makes panics put the terminal back before the message is printed, so it can be read and the
+//shell still works. leave undoes what the client changed on screen.
+pub fn restore_on_panic(leave: impl Fn() + Send + Sync + 'static) {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        leave();
+        if let Some(original) = *ORIGINAL.lock().unwrap_or_else(|err| err.into_inner()) {
+            set_attributes(&original);
+        }
+        hook(info);
+    }));
+}
+
+//Lines and columns of the terminal. Falls back on $LINES and $COLUMNS, then on 80x24.
+pub fn get_size() -> (i32, i32) {
+    //SAFETY: winsize is plain data, and TIOCGWINSZ only writes to it
+    let mut size: libc::winsize = unsafe { mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0
+        && size.ws_row > 0
+        && size.ws_col > 0
+    {
+        return (size.ws_row as i32, size.ws_col as i32);
+    }
+
+    let variable = |name: &str, default: i32| {
+        std::env::var(name)
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .filter(|value| *value > 0)
+            .unwrap_or(default)
+    };
+    (
+        variable("LINES", DEFAULT_LINES),
+        variable("COLUMNS", DEFAULT_COLUMNS),
+    )
+}
+
+//Appends whatever input has arrived to buffer, without waiting.
+pub fn read_input(buffer: &mut Vec<u8>) {
+    let mut bytes = [0; 64];
+    loop {
+        let count = unsafe {
+            libc::read(
+                libc::STDIN_FILENO,
+                bytes.as_mut_ptr() as *mut libc::c_void,
+                bytes.len(),
+            )
+        };
+        if count <= 0 {
+            return;
+        }
+        buffer.extend(&bytes[..count as usize]);
+    }
+}
+
+//Writes text in one go so a frame never shows half drawn.
+pub fn write(text: &str) {
+    let mut stdout = io::stdout().lock();
+    //Nothing useful can be done if the terminal is gone
+    let _ = stdout
+        .write_all(text.as_bytes())
+        .and_then(|_| stdout.flush());
+}
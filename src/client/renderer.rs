@@ -0,0 +1,338 @@
+//Turns the emulator screen into terminal cells. Pixels are palette indices, 0 being the
+//background, and so are the colours of the cells, which leaves the actual colours to the client.
+
+use crate::cpu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+//Ways of packing pixels into terminal cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    //One full block per pixel. Pixels come out twice as tall as they are wide.
+    Block,
+    //Two pixels stacked in each cell with the upper and lower half block characters, which
+    //gives square pixels and halves the number of rows.
+    HalfBlock,
+    //2x4 pixels in each cell as the dots of a braille pattern. Fits a 128x64 screen in 64x16
+    //cells, but a cell only has one colour.
+    Braille,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub character: char,
+    pub foreground: u8,
+    pub background: u8,
+}
+
+//Cells to draw in a row of the screen, from a column on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub row: usize,
+    pub column: usize,
+    pub cells: Vec<Cell>,
+}
+
+//Keeps the cells on the terminal so a frame only draws what changed.
+#[derive(Default)]
+pub struct ScreenDiff {
+    //Empty when the whole screen has to be drawn again
+    drawn: Vec<Cell>,
+}
+
+const FULL_BLOCK: char = '\u{2588}';
+const UPPER_HALF_BLOCK: char = '\u{2580}';
+const LOWER_HALF_BLOCK: char = '\u{2584}';
+
+//Braille patterns are this plus one bit per dot.
+const BRAILLE_BLANK: u32 = 0x2800;
+//Bit of the dot for each pixel of a 2x4 block, indexed by [y][x].
+const BRAILLE_DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+const MODES: [RenderMode; 3] = [
+    RenderMode::Block,
+    RenderMode::HalfBlock,
+    RenderMode::Braille,
+];
+
+impl RenderMode {
+    pub fn parse(name: &str) -> Option<RenderMode> {
+        MODES.into_iter().find(|mode| mode.get_name() == name)
+    }
+
+    pub fn get_name(self) -> &'static str {
+        match self {
+            RenderMode::Block => "block",
+            RenderMode::HalfBlock => "half-block",
+            RenderMode::Braille => "braille",
+        }
+    }
+
+    //Mode after this one, to cycle through them at runtime.
+    pub fn next(self) -> RenderMode {
+        let index = MODES.iter().position(|mode| *mode == self).unwrap_or(0);
+        MODES[(index + 1) % MODES.len()]
+    }
+
+    //Columns and rows of cells needed for a screen of the given size.
+    pub fn get_size(self, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            RenderMode::Block => (width, height),
+            RenderMode::HalfBlock => (width, height.div_ceil(2)),
+            RenderMode::Braille => (width.div_ceil(2), height.div_ceil(4)),
+        }
+    }
+
+    //Cells for a row-major screen, row by row.
+    pub fn render(self, pixels: &[u8], width: usize, height: usize) -> Vec<Cell> {
+        let pixel = |x: usize, y: usize| {
+            if x < width && y < height {
+                pixels[x + y * width]
+            } else {
+                0
+            }
+        };
+        let (columns, rows) = self.get_size(width, height);
+
+        let mut cells = Vec::with_capacity(columns * rows);
+        for row in 0..rows {
+            for column in 0..columns {
+                cells.push(match self {
+                    RenderMode::Block => block(pixel(column, row)),
+                    RenderMode::HalfBlock => {
+                        half_block(pixel(column, row * 2), pixel(column, row * 2 + 1))
+                    }
+                    RenderMode::Braille => {
+                        let mut block = [[0; 2]; 4];
+                        for (y, pixels) in block.iter_mut().enumerate() {
+                            for (x, value) in pixels.iter_mut().enumerate() {
+                                *value = pixel(column * 2 + x, row * 4 + y);
+                            }
+                        }
+                        braille(&block)
+                    }
+                });
+            }
+        }
+        cells
+    }
+}
+
+impl ScreenDiff {
+    //Forgets what is on the terminal, after it was cleared or the layout changed.
+    pub fn clear(&mut self) {
+        self.drawn.clear();
+    }
+
+    //Spans of cells that changed for the emulator screen drawn in a mode and scale. Only rows of
+    //cells covering a dirty row of pixels are compared.
+    pub fn update(
+        &mut self,
+        pixels: &[u8],
+        dirty_rows: &[bool],
+        mode: RenderMode,
+        factor: usize,
+    ) -> Vec<Span> {
+        let (width, height) = (SCREEN_WIDTH * factor, SCREEN_HEIGHT * factor);
+        let (columns, rows) = mode.get_size(width, height);
+        //Scaled pixel rows in each row of cells
+        let pixel_rows = height.div_ceil(rows);
+        let check_all = self.drawn.is_empty();
+
+        let pixels = scale(pixels, SCREEN_WIDTH, SCREEN_HEIGHT, factor);
+        let cells = mode.render(&pixels, width, height);
+
+        let mut spans = Vec::new();
+        for (row, row_cells) in cells.chunks(columns).enumerate() {
+            let first_pixel_row = row * pixel_rows / factor;
+            let last_pixel_row = ((row + 1) * pixel_rows).div_ceil(factor).min(SCREEN_HEIGHT);
+            if !check_all && !dirty_rows[first_pixel_row..last_pixel_row].contains(&true) {
+                continue;
+            }
+
+            let span = match self.drawn.get(row * columns..(row + 1) * columns) {
+                Some(drawn) => {
+                    let changed = |(new, old): (&Cell, &Cell)| new != old;
+                    let pairs = || row_cells.iter().zip(drawn);
+                    match (pairs().position(changed), pairs().rposition(changed)) {
+                        (Some(first), Some(last)) => first..last + 1,
+                        _ => continue,
+                    }
+                }
+                None => 0..row_cells.len(),
+            };
+            spans.push(Span {
+                row,
+                column: span.start,
+                cells: row_cells[span].to_vec(),
+            });
+        }
+
+        self.drawn = cells;
+        spans
+    }
+}
+
+//Screen made bigger by repeating every pixel factor times in each direction.
+pub fn scale(pixels: &[u8], width: usize, height: usize, factor: usize) -> Vec<u8> {
+    let mut scaled = Vec::with_capacity(pixels.len() * factor * factor);
+    for row in pixels.chunks(width).take(height) {
+        let scaled_row: Vec<u8> = row
+            .iter()
+            .flat_map(|pixel| std::iter::repeat_n(*pixel, factor))
+            .collect();
+        for _ in 0..factor {
+            scaled.extend(&scaled_row);
+        }
+    }
+    scaled
+}
+
+fn block(pixel: u8) -> Cell {
+    Cell {
+        character: if pixel == 0 { ' ' } else { FULL_BLOCK },
+        foreground: pixel,
+        background: 0,
+    }
+}
+
+//Lit halves are drawn in the foreground so the cell still reads right without colours. Only
+//two different lit colours need the background.
+fn half_block(top: u8, bottom: u8) -> Cell {
+    let (character, foreground, background) = match (top, bottom) {
+        (0, 0) => (' ', 0, 0),
+        (top, 0) => (UPPER_HALF_BLOCK, top, 0),
+        (0, bottom) => (LOWER_HALF_BLOCK, bottom, 0),
+        (top, bottom) if top == bottom => (FULL_BLOCK, top, 0),
+        (top, bottom) => (UPPER_HALF_BLOCK, top, bottom),
+    };
+    Cell {
+        character,
+        foreground,
+        background,
+    }
+}
+
+//A cell has a single colour, that of the lit pixel with the lowest palette index, so fully lit
+//pixels win over fading ones.
+fn braille(block: &[[u8; 2]; 4]) -> Cell {
+    let mut pattern = 0;
+    let mut foreground = 0;
+    for (y, pixels) in block.iter().enumerate() {
+        for (x, pixel) in pixels.iter().enumerate() {
+            if *pixel != 0 {
+                pattern |= BRAILLE_DOTS[y][x];
+                if foreground == 0 || *pixel < foreground {
+                    foreground = *pixel;
+                }
+            }
+        }
+    }
+    Cell {
+        //Some fonts show faint dots for the blank pattern
+        character: match pattern {
+            0 => ' ',
+            pattern => char::from_u32(BRAILLE_BLANK + pattern).unwrap_or(' '),
+        },
+        foreground,
+        background: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(character: char, foreground: u8, background: u8) -> Cell {
+        Cell {
+            character,
+            foreground,
+            background,
+        }
+    }
+
+    #[test]
+    fn half_blocks_stack_two_pixels() {
+        #[rustfmt::skip]
+        let pixels = [
+            1, 1, 0,
+            1, 2, 0,
+            0, 0, 1,
+            3, 0, 0,
+        ];
+        assert_eq!(
+            RenderMode::HalfBlock.render(&pixels, 3, 4),
+            [
+                cell(FULL_BLOCK, 1, 0),
+                cell(UPPER_HALF_BLOCK, 1, 2),
+                cell(' ', 0, 0),
+                cell(LOWER_HALF_BLOCK, 3, 0),
+                cell(' ', 0, 0),
+                cell(UPPER_HALF_BLOCK, 1, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn braille_packs_two_by_four_pixels() {
+        //The second cell is only half covered by the screen
+        #[rustfmt::skip]
+        let pixels = [
+            4, 0, 1,
+            0, 4, 0,
+            0, 0, 0,
+            5, 1, 0,
+        ];
+        assert_eq!(
+            RenderMode::Braille.render(&pixels, 3, 4),
+            [cell('\u{28D1}', 1, 0), cell('\u{2801}', 1, 0)]
+        );
+        assert_eq!(RenderMode::Braille.render(&[0; 8], 2, 4), [cell(' ', 0, 0)]);
+    }
+
+    #[test]
+    fn scales_every_pixel() {
+        assert_eq!(
+            scale(&[1, 0, 0, 2], 2, 2, 2),
+            [1, 1, 0, 0, 1, 1, 0, 0, 0, 0, 2, 2, 0, 0, 2, 2]
+        );
+    }
+
+    #[test]
+    fn screen_diff_draws_changed_spans() {
+        let mut screen = ScreenDiff::default();
+        let mut pixels = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let mut dirty_rows = [false; SCREEN_HEIGHT];
+
+        //Everything the first time
+        let spans = screen.update(&pixels, &dirty_rows, RenderMode::HalfBlock, 1);
+        assert_eq!(spans.len(), SCREEN_HEIGHT / 2);
+        assert!(spans.iter().all(|span| span.cells.len() == SCREEN_WIDTH));
+
+        //Two pixels in the same row of cells
+        pixels[3 * SCREEN_WIDTH + 5] = 1;
+        pixels[2 * SCREEN_WIDTH + 9] = 1;
+        dirty_rows[2] = true;
+        dirty_rows[3] = true;
+        let spans = screen.update(&pixels, &dirty_rows, RenderMode::HalfBlock, 1);
+        let mut expected = vec![cell(' ', 0, 0); 5];
+        expected[0] = cell(LOWER_HALF_BLOCK, 1, 0);
+        expected[4] = cell(UPPER_HALF_BLOCK, 1, 0);
+        assert_eq!(
+            spans,
+            [Span {
+                row: 1,
+                column: 5,
+                cells: expected
+            }]
+        );
+
+        //Rows that are not dirty are not looked at
+        pixels[0] = 1;
+        assert!(screen
+            .update(&pixels, &dirty_rows, RenderMode::HalfBlock, 1)
+            .is_empty());
+
+        screen.clear();
+        let spans = screen.update(&pixels, &dirty_rows, RenderMode::Braille, 2);
+        assert_eq!(spans.len(), SCREEN_HEIGHT * 2 / 4);
+    }
+}
@@ -0,0 +1,152 @@
+//Colours of the screen. A palette has an entry for each combination of the two XO-CHIP bit
+//planes: background, first plane, second plane and both. Plain CHIP-8 programs only draw on the
+//first plane, so they only use the first two entries.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+//Levels of each channel in the 6x6x6 colour cube of 256 colour terminals.
+const CUBE_LEVELS: [u8; 6] = [0x00, 0x5F, 0x87, 0xAF, 0xD7, 0xFF];
+//First colour of the cube, and of the 24 step gray ramp after it.
+const CUBE_START: i16 = 16;
+const GRAY_START: i16 = 232;
+
+//The 8 basic terminal colours, as xterm shows them.
+const BASIC_COLORS: [Rgb; 8] = [
+    Rgb::new(0x00, 0x00, 0x00),
+    Rgb::new(0xCD, 0x00, 0x00),
+    Rgb::new(0x00, 0xCD, 0x00),
+    Rgb::new(0xCD, 0xCD, 0x00),
+    Rgb::new(0x00, 0x00, 0xEE),
+    Rgb::new(0xCD, 0x00, 0xCD),
+    Rgb::new(0x00, 0xCD, 0xCD),
+    Rgb::new(0xE5, 0xE5, 0xE5),
+];
+
+impl Rgb {
+    pub const fn new(red: u8, green: u8, blue: u8) -> Rgb {
+        Rgb { red, green, blue }
+    }
+
+    //Parses '#rrggbb', the # being optional.
+    pub fn parse(text: &str) -> Result<Rgb, String> {
+        let hex = text.strip_prefix('#').unwrap_or(text);
+        let value = u32::from_str_radix(hex, 16)
+            .ok()
+            .filter(|_| hex.len() == 6)
+            .ok_or_else(|| format!("'{text}' is not a colour, expected #rrggbb"))?;
+        Ok(Rgb::new(
+            (value >> 16) as u8,
+            (value >> 8) as u8,
+            value as u8,
+        ))
+    }
+
+    //Mix of two colours, weight out of 256 going to this one.
+    pub fn mix(self, other: Rgb, weight: u16) -> Rgb {
+        let channel = |a: u8, b: u8| ((a as u16 * weight + b as u16 * (256 - weight)) / 256) as u8;
+        Rgb::new(
+            channel(self.red, other.red),
+            channel(self.green, other.green),
+            channel(self.blue, other.blue),
+        )
+    }
+
+    fn distance(self, other: Rgb) -> u32 {
+        let channel = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+        channel(self.red, other.red)
+            + channel(self.green, other.green)
+            + channel(self.blue, other.blue)
+    }
+
+    //Closest colour of a 256 colour terminal, from the colour cube or the gray ramp.
+    pub fn to_256_colors(self) -> i16 {
+        let level = |channel: u8| {
+            (0..CUBE_LEVELS.len())
+                .min_by_key(|level| (CUBE_LEVELS[*level] as i32 - channel as i32).abs())
+                .unwrap_or(0)
+        };
+        let (red, green, blue) = (level(self.red), level(self.green), level(self.blue));
+        let cube = Rgb::new(CUBE_LEVELS[red], CUBE_LEVELS[green], CUBE_LEVELS[blue]);
+
+        let average = (self.red as u32 + self.green as u32 + self.blue as u32) / 3;
+        let step = (average.saturating_sub(3) / 10).min(23) as u8;
+        let gray_level = 8 + step * 10;
+        let gray = Rgb::new(gray_level, gray_level, gray_level);
+
+        if self.distance(gray) < self.distance(cube) {
+            GRAY_START + step as i16
+        } else {
+            CUBE_START + (red * 36 + green * 6 + blue) as i16
+        }
+    }
+
+    //Closest of the 8 basic terminal colours.
+    pub fn to_8_colors(self) -> i16 {
+        (0..BASIC_COLORS.len())
+            .min_by_key(|color| self.distance(BASIC_COLORS[*color]))
+            .unwrap_or(0) as i16
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    pub name: &'static str,
+    pub palette: [Rgb; 4],
+}
+
+pub const THEMES: [Theme; 4] = [
+    Theme {
+        name: "classic",
+        palette: [
+            Rgb::new(0x00, 0x00, 0x00),
+            Rgb::new(0xFF, 0xFF, 0xFF),
+            Rgb::new(0xAA, 0xAA, 0xAA),
+            Rgb::new(0x55, 0x55, 0x55),
+        ],
+    },
+    Theme {
+        name: "amber",
+        palette: [
+            Rgb::new(0x1A, 0x0F, 0x00),
+            Rgb::new(0xFF, 0xB0, 0x00),
+            Rgb::new(0xB3, 0x6B, 0x00),
+            Rgb::new(0x66, 0x3D, 0x00),
+        ],
+    },
+    Theme {
+        name: "green",
+        palette: [
+            Rgb::new(0x0A, 0x14, 0x0A),
+            Rgb::new(0x33, 0xFF, 0x66),
+            Rgb::new(0x1F, 0xA0, 0x40),
+            Rgb::new(0x0F, 0x50, 0x20),
+        ],
+    },
+    //Dark pixels on a pale green LCD
+    Theme {
+        name: "lcd",
+        palette: [
+            Rgb::new(0x9B, 0xBC, 0x0F),
+            Rgb::new(0x0F, 0x38, 0x0F),
+            Rgb::new(0x30, 0x62, 0x30),
+            Rgb::new(0x5C, 0x7F, 0x1F),
+        ],
+    },
+];
+
+impl Default for Theme {
+    fn default() -> Theme {
+        THEMES[0]
+    }
+}
+
+impl Theme {
+    pub fn find(name: &str) -> Option<Theme> {
+        THEMES.into_iter().find(|theme| theme.name == name)
+    }
+}
@@ -1,7 +1,23 @@
+pub mod decoder;
 mod instruction_set;
 pub mod utils;
 
-use core::panic;
+use decoder::Instruction;
+
+//Size of the display in pixels.
+pub const SCREEN_WIDTH: usize = 64;
+pub const SCREEN_HEIGHT: usize = 32;
+
+//Return addresses the stack holds, so how deep subroutine calls can nest.
+pub const STACK_SIZE: usize = 16;
+
+//Raised when a CALL is executed with a full stack or a RET with an empty one.
+//The interpreter halts until the stack pointer is fixed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackFault {
+    Overflow,
+    Underflow,
+}
 
 pub struct Cpu {
     gp_registers: [u8; 16], //General purpose registers. V16 or VF should not be used by programs.
@@ -12,14 +28,21 @@ pub struct Cpu {
     program_counter: u16,
 
     stack_pointer: u8,
-    stack: [u16; 16],
+    stack: [u16; STACK_SIZE],
 
     memory: [u8; 4096],
-    screen: [u8; 64 * 32],
+    screen: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
+    //Rows of the screen changed since the client last drew it
+    dirty_rows: [bool; SCREEN_HEIGHT],
 
     keyboard: [bool; 16],
 
     awaited_keypress: Option<u8>,
+
+    stack_fault: Option<StackFault>,
+
+    //Opcode that means nothing to the interpreter, which halts on it like on a stack fault.
+    unknown_opcode: Option<u16>,
 }
 
 impl Cpu {
@@ -33,7 +56,9 @@ impl Cpu {
             memory[i + 0x200] = byte;
         }
 
-        memory[0x1FF] = 3;
+        //The Timendus test suite reads the platform to test from here, 1 being CHIP-8. It is set
+        //once so running never writes memory the program did not ask to.
+        memory[0x1FF] = 1;
 
         Cpu {
             gp_registers: [0; 16],
@@ -43,22 +68,42 @@ impl Cpu {
             program_counter: 0x200,
 
             stack_pointer: 0x0,
-            stack: [0; 16],
+            stack: [0; STACK_SIZE],
 
             memory,
 
-            screen: [0; 64 * 32],
+            screen: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            //The whole screen is new to the client
+            dirty_rows: [true; SCREEN_HEIGHT],
 
             keyboard: [false; 16],
 
             awaited_keypress: None,
+
+            stack_fault: None,
+
+            unknown_opcode: None,
         }
     }
 
-    pub fn get_screen(&self) -> &[u8; 64 * 32] {
+    pub fn get_screen(&self) -> &[u8; SCREEN_WIDTH * SCREEN_HEIGHT] {
         &self.screen
     }
 
+    pub fn get_dirty_rows(&self) -> &[bool; SCREEN_HEIGHT] {
+        &self.dirty_rows
+    }
+
+    //True when the screen changed since the last call to clear_dirty_rows.
+    pub fn is_frame_changed(&self) -> bool {
+        self.dirty_rows.contains(&true)
+    }
+
+    //Called by clients once they have drawn the screen.
+    pub fn clear_dirty_rows(&mut self) {
+        self.dirty_rows.fill(false);
+    }
+
     pub fn get_gp_registers(&self) -> &[u8; 16] {
         &self.gp_registers
     }
@@ -78,21 +123,49 @@ impl Cpu {
         self.stack_pointer
     }
 
-    pub fn get_stack(&self) -> &[u16; 16] {
+    pub fn get_stack(&self) -> &[u16; STACK_SIZE] {
         &self.stack
     }
 
+    pub fn get_stack_fault(&self) -> Option<StackFault> {
+        self.stack_fault
+    }
+
+    pub fn get_unknown_opcode(&self) -> Option<u16> {
+        self.unknown_opcode
+    }
+
+    pub fn get_keyboard(&self) -> &[bool; 16] {
+        &self.keyboard
+    }
+
+    //True while clock() would not execute anything: waiting on FX0A or halted on a fault.
+    pub fn is_blocked(&self) -> bool {
+        self.awaited_keypress.is_some() || self.is_faulted()
+    }
+
+    //True while halted on a stack fault or an unknown opcode.
+    pub fn is_faulted(&self) -> bool {
+        self.stack_fault.is_some() || self.unknown_opcode.is_some()
+    }
+
     pub fn get_program_counter(&self) -> u16 {
         self.program_counter
     }
 
+    pub fn get_memory(&self) -> &[u8; 4096] {
+        &self.memory
+    }
+
     pub fn get_current_instruction(&self) -> u16 {
-        //Get first part
-        let mut instruction: u16 = (self.memory[self.program_counter as usize] as u16) << 8;
-        instruction |= self.memory[(self.program_counter + 1) as usize] as u16;
-        //Get second part
-        // (self.memory[self.program_counter as usize] <<8)
-        instruction
+        self.get_instruction_at(self.program_counter)
+    }
+
+    //Reads the opcode stored at address, wrapping around the end of memory.
+    pub fn get_instruction_at(&self, address: u16) -> u16 {
+        let high = self.memory[(address & 0xFFF) as usize] as u16;
+        let low = self.memory[(address.wrapping_add(1) & 0xFFF) as usize] as u16;
+        (high << 8) | low
     }
 
     pub fn set_key(&mut self, key: u8) {
@@ -104,115 +177,309 @@ impl Cpu {
         }
     }
 
+    pub fn release_key(&mut self, key: u8) {
+        self.keyboard[key as usize] = false;
+    }
+
+    //Setters used by the debugger to poke at the machine state while paused.
+    //Values that would make the interpreter index out of bounds are rejected.
+    pub fn set_gp_register(&mut self, register: u8, value: u8) -> Result<(), &'static str> {
+        if register > 0xF {
+            return Err("register must be between V0 and VF");
+        }
+        self.gp_registers[register as usize] = value;
+        Ok(())
+    }
+
+    pub fn set_i_register(&mut self, value: u16) -> Result<(), &'static str> {
+        if value > 0xFFF {
+            return Err("I must point inside memory (0x000-0xFFF)");
+        }
+        self.i_register = value;
+        Ok(())
+    }
+
+    pub fn set_t_register(&mut self, value: u8) {
+        self.t_register = value;
+    }
+
+    pub fn set_s_register(&mut self, value: u8) {
+        self.s_register = value;
+    }
+
+    pub fn set_program_counter(&mut self, address: u16) -> Result<(), &'static str> {
+        if address > 0xFFE {
+            return Err("program counter must be between 0x000 and 0xFFE");
+        }
+        self.program_counter = address;
+        //Moving off an unknown opcode resumes, clock() checks the new one
+        self.unknown_opcode = None;
+        Ok(())
+    }
+
+    pub fn set_stack_pointer(&mut self, value: u8) -> Result<(), &'static str> {
+        if value as usize > self.stack.len() {
+            return Err("stack pointer must be between 0 and 16");
+        }
+        self.stack_pointer = value;
+        self.stack_fault = None;
+        Ok(())
+    }
+
+    pub fn set_stack_entry(&mut self, index: u8, address: u16) -> Result<(), &'static str> {
+        if index as usize >= self.stack.len() {
+            return Err("stack entry must be between 0 and 15");
+        }
+        if address > 0xFFE {
+            return Err("return address must be between 0x000 and 0xFFE");
+        }
+        self.stack[index as usize] = address;
+        Ok(())
+    }
+
+    pub fn write_memory(&mut self, address: u16, value: u8) -> Result<(), &'static str> {
+        if address > 0xFFF {
+            return Err("address must be between 0x000 and 0xFFF");
+        }
+        self.memory[address as usize] = value;
+        //The unknown opcode may have just been patched
+        self.unknown_opcode = None;
+        Ok(())
+    }
+
+    pub fn set_pixel(&mut self, x: u8, y: u8, lit: bool) -> Result<(), &'static str> {
+        if x as usize >= SCREEN_WIDTH || y as usize >= SCREEN_HEIGHT {
+            return Err("pixel must be inside the 64x32 screen");
+        }
+        self.screen[x as usize + (y as usize * SCREEN_WIDTH)] = lit as u8;
+        self.dirty_rows[y as usize] = true;
+        Ok(())
+    }
+
     //TODO: make this return Result<(), &str>. So by analyzing it from outside we can know if its blocked on input or not.
     pub fn clock(&mut self) {
-        self.memory[0x1FF] = 1;
-
-        //If we are in blocking mode just return, change this.
-        if self.awaited_keypress.is_some() {
+        //If we are in blocking mode or the stack is broken just return, change this.
+        if self.is_blocked() {
             return;
         }
 
         //Fetch
-        let opcode_1: u8 = self.memory[self.program_counter as usize] >> 4;
-        let opcode_2: u8 = (self.memory[self.program_counter as usize] << 4) >> 4;
-        let opcode_3: u8 = self.memory[(self.program_counter + 1) as usize] >> 4;
-        let opcode_4: u8 = (self.memory[(self.program_counter + 1) as usize] << 4) >> 4;
+        let instruction = decoder::decode(self.get_current_instruction());
 
         self.program_counter += 2;
 
         //Decode and execute
-        match opcode_1 {
-            0 => match (opcode_2, opcode_4) {
-                (0x1..=0x8, 0x1..=0x8) => instruction_set::call(
-                    self,
-                    utils::opcode_to_address(opcode_2, opcode_3, opcode_4),
-                ),
-                (0x0, 0xE) => instruction_set::return_from_subroutine(self),
-                (0x0, 0x0) => instruction_set::clear_screen(self),
-                _ => panic!("unsupported operation!"),
-            },
-
-            0x1 => {
-                instruction_set::goto(self, utils::opcode_to_address(opcode_2, opcode_3, opcode_4))
-            }
-            0x2 => instruction_set::call_sub(
-                self,
-                utils::opcode_to_address(opcode_2, opcode_3, opcode_4),
-            ),
-            0x3 => instruction_set::skip_if_register_equals(
-                self,
-                opcode_2,
-                utils::opcode_to_value(opcode_3, opcode_4),
-            ),
-            0x4 => instruction_set::skip_if_register_nequals(
-                self,
-                opcode_2,
-                utils::opcode_to_value(opcode_3, opcode_4),
-            ),
-            0x5 => instruction_set::skip_if_register_equals_register(self, opcode_2, opcode_3),
-            0x6 => instruction_set::set_register_to_value(
-                self,
-                opcode_2,
-                utils::opcode_to_value(opcode_3, opcode_4),
-            ),
-            0x7 => instruction_set::add_to_register(
-                self,
-                opcode_2,
-                utils::opcode_to_value(opcode_3, opcode_4),
-            ),
-            0x8 => match opcode_4 {
-                0 => instruction_set::set_reigster_as_register(self, opcode_2, opcode_3),
-                1 => instruction_set::set_register_as_bitwise_or_register(self, opcode_2, opcode_3),
-                2 => {
-                    instruction_set::set_register_as_bitwise_and_register(self, opcode_2, opcode_3)
-                }
-                3 => {
-                    instruction_set::set_register_as_bitwise_xor_register(self, opcode_2, opcode_3)
-                }
-                4 => instruction_set::set_register_as_addition(self, opcode_2, opcode_3),
-                5 => instruction_set::set_register_as_subtraction(self, opcode_2, opcode_3),
-                6 => instruction_set::set_register_as_shift_right(self, opcode_2),
-                7 => instruction_set::set_register_as_b_sub_a(self, opcode_2, opcode_3),
-                14 => instruction_set::set_register_as_shift_left(self, opcode_2),
-                _ => panic!("unsupported operation!"),
-            },
-            0x9 => instruction_set::skip_if_register_nequal_register(self, opcode_2, opcode_3),
-            0xA => instruction_set::set_i_to_addr(
-                self,
-                utils::opcode_to_address(opcode_2, opcode_3, opcode_4),
-            ),
-            0xB => instruction_set::set_i_to_addr_plus_v0(
-                self,
-                utils::opcode_to_address(opcode_2, opcode_3, opcode_4),
-            ),
-            0xC => instruction_set::set_register_as_bitwise_and_with_random(
-                self,
-                opcode_2,
-                utils::opcode_to_value(opcode_3, opcode_4),
-            ),
-            0xD => instruction_set::draw(self, opcode_2, opcode_3, opcode_4),
-            0xE => match (opcode_3, opcode_4) {
-                (0x9, 0xE) => instruction_set::skip_if_key_pressed(self, opcode_2),
-                (0xA, 0x1) => instruction_set::skip_if_key_npressed(self, opcode_2),
-                _ => panic!(
-                    "unsupported operation! {:#x}{:#x}{:#x}{:#x}",
-                    opcode_1, opcode_2, opcode_3, opcode_4
-                ),
-            },
-            0xF => match (opcode_3, opcode_4) {
-                (0x0, 0x7) => instruction_set::set_register_to_tregister(self, opcode_2),
-                (0x0, 0xA) => instruction_set::set_register_to_key(self, opcode_2),
-                (0x1, 0x5) => instruction_set::set_tregister_to_register(self, opcode_2),
-                (0x1, 0x8) => instruction_set::set_sregister_to_register(self, opcode_2),
-                (0x1, 0xE) => instruction_set::add_register_to_iregister(self, opcode_2),
-                (0x2, 0x9) => instruction_set::set_iregister_to_sprite(self, opcode_2),
-                (0x3, 0x3) => instruction_set::register_to_decimal(self, opcode_2),
-                (0x5, 0x5) => instruction_set::register_dump_to_memory(self, opcode_2),
-                (0x6, 0x5) => instruction_set::register_load_from_memory(self, opcode_2),
-                _ => panic!("unsupported operation!"),
-            },
-            _ => panic!("unsupported operation!"),
+        match instruction {
+            Instruction::Call(address) => instruction_set::call(self, address),
+            Instruction::ClearScreen => instruction_set::clear_screen(self),
+            Instruction::ReturnFromSubroutine => instruction_set::return_from_subroutine(self),
+            Instruction::Goto(address) => instruction_set::goto(self, address),
+            Instruction::CallSub(address) => instruction_set::call_sub(self, address),
+            Instruction::SkipIfRegisterEquals(x, value) => {
+                instruction_set::skip_if_register_equals(self, x, value)
+            }
+            Instruction::SkipIfRegisterNequals(x, value) => {
+                instruction_set::skip_if_register_nequals(self, x, value)
+            }
+            Instruction::SkipIfRegisterEqualsRegister(x, y) => {
+                instruction_set::skip_if_register_equals_register(self, x, y)
+            }
+            Instruction::SetRegisterToValue(x, value) => {
+                instruction_set::set_register_to_value(self, x, value)
+            }
+            Instruction::AddToRegister(x, value) => {
+                instruction_set::add_to_register(self, x, value)
+            }
+            Instruction::SetRegisterAsRegister(x, y) => {
+                instruction_set::set_reigster_as_register(self, x, y)
+            }
+            Instruction::SetRegisterAsBitwiseOrRegister(x, y) => {
+                instruction_set::set_register_as_bitwise_or_register(self, x, y)
+            }
+            Instruction::SetRegisterAsBitwiseAndRegister(x, y) => {
+                instruction_set::set_register_as_bitwise_and_register(self, x, y)
+            }
+            Instruction::SetRegisterAsBitwiseXorRegister(x, y) => {
+                instruction_set::set_register_as_bitwise_xor_register(self, x, y)
+            }
+            Instruction::SetRegisterAsAddition(x, y) => {
+                instruction_set::set_register_as_addition(self, x, y)
+            }
+            Instruction::SetRegisterAsSubtraction(x, y) => {
+                instruction_set::set_register_as_subtraction(self, x, y)
+            }
+            Instruction::SetRegisterAsShiftRight(x, _) => {
+                instruction_set::set_register_as_shift_right(self, x)
+            }
+            Instruction::SetRegisterAsBSubA(x, y) => {
+                instruction_set::set_register_as_b_sub_a(self, x, y)
+            }
+            Instruction::SetRegisterAsShiftLeft(x, _) => {
+                instruction_set::set_register_as_shift_left(self, x)
+            }
+            Instruction::SkipIfRegisterNequalRegister(x, y) => {
+                instruction_set::skip_if_register_nequal_register(self, x, y)
+            }
+            Instruction::SetIToAddr(address) => instruction_set::set_i_to_addr(self, address),
+            Instruction::SetIToAddrPlusV0(address) => {
+                instruction_set::set_i_to_addr_plus_v0(self, address)
+            }
+            Instruction::SetRegisterAsBitwiseAndWithRandom(x, value) => {
+                instruction_set::set_register_as_bitwise_and_with_random(self, x, value)
+            }
+            Instruction::Draw(x, y, height) => instruction_set::draw(self, x, y, height),
+            Instruction::SkipIfKeyPressed(x) => instruction_set::skip_if_key_pressed(self, x),
+            Instruction::SkipIfKeyNpressed(x) => instruction_set::skip_if_key_npressed(self, x),
+            Instruction::SetRegisterToTregister(x) => {
+                instruction_set::set_register_to_tregister(self, x)
+            }
+            Instruction::SetRegisterToKey(x) => instruction_set::set_register_to_key(self, x),
+            Instruction::SetTregisterToRegister(x) => {
+                instruction_set::set_tregister_to_register(self, x)
+            }
+            Instruction::SetSregisterToRegister(x) => {
+                instruction_set::set_sregister_to_register(self, x)
+            }
+            Instruction::AddRegisterToIregister(x) => {
+                instruction_set::add_register_to_iregister(self, x)
+            }
+            Instruction::SetIregisterToSprite(x) => {
+                instruction_set::set_iregister_to_sprite(self, x)
+            }
+            Instruction::RegisterToDecimal(x) => instruction_set::register_to_decimal(self, x),
+            Instruction::RegisterDumpToMemory(x) => {
+                instruction_set::register_dump_to_memory(self, x)
+            }
+            Instruction::RegisterLoadFromMemory(x) => {
+                instruction_set::register_load_from_memory(self, x)
+            }
+            Instruction::Unknown(opcode) => {
+                //Leave PC on the faulting instruction
+                self.unknown_opcode = Some(opcode);
+                self.program_counter -= 2;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dirty_rows(cpu: &Cpu) -> Vec<usize> {
+        (0..SCREEN_HEIGHT)
+            .filter(|&row| cpu.get_dirty_rows()[row])
+            .collect()
+    }
+
+    #[test]
+    fn marks_only_changed_rows_dirty() {
+        //Draws the 5 rows of the 0 font sprite at (0, 4), then 3 empty rows from 0x050
+        let mut cpu = Cpu::new(vec![
+            0x60, 0x00, 0x61, 0x04, 0xA0, 0x00, 0xD0, 0x15, 0xA0, 0x50, 0xD0, 0x13, 0x00, 0xE0,
+        ]);
+        assert_eq!(dirty_rows(&cpu).len(), SCREEN_HEIGHT);
+        cpu.clear_dirty_rows();
+        assert!(!cpu.is_frame_changed());
+
+        for _ in 0..4 {
+            cpu.clock();
         }
+        assert_eq!(dirty_rows(&cpu), [4, 5, 6, 7, 8]);
+        cpu.clear_dirty_rows();
+
+        //Nothing changes when a sprite has no lit pixels
+        cpu.clock();
+        cpu.clock();
+        assert!(!cpu.is_frame_changed());
+
+        cpu.set_pixel(3, 20, true).unwrap();
+        assert_eq!(dirty_rows(&cpu), [20]);
+        cpu.clear_dirty_rows();
+
+        cpu.clock();
+        assert_eq!(dirty_rows(&cpu).len(), SCREEN_HEIGHT);
+    }
+
+    #[test]
+    fn halts_on_unknown_opcodes() {
+        let mut cpu = Cpu::new(vec![0x61, 0x01, 0x81, 0x28, 0x71, 0x01]);
+        cpu.clock();
+        cpu.clock();
+        assert_eq!(cpu.get_unknown_opcode(), Some(0x8128));
+        assert_eq!(cpu.get_program_counter(), 0x202);
+        assert!(cpu.is_faulted());
+
+        //Nothing runs until the opcode is fixed
+        cpu.clock();
+        assert_eq!(cpu.get_program_counter(), 0x202);
+        assert_eq!(cpu.get_gp_registers()[1], 1);
+
+        cpu.write_memory(0x203, 0x21).unwrap();
+        assert!(!cpu.is_faulted());
+        cpu.clock();
+        cpu.clock();
+        assert_eq!(cpu.get_gp_registers()[1], 2);
+
+        //Or skipped
+        let mut cpu = Cpu::new(vec![0xE0, 0x00, 0x71, 0x01]);
+        cpu.clock();
+        assert_eq!(cpu.get_unknown_opcode(), Some(0xE000));
+        cpu.set_program_counter(0x202).unwrap();
+        cpu.clock();
+        assert_eq!(cpu.get_gp_registers()[1], 1);
+    }
+
+    #[test]
+    fn sets_state_inside_bounds() {
+        let mut cpu = Cpu::new(Vec::new());
+        assert!(cpu.set_gp_register(0xF, 0xAB).is_ok());
+        assert!(cpu.set_gp_register(0x10, 1).is_err());
+        assert_eq!(cpu.get_gp_registers()[0xF], 0xAB);
+
+        assert!(cpu.set_i_register(0xFFF).is_ok());
+        assert!(cpu.set_i_register(0x1000).is_err());
+        assert_eq!(cpu.get_i_register(), 0xFFF);
+
+        cpu.set_t_register(10);
+        cpu.set_s_register(20);
+        assert_eq!((cpu.get_t_register(), cpu.get_s_register()), (10, 20));
+
+        assert!(cpu.set_program_counter(0xFFE).is_ok());
+        assert!(cpu.set_program_counter(0xFFF).is_err());
+        assert_eq!(cpu.get_program_counter(), 0xFFE);
+
+        assert!(cpu.set_stack_pointer(16).is_ok());
+        assert!(cpu.set_stack_pointer(17).is_err());
+        assert_eq!(cpu.get_stack_pointer(), 16);
+
+        assert!(cpu.set_stack_entry(15, 0x202).is_ok());
+        assert!(cpu.set_stack_entry(16, 0x202).is_err());
+        assert!(cpu.set_stack_entry(0, 0xFFF).is_err());
+        assert_eq!(cpu.get_stack()[15], 0x202);
+
+        assert!(cpu.write_memory(0xFFF, 0x12).is_ok());
+        assert!(cpu.write_memory(0x1000, 0x12).is_err());
+        assert_eq!(cpu.get_memory()[0xFFF], 0x12);
+
+        assert!(cpu.set_pixel(63, 31, true).is_ok());
+        assert!(cpu.set_pixel(64, 0, true).is_err());
+        assert!(cpu.set_pixel(0, 32, true).is_err());
+        assert_eq!(cpu.get_screen()[SCREEN_WIDTH * SCREEN_HEIGHT - 1], 1);
+    }
+
+    #[test]
+    fn setting_the_stack_pointer_clears_stack_faults() {
+        //RET with an empty stack
+        let mut cpu = Cpu::new(vec![0x00, 0xEE]);
+        cpu.clock();
+        assert_eq!(cpu.get_stack_fault(), Some(StackFault::Underflow));
+        assert_eq!(cpu.get_program_counter(), 0x200);
+
+        cpu.set_stack_entry(0, 0x300).unwrap();
+        cpu.set_stack_pointer(1).unwrap();
+        assert_eq!(cpu.get_stack_fault(), None);
+        cpu.clock();
+        assert_eq!(cpu.get_program_counter(), 0x300);
     }
 }
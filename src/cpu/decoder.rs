@@ -0,0 +1,380 @@
+use std::fmt;
+use std::str::FromStr;
+
+//Decoded form of a single opcode. Variants mirror the functions in instruction_set so the
+//interpreter, the debugger and the tools all agree on what an opcode means.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Call(u16),                                 //Opcode: 0NNN
+    ClearScreen,                               //Opcode: 00E0
+    ReturnFromSubroutine,                      //Opcode: 00EE
+    Goto(u16),                                 //Opcode: 1NNN
+    CallSub(u16),                              //Opcode: 2NNN
+    SkipIfRegisterEquals(u8, u8),              //Opcode: 3XNN
+    SkipIfRegisterNequals(u8, u8),             //Opcode: 4XNN
+    SkipIfRegisterEqualsRegister(u8, u8),      //Opcode: 5XY0
+    SetRegisterToValue(u8, u8),                //Opcode: 6XNN
+    AddToRegister(u8, u8),                     //Opcode: 7XNN
+    SetRegisterAsRegister(u8, u8),             //Opcode: 8XY0
+    SetRegisterAsBitwiseOrRegister(u8, u8),    //Opcode: 8XY1
+    SetRegisterAsBitwiseAndRegister(u8, u8),   //Opcode: 8XY2
+    SetRegisterAsBitwiseXorRegister(u8, u8),   //Opcode: 8XY3
+    SetRegisterAsAddition(u8, u8),             //Opcode: 8XY4
+    SetRegisterAsSubtraction(u8, u8),          //Opcode: 8XY5
+    SetRegisterAsShiftRight(u8, u8),           //Opcode: 8XY6
+    SetRegisterAsBSubA(u8, u8),                //Opcode: 8XY7
+    SetRegisterAsShiftLeft(u8, u8),            //Opcode: 8XYE
+    SkipIfRegisterNequalRegister(u8, u8),      //Opcode: 9XY0
+    SetIToAddr(u16),                           //Opcode: ANNN
+    SetIToAddrPlusV0(u16),                     //Opcode: BNNN
+    SetRegisterAsBitwiseAndWithRandom(u8, u8), //Opcode: CXNN
+    Draw(u8, u8, u8),                          //Opcode: DXYN
+    SkipIfKeyPressed(u8),                      //Opcode: EX9E
+    SkipIfKeyNpressed(u8),                     //Opcode: EXA1
+    SetRegisterToTregister(u8),                //Opcode: FX07
+    SetRegisterToKey(u8),                      //Opcode: FX0A
+    SetTregisterToRegister(u8),                //Opcode: FX15
+    SetSregisterToRegister(u8),                //Opcode: FX18
+    AddRegisterToIregister(u8),                //Opcode: FX1E
+    SetIregisterToSprite(u8),                  //Opcode: FX29
+    RegisterToDecimal(u8),                     //Opcode: FX33
+    RegisterDumpToMemory(u8),                  //Opcode: FX55
+    RegisterLoadFromMemory(u8),                //Opcode: FX65
+    Unknown(u16),
+}
+
+pub fn decode(opcode: u16) -> Instruction {
+    let opcode_1 = (opcode >> 12) as u8;
+    let opcode_2 = ((opcode >> 8) & 0xF) as u8;
+    let opcode_3 = ((opcode >> 4) & 0xF) as u8;
+    let opcode_4 = (opcode & 0xF) as u8;
+
+    let address = opcode & 0xFFF;
+    let value = (opcode & 0xFF) as u8;
+
+    match opcode_1 {
+        0x0 => match address {
+            0x0E0 => Instruction::ClearScreen,
+            0x0EE => Instruction::ReturnFromSubroutine,
+            _ => Instruction::Call(address),
+        },
+        0x1 => Instruction::Goto(address),
+        0x2 => Instruction::CallSub(address),
+        0x3 => Instruction::SkipIfRegisterEquals(opcode_2, value),
+        0x4 => Instruction::SkipIfRegisterNequals(opcode_2, value),
+        0x5 if opcode_4 == 0 => Instruction::SkipIfRegisterEqualsRegister(opcode_2, opcode_3),
+        0x6 => Instruction::SetRegisterToValue(opcode_2, value),
+        0x7 => Instruction::AddToRegister(opcode_2, value),
+        0x8 => match opcode_4 {
+            0x0 => Instruction::SetRegisterAsRegister(opcode_2, opcode_3),
+            0x1 => Instruction::SetRegisterAsBitwiseOrRegister(opcode_2, opcode_3),
+            0x2 => Instruction::SetRegisterAsBitwiseAndRegister(opcode_2, opcode_3),
+            0x3 => Instruction::SetRegisterAsBitwiseXorRegister(opcode_2, opcode_3),
+            0x4 => Instruction::SetRegisterAsAddition(opcode_2, opcode_3),
+            0x5 => Instruction::SetRegisterAsSubtraction(opcode_2, opcode_3),
+            0x6 => Instruction::SetRegisterAsShiftRight(opcode_2, opcode_3),
+            0x7 => Instruction::SetRegisterAsBSubA(opcode_2, opcode_3),
+            0xE => Instruction::SetRegisterAsShiftLeft(opcode_2, opcode_3),
+            _ => Instruction::Unknown(opcode),
+        },
+        0x9 if opcode_4 == 0 => Instruction::SkipIfRegisterNequalRegister(opcode_2, opcode_3),
+        0xA => Instruction::SetIToAddr(address),
+        0xB => Instruction::SetIToAddrPlusV0(address),
+        0xC => Instruction::SetRegisterAsBitwiseAndWithRandom(opcode_2, value),
+        0xD => Instruction::Draw(opcode_2, opcode_3, opcode_4),
+        0xE => match value {
+            0x9E => Instruction::SkipIfKeyPressed(opcode_2),
+            0xA1 => Instruction::SkipIfKeyNpressed(opcode_2),
+            _ => Instruction::Unknown(opcode),
+        },
+        0xF => match value {
+            0x07 => Instruction::SetRegisterToTregister(opcode_2),
+            0x0A => Instruction::SetRegisterToKey(opcode_2),
+            0x15 => Instruction::SetTregisterToRegister(opcode_2),
+            0x18 => Instruction::SetSregisterToRegister(opcode_2),
+            0x1E => Instruction::AddRegisterToIregister(opcode_2),
+            0x29 => Instruction::SetIregisterToSprite(opcode_2),
+            0x33 => Instruction::RegisterToDecimal(opcode_2),
+            0x55 => Instruction::RegisterDumpToMemory(opcode_2),
+            0x65 => Instruction::RegisterLoadFromMemory(opcode_2),
+            _ => Instruction::Unknown(opcode),
+        },
+        _ => Instruction::Unknown(opcode),
+    }
+}
+
+//Machines a program can target. Each one runs everything the previous one does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Platform {
+    Chip8,
+    Schip,
+    XoChip,
+}
+
+impl Platform {
+    //Highest address programs for this platform can use.
+    pub fn memory_end(&self) -> u32 {
+        match self {
+            Platform::Chip8 | Platform::Schip => 0xFFF,
+            Platform::XoChip => 0xFFFF,
+        }
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Platform, String> {
+        match name {
+            "chip8" | "chip-8" => Ok(Platform::Chip8),
+            "schip" | "superchip" => Ok(Platform::Schip),
+            "xochip" | "xo-chip" => Ok(Platform::XoChip),
+            other => Err(format!("unknown platform '{other}'")),
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Platform::Chip8 => write!(f, "CHIP-8"),
+            Platform::Schip => write!(f, "SCHIP"),
+            Platform::XoChip => write!(f, "XO-CHIP"),
+        }
+    }
+}
+
+//Oldest platform that gives opcode a meaning. Opcodes that mean nothing anywhere, and the
+//0NNN machine code calls, count as CHIP-8. So does DXY0, which draws nothing on CHIP-8 and a
+//16x16 sprite on SCHIP.
+pub fn required_platform(opcode: u16) -> Platform {
+    match opcode {
+        0x00C0..=0x00CF | 0x00FB..=0x00FF => Platform::Schip, //Scrolling, exit, lores/hires
+        0x00D0..=0x00DF => Platform::XoChip,                  //Scroll up
+        0xF000 | 0xF002 => Platform::XoChip,                  //i := long, audio
+        _ => match (opcode >> 12, opcode & 0xF, opcode & 0xFF) {
+            (0x5, 0x2 | 0x3, _) => Platform::XoChip, //Register range save/load
+            (0xF, _, 0x30 | 0x75 | 0x85) => Platform::Schip,
+            (0xF, _, 0x01 | 0x3A) => Platform::XoChip, //Plane select, pitch
+            _ => Platform::Chip8,
+        },
+    }
+}
+
+impl Instruction {
+    //Address the instruction points at, if any. Used to follow jumps, calls and I loads.
+    pub fn target_address(&self) -> Option<u16> {
+        match *self {
+            Instruction::Call(address)
+            | Instruction::Goto(address)
+            | Instruction::CallSub(address)
+            | Instruction::SetIToAddr(address)
+            | Instruction::SetIToAddrPlusV0(address) => Some(address),
+            _ => None,
+        }
+    }
+
+    //Inverse of decode. Operands are masked to the width of their field.
+    pub fn encode(&self) -> u16 {
+        let xy = |prefix: u16, x: u8, y: u8, suffix: u16| {
+            prefix | ((x as u16 & 0xF) << 8) | ((y as u16 & 0xF) << 4) | suffix
+        };
+        let xnn = |prefix: u16, x: u8, value: u8| prefix | ((x as u16 & 0xF) << 8) | value as u16;
+        let x = |prefix: u16, x: u8, suffix: u16| prefix | ((x as u16 & 0xF) << 8) | suffix;
+
+        match *self {
+            Instruction::Call(address) => address & 0xFFF,
+            Instruction::ClearScreen => 0x00E0,
+            Instruction::ReturnFromSubroutine => 0x00EE,
+            Instruction::Goto(address) => 0x1000 | (address & 0xFFF),
+            Instruction::CallSub(address) => 0x2000 | (address & 0xFFF),
+            Instruction::SkipIfRegisterEquals(vx, value) => xnn(0x3000, vx, value),
+            Instruction::SkipIfRegisterNequals(vx, value) => xnn(0x4000, vx, value),
+            Instruction::SkipIfRegisterEqualsRegister(vx, vy) => xy(0x5000, vx, vy, 0x0),
+            Instruction::SetRegisterToValue(vx, value) => xnn(0x6000, vx, value),
+            Instruction::AddToRegister(vx, value) => xnn(0x7000, vx, value),
+            Instruction::SetRegisterAsRegister(vx, vy) => xy(0x8000, vx, vy, 0x0),
+            Instruction::SetRegisterAsBitwiseOrRegister(vx, vy) => xy(0x8000, vx, vy, 0x1),
+            Instruction::SetRegisterAsBitwiseAndRegister(vx, vy) => xy(0x8000, vx, vy, 0x2),
+            Instruction::SetRegisterAsBitwiseXorRegister(vx, vy) => xy(0x8000, vx, vy, 0x3),
+            Instruction::SetRegisterAsAddition(vx, vy) => xy(0x8000, vx, vy, 0x4),
+            Instruction::SetRegisterAsSubtraction(vx, vy) => xy(0x8000, vx, vy, 0x5),
+            Instruction::SetRegisterAsShiftRight(vx, vy) => xy(0x8000, vx, vy, 0x6),
+            Instruction::SetRegisterAsBSubA(vx, vy) => xy(0x8000, vx, vy, 0x7),
+            Instruction::SetRegisterAsShiftLeft(vx, vy) => xy(0x8000, vx, vy, 0xE),
+            Instruction::SkipIfRegisterNequalRegister(vx, vy) => xy(0x9000, vx, vy, 0x0),
+            Instruction::SetIToAddr(address) => 0xA000 | (address & 0xFFF),
+            Instruction::SetIToAddrPlusV0(address) => 0xB000 | (address & 0xFFF),
+            Instruction::SetRegisterAsBitwiseAndWithRandom(vx, value) => xnn(0xC000, vx, value),
+            Instruction::Draw(vx, vy, height) => xy(0xD000, vx, vy, height as u16 & 0xF),
+            Instruction::SkipIfKeyPressed(vx) => x(0xE000, vx, 0x9E),
+            Instruction::SkipIfKeyNpressed(vx) => x(0xE000, vx, 0xA1),
+            Instruction::SetRegisterToTregister(vx) => x(0xF000, vx, 0x07),
+            Instruction::SetRegisterToKey(vx) => x(0xF000, vx, 0x0A),
+            Instruction::SetTregisterToRegister(vx) => x(0xF000, vx, 0x15),
+            Instruction::SetSregisterToRegister(vx) => x(0xF000, vx, 0x18),
+            Instruction::AddRegisterToIregister(vx) => x(0xF000, vx, 0x1E),
+            Instruction::SetIregisterToSprite(vx) => x(0xF000, vx, 0x29),
+            Instruction::RegisterToDecimal(vx) => x(0xF000, vx, 0x33),
+            Instruction::RegisterDumpToMemory(vx) => x(0xF000, vx, 0x55),
+            Instruction::RegisterLoadFromMemory(vx) => x(0xF000, vx, 0x65),
+            Instruction::Unknown(opcode) => opcode,
+        }
+    }
+}
+
+//Mnemonics follow the syntax of Cowgod's technical reference.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Call(address) => write!(f, "SYS {:#05X}", address),
+            Instruction::ClearScreen => write!(f, "CLS"),
+            Instruction::ReturnFromSubroutine => write!(f, "RET"),
+            Instruction::Goto(address) => write!(f, "JP {:#05X}", address),
+            Instruction::CallSub(address) => write!(f, "CALL {:#05X}", address),
+            Instruction::SkipIfRegisterEquals(x, value) => write!(f, "SE V{:X}, {:#04X}", x, value),
+            Instruction::SkipIfRegisterNequals(x, value) => {
+                write!(f, "SNE V{:X}, {:#04X}", x, value)
+            }
+            Instruction::SkipIfRegisterEqualsRegister(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::SetRegisterToValue(x, value) => write!(f, "LD V{:X}, {:#04X}", x, value),
+            Instruction::AddToRegister(x, value) => write!(f, "ADD V{:X}, {:#04X}", x, value),
+            Instruction::SetRegisterAsRegister(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::SetRegisterAsBitwiseOrRegister(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::SetRegisterAsBitwiseAndRegister(x, y) => {
+                write!(f, "AND V{:X}, V{:X}", x, y)
+            }
+            Instruction::SetRegisterAsBitwiseXorRegister(x, y) => {
+                write!(f, "XOR V{:X}, V{:X}", x, y)
+            }
+            Instruction::SetRegisterAsAddition(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::SetRegisterAsSubtraction(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::SetRegisterAsShiftRight(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SetRegisterAsBSubA(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::SetRegisterAsShiftLeft(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipIfRegisterNequalRegister(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::SetIToAddr(address) => write!(f, "LD I, {:#05X}", address),
+            Instruction::SetIToAddrPlusV0(address) => write!(f, "JP V0, {:#05X}", address),
+            Instruction::SetRegisterAsBitwiseAndWithRandom(x, value) => {
+                write!(f, "RND V{:X}, {:#04X}", x, value)
+            }
+            Instruction::Draw(x, y, height) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, height),
+            Instruction::SkipIfKeyPressed(x) => write!(f, "SKP V{:X}", x),
+            Instruction::SkipIfKeyNpressed(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::SetRegisterToTregister(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::SetRegisterToKey(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::SetTregisterToRegister(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSregisterToRegister(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddRegisterToIregister(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::SetIregisterToSprite(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::RegisterToDecimal(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::RegisterDumpToMemory(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::RegisterLoadFromMemory(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::Unknown(opcode) => write!(f, "DW {:#06X}", opcode),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_every_opcode_back() {
+        for opcode in 0..=u16::MAX {
+            assert_eq!(decode(opcode).encode(), opcode, "{:#06X}", opcode);
+        }
+    }
+
+    #[test]
+    fn writes_mnemonics() {
+        let mnemonics = [
+            (0x0123, "SYS 0x123"),
+            (0x00E0, "CLS"),
+            (0x00EE, "RET"),
+            (0x1ABC, "JP 0xABC"),
+            (0x2204, "CALL 0x204"),
+            (0x3A0F, "SE VA, 0x0F"),
+            (0x4B10, "SNE VB, 0x10"),
+            (0x5120, "SE V1, V2"),
+            (0x6CFF, "LD VC, 0xFF"),
+            (0x7D01, "ADD VD, 0x01"),
+            (0x8120, "LD V1, V2"),
+            (0x8121, "OR V1, V2"),
+            (0x8122, "AND V1, V2"),
+            (0x8123, "XOR V1, V2"),
+            (0x8124, "ADD V1, V2"),
+            (0x8125, "SUB V1, V2"),
+            (0x8126, "SHR V1, V2"),
+            (0x8127, "SUBN V1, V2"),
+            (0x812E, "SHL V1, V2"),
+            (0x9340, "SNE V3, V4"),
+            (0xA050, "LD I, 0x050"),
+            (0xB300, "JP V0, 0x300"),
+            (0xC50F, "RND V5, 0x0F"),
+            (0xD12F, "DRW V1, V2, 15"),
+            (0xE29E, "SKP V2"),
+            (0xE3A1, "SKNP V3"),
+            (0xF407, "LD V4, DT"),
+            (0xF50A, "LD V5, K"),
+            (0xF615, "LD DT, V6"),
+            (0xF718, "LD ST, V7"),
+            (0xF81E, "ADD I, V8"),
+            (0xF929, "LD F, V9"),
+            (0xFA33, "LD B, VA"),
+            (0xFB55, "LD [I], VB"),
+            (0xFC65, "LD VC, [I]"),
+            (0x8128, "DW 0x8128"),
+            (0xE000, "DW 0xE000"),
+            (0x5121, "DW 0x5121"),
+        ];
+        for (opcode, mnemonic) in mnemonics {
+            assert_eq!(decode(opcode).to_string(), mnemonic);
+        }
+    }
+
+    #[test]
+    fn finds_the_oldest_platform() {
+        let platforms = [
+            (0x00E0, Platform::Chip8),
+            (0x0123, Platform::Chip8),
+            (0xD120, Platform::Chip8),
+            (0x8128, Platform::Chip8),
+            (0x00C4, Platform::Schip),
+            (0x00FB, Platform::Schip),
+            (0x00FF, Platform::Schip),
+            (0xF130, Platform::Schip),
+            (0xF275, Platform::Schip),
+            (0xF385, Platform::Schip),
+            (0x00D2, Platform::XoChip),
+            (0xF000, Platform::XoChip),
+            (0xF002, Platform::XoChip),
+            (0x5122, Platform::XoChip),
+            (0x5123, Platform::XoChip),
+            (0xF201, Platform::XoChip),
+            (0xF03A, Platform::XoChip),
+        ];
+        for (opcode, platform) in platforms {
+            assert_eq!(required_platform(opcode), platform, "{:#06X}", opcode);
+        }
+        assert!(Platform::Chip8 < Platform::Schip && Platform::Schip < Platform::XoChip);
+    }
+
+    #[test]
+    fn finds_target_addresses() {
+        let targets = [
+            (0x0123, Some(0x123)),
+            (0x1ABC, Some(0xABC)),
+            (0x2204, Some(0x204)),
+            (0xA050, Some(0x050)),
+            (0xB300, Some(0x300)),
+            (0x00EE, None),
+            (0x3A0F, None),
+            (0xD12F, None),
+            (0xF000, None),
+        ];
+        for (opcode, target) in targets {
+            assert_eq!(decode(opcode).target_address(), target, "{:#06X}", opcode);
+        }
+    }
+}
@@ -1,18 +1,26 @@
-use crate::cpu::Cpu;
+use crate::cpu::{Cpu, StackFault, SCREEN_HEIGHT, SCREEN_WIDTH};
 use nanorand::{Rng, WyRand};
 
 //Opcode: 0NNN
-pub fn call(_cpu: &mut Cpu, _address: u16) {
-    todo!()
-}
+//Runs a machine code routine on the original interpreters, which we can't do. Modern
+//interpreters skip it, and so do we, so running into zeroed memory doesn't stop the emulator.
+pub fn call(_cpu: &mut Cpu, _address: u16) {}
 
 //Opcode: 00E0
 pub fn clear_screen(cpu: &mut Cpu) {
     cpu.screen.fill(0);
+    cpu.dirty_rows.fill(true);
 }
 
 //Opcode: 00EE
 pub fn return_from_subroutine(cpu: &mut Cpu) {
+    if cpu.stack_pointer == 0 {
+        //Leave PC on the faulting instruction
+        cpu.stack_fault = Some(StackFault::Underflow);
+        cpu.program_counter -= 2;
+        return;
+    }
+
     cpu.program_counter = cpu.stack[(cpu.stack_pointer - 1) as usize];
     cpu.stack_pointer -= 1;
 }
@@ -24,6 +32,13 @@ pub fn goto(cpu: &mut Cpu, address: u16) {
 
 //Opcode: 2NNN
 pub fn call_sub(cpu: &mut Cpu, address: u16) {
+    if cpu.stack_pointer as usize >= cpu.stack.len() {
+        //Leave PC on the faulting instruction
+        cpu.stack_fault = Some(StackFault::Overflow);
+        cpu.program_counter -= 2;
+        return;
+    }
+
     cpu.stack[cpu.stack_pointer as usize] = cpu.program_counter;
     cpu.stack_pointer += 1;
     cpu.program_counter = address;
@@ -171,9 +186,9 @@ pub fn draw(cpu: &mut Cpu, register_a: u8, register_b: u8, sprite_height: u8) {
 
         for column in 0..8 {
             //Get only the first bit and put in in the display
-            let index = (x + column) + ((y + offset) * 64);
+            let index = (x + column) + ((y + offset) * SCREEN_WIDTH);
 
-            if index >= 2047 {
+            if index >= SCREEN_WIDTH * SCREEN_HEIGHT - 1 {
                 return;
             }
 
@@ -185,7 +200,10 @@ pub fn draw(cpu: &mut Cpu, register_a: u8, register_b: u8, sprite_height: u8) {
                 cpu.gp_registers[0xF] = 1;
             }
             row <<= 1;
-            cpu.screen[(x + column) + ((y + offset) * 64)] = new_pixel;
+            cpu.screen[index] = new_pixel;
+            if new_pixel != previous_pixel {
+                cpu.dirty_rows[index / SCREEN_WIDTH] = true;
+            }
         }
     }
 }
@@ -0,0 +1,5 @@
+pub mod client;
+pub mod cpu;
+pub mod octo;
+pub mod symbols;
+pub mod trace;
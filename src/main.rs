@@ -1,18 +1,25 @@
 use std::env;
+use std::process;
 
-mod cpu;
-use crate::cpu::Cpu;
-
-mod client;
-use client::DebugClient;
-use client::EmulatorClient;
-use client::NcursesClient;
+use chip_8_emulator::client::AnsiClient;
+use chip_8_emulator::client::EmulatorClient;
+use chip_8_emulator::client::Frontend;
+#[cfg(feature = "ncurses")]
+use chip_8_emulator::client::NcursesClient;
+use chip_8_emulator::client::Options;
 
 fn main() {
     //Read arguments
     let args: Vec<String> = env::args().collect();
+    let options = Options::parse(&args[1..]).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(1);
+    });
     //Create the emulator client
-    let mut emulator_client = NcursesClient::build(args.get(1));
-    // let mut emulator_client = DebugClient::build(args.get(1));
-    emulator_client.update();
+    match options.frontend {
+        #[cfg(feature = "ncurses")]
+        Frontend::Ncurses => NcursesClient::build(&options).update(),
+        Frontend::Ansi => AnsiClient::build(&options).update(),
+    }
+    // let mut emulator_client = DebugClient::build(&options);
 }
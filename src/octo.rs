@@ -0,0 +1,30 @@
+//Compiler for Octo, the assembly language most modern CHIP-8, SCHIP and XO-CHIP programs are
+//written in. See https://johnearnest.github.io/Octo/docs/Manual.html for the language.
+//
+//Supported: labels, :alias, :const, :macro, :calc, :byte, :org, :call, :unpack, :next,
+//if ... then, if ... begin/else/end, loop/while/again and every instruction of the three
+//platforms. Strings, :assert, :stringmode and :monitor are not.
+
+mod calc;
+mod compiler;
+mod lexer;
+
+use std::error::Error;
+use std::fmt;
+
+pub use compiler::{compile, Program};
+
+#[derive(Debug, Clone)]
+pub struct CompileError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: error: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Error for CompileError {}
@@ -0,0 +1,168 @@
+use std::f64::consts;
+
+use super::lexer::{self, Token};
+use super::CompileError;
+
+//What a :calc expression can refer to.
+pub trait Scope {
+    //Constants, labels and HERE
+    fn lookup(&self, name: &str) -> Option<f64>;
+    //Byte already compiled at address, for the @ operator
+    fn peek(&self, address: i64) -> Option<u8>;
+}
+
+fn error(token: &Token, message: String) -> CompileError {
+    CompileError {
+        line: token.line,
+        column: token.column,
+        message,
+    }
+}
+
+fn binary(op: &str, left: f64, right: f64) -> Option<f64> {
+    let integer = |f: fn(i64, i64) -> i64| Some(f(left as i64, right as i64) as f64);
+    match op {
+        "+" => Some(left + right),
+        "-" => Some(left - right),
+        "*" => Some(left * right),
+        "/" => Some(left / right),
+        "%" => Some(left % right),
+        "pow" => Some(left.powf(right)),
+        "min" => Some(left.min(right)),
+        "max" => Some(left.max(right)),
+        "&" => integer(|a, b| a & b),
+        "|" => integer(|a, b| a | b),
+        "^" => integer(|a, b| a ^ b),
+        "<<" => integer(|a, b| a.wrapping_shl(b as u32)),
+        ">>" => integer(|a, b| a.wrapping_shr(b as u32)),
+        "<" => Some((left < right) as i64 as f64),
+        ">" => Some((left > right) as i64 as f64),
+        "<=" => Some((left <= right) as i64 as f64),
+        ">=" => Some((left >= right) as i64 as f64),
+        "==" => Some((left == right) as i64 as f64),
+        "!=" => Some((left != right) as i64 as f64),
+        _ => None,
+    }
+}
+
+fn unary(op: &str, value: f64) -> Option<f64> {
+    match op {
+        "-" => Some(-value),
+        "~" => Some(!(value as i64) as f64),
+        "!" => Some((value == 0.0) as i64 as f64),
+        "sin" => Some(value.sin()),
+        "cos" => Some(value.cos()),
+        "tan" => Some(value.tan()),
+        "exp" => Some(value.exp()),
+        "log" => Some(value.ln()),
+        "abs" => Some(value.abs()),
+        "sqrt" => Some(value.sqrt()),
+        "sign" => Some(value.signum()),
+        "ceil" => Some(value.ceil()),
+        "floor" => Some(value.floor()),
+        _ => None,
+    }
+}
+
+//Octo computes with doubles, but only finite numbers can end up in a ROM.
+fn finite(value: f64, token: &Token) -> Result<f64, CompileError> {
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err(error(
+            token,
+            format!("'{}' does not give a finite number", token.text),
+        ))
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    scope: &'a dyn Scope,
+    //Closing brace, reported when the expression ends too early
+    end: &'a Token,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Result<&Token, CompileError> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .ok_or_else(|| error(self.end, String::from("incomplete expression")))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    //Octo evaluates right to left without precedence: a - b - c is a - (b - c).
+    fn expression(&mut self) -> Result<f64, CompileError> {
+        let left = self.term()?;
+        match self.tokens.get(self.position) {
+            None => Ok(left),
+            Some(token) if token.text == ")" => Ok(left),
+            Some(token) => {
+                let op = token.clone();
+                self.position += 1;
+                let right = self.expression()?;
+                let value = binary(&op.text, left, right)
+                    .ok_or_else(|| error(&op, format!("unknown operator '{}'", op.text)))?;
+                if matches!(op.text.as_str(), "/" | "%") && right == 0.0 {
+                    return Err(error(&op, String::from("division by zero")));
+                }
+                finite(value, &op)
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<f64, CompileError> {
+        let token = self.next()?.clone();
+        let text = token.text.as_str();
+
+        if let Some(value) = lexer::parse_number(text) {
+            return Ok(value as f64);
+        }
+        match text {
+            "(" => {
+                let value = self.expression()?;
+                match self.next()? {
+                    close if close.text == ")" => Ok(value),
+                    other => Err(error(other, String::from("expected ')'"))),
+                }
+            }
+            "PI" => Ok(consts::PI),
+            "E" => Ok(consts::E),
+            "@" => {
+                let address = self.term()?;
+                self.scope
+                    .peek(address as i64)
+                    .map(f64::from)
+                    .ok_or_else(|| error(&token, format!("nothing compiled at {address}")))
+            }
+            _ => match self.scope.lookup(text) {
+                Some(value) => Ok(value),
+                None => match unary(text, 0.0) {
+                    Some(_) => {
+                        let value = self.term()?;
+                        finite(unary(text, value).unwrap_or_default(), &token)
+                    }
+                    None => Err(error(&token, format!("undefined name '{text}'"))),
+                },
+            },
+        }
+    }
+}
+
+//Evaluates the tokens between the braces of a :calc. end is the closing brace.
+pub fn evaluate(tokens: &[Token], end: &Token, scope: &dyn Scope) -> Result<f64, CompileError> {
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+        scope,
+        end,
+    };
+    let value = parser.expression()?;
+    match tokens.get(parser.position) {
+        Some(extra) => Err(error(extra, format!("unexpected '{}'", extra.text))),
+        None => Ok(value),
+    }
+}
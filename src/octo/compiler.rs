@@ -0,0 +1,1113 @@
+use std::collections::{HashMap, VecDeque};
+
+use super::calc::{self, Scope};
+use super::lexer::{self, Token};
+use super::CompileError;
+use crate::cpu::decoder::{self, Platform};
+use crate::symbols::SymbolMap;
+
+//Programs are loaded right after the interpreter area.
+const LOAD_ADDRESS: u32 = 0x200;
+//Macros invoking each other more often than this are assumed to recurse forever.
+const MAX_MACRO_EXPANSIONS: usize = 10_000;
+
+//Words with a meaning of their own, which cannot be used as names.
+const KEYWORDS: &[&str] = &[
+    ":=", "|=", "&=", "^=", "-=", "=-", "+=", ">>=", "<<=", "==", "!=", "<", ">", "<=", ">=",
+    "key", "-key", "hex", "bighex", "random", "delay", "buzzer", "pitch", "i", "long", "return",
+    ";", "clear", "bcd", "save", "load", "sprite", "jump", "jump0", "native", "if", "then",
+    "begin", "else", "end", "loop", "again", "while", "-",
+];
+
+//Register to register operators and the low nibble of their 8XYN opcode.
+const REGISTER_OPERATORS: [(&str, u16); 9] = [
+    (":=", 0x0),
+    ("|=", 0x1),
+    ("&=", 0x2),
+    ("^=", 0x3),
+    ("+=", 0x4),
+    ("-=", 0x5),
+    (">>=", 0x6),
+    ("=-", 0x7),
+    ("<<=", 0xE),
+];
+
+pub struct Program {
+    pub rom: Vec<u8>,
+    pub symbols: SymbolMap,
+}
+
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<Token>,
+}
+
+//How a label reference is patched in once the label is defined.
+enum FixupKind {
+    //Low 12 bits of the opcode at the address
+    Address12,
+    //Both bytes at the address, for i := long
+    Address16,
+    //The two immediates of the v0 := / v1 := pair written by :unpack. None for :unpack long.
+    Unpack(Option<u8>),
+}
+
+struct Fixup {
+    address: u32,
+    kind: FixupKind,
+    token: Token,
+}
+
+//Open control flow blocks, innermost last.
+enum Block {
+    //if ... begin, with the jump taken when the condition is false
+    If {
+        jump: u32,
+        token: Token,
+    },
+    //else, with the jump past the else branch
+    Else {
+        jump: u32,
+        token: Token,
+    },
+    //loop, with the jumps out of it written by while
+    Loop {
+        start: u32,
+        exits: Vec<u32>,
+        token: Token,
+    },
+}
+
+#[derive(Clone, Copy)]
+enum Operand {
+    Register(u8),
+    Byte(u8),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+}
+
+#[derive(Clone, Copy)]
+enum Test {
+    Compare(Comparison, Operand),
+    Key,
+    NotKey,
+}
+
+#[derive(Clone, Copy)]
+struct Condition {
+    register: u8,
+    test: Test,
+}
+
+impl Condition {
+    fn negate(self) -> Condition {
+        let test = match self.test {
+            Test::Key => Test::NotKey,
+            Test::NotKey => Test::Key,
+            Test::Compare(comparison, operand) => {
+                let negated = match comparison {
+                    Comparison::Equal => Comparison::NotEqual,
+                    Comparison::NotEqual => Comparison::Equal,
+                    Comparison::Less => Comparison::GreaterEqual,
+                    Comparison::GreaterEqual => Comparison::Less,
+                    Comparison::Greater => Comparison::LessEqual,
+                    Comparison::LessEqual => Comparison::Greater,
+                };
+                Test::Compare(negated, operand)
+            }
+        };
+        Condition {
+            register: self.register,
+            test,
+        }
+    }
+}
+
+fn error(token: &Token, message: String) -> CompileError {
+    CompileError {
+        line: token.line,
+        column: token.column,
+        message,
+    }
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    match text.as_bytes() {
+        [b'v' | b'V', digit] => (*digit as char).to_digit(16).map(|digit| digit as u8),
+        _ => None,
+    }
+}
+
+struct Compiler {
+    tokens: VecDeque<Token>,
+    //Last token read, errors at the end of the source point here
+    last: Token,
+    platform: Platform,
+    memory: Vec<u8>,
+    here: u32,
+    //One past the highest address written
+    end: u32,
+    labels: HashMap<String, u32>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    //Label given by :next to the immediate of the next instruction
+    pending_next: Option<Token>,
+    //Name of the source file, for the line map
+    file: String,
+    symbols: SymbolMap,
+}
+
+impl Scope for Compiler {
+    fn lookup(&self, name: &str) -> Option<f64> {
+        if name == "HERE" {
+            return Some(self.here as f64);
+        }
+        self.constants
+            .get(name)
+            .copied()
+            .or_else(|| self.labels.get(name).map(|address| *address as f64))
+    }
+
+    fn peek(&self, address: i64) -> Option<u8> {
+        if address >= LOAD_ADDRESS as i64 && address < self.end as i64 {
+            Some(self.memory[address as usize])
+        } else {
+            None
+        }
+    }
+}
+
+impl Compiler {
+    fn next(&mut self) -> Result<Token, CompileError> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.last = token.clone();
+                Ok(token)
+            }
+            None => Err(error(&self.last, String::from("unexpected end of file"))),
+        }
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens
+            .front()
+            .map(|token| token.text == text)
+            .unwrap_or(false)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<Token, CompileError> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(error(
+                &token,
+                format!("expected '{text}', found '{}'", token.text),
+            ));
+        }
+        Ok(token)
+    }
+
+    fn register_of(&self, text: &str) -> Option<u8> {
+        parse_register(text).or_else(|| self.aliases.get(text).copied())
+    }
+
+    fn register(&mut self) -> Result<u8, CompileError> {
+        let token = self.next()?;
+        self.register_of(&token.text).ok_or_else(|| {
+            error(
+                &token,
+                format!("expected a register, found '{}'", token.text),
+            )
+        })
+    }
+
+    //Numbers and constants.
+    fn value_of(&self, text: &str) -> Option<i64> {
+        lexer::parse_number(text).or_else(|| self.constants.get(text).map(|value| *value as i64))
+    }
+
+    fn ranged(&self, token: &Token, min: i64, max: i64, what: &str) -> Result<i64, CompileError> {
+        match self.value_of(&token.text) {
+            Some(value) if (min..=max).contains(&value) => Ok(value),
+            Some(value) => Err(error(token, format!("{value} does not fit in {what}"))),
+            None => Err(error(
+                token,
+                format!("expected {what}, found '{}'", token.text),
+            )),
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, CompileError> {
+        let token = self.next()?;
+        self.ranged(&token, -128, 255, "a byte")
+            .map(|value| value as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u8, CompileError> {
+        let token = self.next()?;
+        self.ranged(&token, 0, 15, "a nibble")
+            .map(|value| value as u8)
+    }
+
+    fn register_or_byte(&mut self) -> Result<Operand, CompileError> {
+        let token = self.next()?;
+        match self.register_of(&token.text) {
+            Some(register) => Ok(Operand::Register(register)),
+            None => self
+                .ranged(&token, -128, 255, "a byte or register")
+                .map(|value| Operand::Byte(value as u8)),
+        }
+    }
+
+    fn check_name(&self, token: &Token) -> Result<(), CompileError> {
+        let text = token.text.as_str();
+        if lexer::parse_number(text).is_some()
+            || parse_register(text).is_some()
+            || text.starts_with(':')
+            || text == "{"
+            || text == "}"
+            || KEYWORDS.contains(&text)
+        {
+            return Err(error(token, format!("'{text}' cannot be used as a name")));
+        }
+        Ok(())
+    }
+
+    fn check_undefined(&self, token: &Token) -> Result<(), CompileError> {
+        self.check_name(token)?;
+        let text = token.text.as_str();
+        if self.labels.contains_key(text)
+            || self.constants.contains_key(text)
+            || self.macros.contains_key(text)
+        {
+            return Err(error(token, format!("'{text}' is already defined")));
+        }
+        Ok(())
+    }
+
+    fn define_label(&mut self, token: &Token, address: u32) -> Result<(), CompileError> {
+        self.check_undefined(token)?;
+        self.labels.insert(token.text.clone(), address);
+        self.symbols.add_label(address as u16, &token.text);
+        Ok(())
+    }
+
+    //Address operand of an instruction written offset bytes after the current address.
+    //Labels that are not defined yet are patched in at the end.
+    fn address(&mut self, offset: u32, kind: FixupKind) -> Result<u32, CompileError> {
+        let token = self.next()?;
+        let max = match kind {
+            FixupKind::Address12 => 0xFFF,
+            _ => 0xFFFF,
+        };
+
+        if let Some(value) = self.value_of(&token.text) {
+            if !(0..=max).contains(&value) {
+                return Err(error(&token, format!("address {value:#X} is out of range")));
+            }
+            return Ok(value as u32);
+        }
+        if let Some(address) = self.labels.get(&token.text) {
+            if *address > max as u32 {
+                return Err(error(&token, format!("'{}' is out of range", token.text)));
+            }
+            return Ok(*address);
+        }
+
+        self.check_name(&token)?;
+        if self.register_of(&token.text).is_some() {
+            return Err(error(&token, String::from("expected an address")));
+        }
+        self.fixups.push(Fixup {
+            address: self.here + offset,
+            kind,
+            token,
+        });
+        Ok(0)
+    }
+
+    fn emit_byte(&mut self, byte: u8, token: &Token) -> Result<(), CompileError> {
+        if self.here > self.platform.memory_end() {
+            return Err(error(token, String::from("program does not fit in memory")));
+        }
+        self.memory[self.here as usize] = byte;
+        self.here += 1;
+        self.end = self.end.max(self.here);
+        Ok(())
+    }
+
+    fn emit(&mut self, opcode: u16, token: &Token) -> Result<(), CompileError> {
+        let required = decoder::required_platform(opcode);
+        if required > self.platform {
+            return Err(error(
+                token,
+                format!(
+                    "'{}' needs {required}, compiling for {}",
+                    token.text, self.platform
+                ),
+            ));
+        }
+        if let Some(name) = self.pending_next.take() {
+            self.define_label(&name, self.here + 1)?;
+        }
+        if self.here <= self.platform.memory_end() {
+            self.symbols
+                .add_line(self.here as u16, &self.file, token.line);
+        }
+
+        let [high, low] = opcode.to_be_bytes();
+        self.emit_byte(high, token)?;
+        self.emit_byte(low, token)
+    }
+
+    //Emits a jump to be patched later, returning its address.
+    fn emit_placeholder_jump(&mut self, token: &Token) -> Result<u32, CompileError> {
+        let address = self.here;
+        self.emit(0x1000, token)?;
+        Ok(address)
+    }
+
+    fn patch_jump(&mut self, address: u32, target: u32, token: &Token) -> Result<(), CompileError> {
+        if target > 0xFFF {
+            return Err(error(token, String::from("jump target is out of range")));
+        }
+        self.memory[address as usize] = 0x10 | (target >> 8) as u8;
+        self.memory[address as usize + 1] = target as u8;
+        Ok(())
+    }
+
+    fn condition(&mut self) -> Result<Condition, CompileError> {
+        let register = self.register()?;
+        let token = self.next()?;
+        let comparison = match token.text.as_str() {
+            "key" => {
+                return Ok(Condition {
+                    register,
+                    test: Test::Key,
+                })
+            }
+            "-key" => {
+                return Ok(Condition {
+                    register,
+                    test: Test::NotKey,
+                })
+            }
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            ">" => Comparison::Greater,
+            "<=" => Comparison::LessEqual,
+            ">=" => Comparison::GreaterEqual,
+            other => {
+                return Err(error(
+                    &token,
+                    format!("expected a comparison, found '{other}'"),
+                ))
+            }
+        };
+        Ok(Condition {
+            register,
+            test: Test::Compare(comparison, self.register_or_byte()?),
+        })
+    }
+
+    //Emits the instructions that skip the next one unless the condition holds. Ordering
+    //comparisons go through vF the same way Octo does it.
+    fn emit_skip_unless(
+        &mut self,
+        condition: Condition,
+        token: &Token,
+    ) -> Result<(), CompileError> {
+        let x = (condition.register as u16) << 8;
+        match condition.test {
+            Test::Key => self.emit(0xE0A1 | x, token),
+            Test::NotKey => self.emit(0xE09E | x, token),
+            Test::Compare(Comparison::Equal, Operand::Byte(value)) => {
+                self.emit(0x4000 | x | value as u16, token)
+            }
+            Test::Compare(Comparison::Equal, Operand::Register(y)) => {
+                self.emit(0x9000 | x | (y as u16) << 4, token)
+            }
+            Test::Compare(Comparison::NotEqual, Operand::Byte(value)) => {
+                self.emit(0x3000 | x | value as u16, token)
+            }
+            Test::Compare(Comparison::NotEqual, Operand::Register(y)) => {
+                self.emit(0x5000 | x | (y as u16) << 4, token)
+            }
+            Test::Compare(comparison, operand) => {
+                match operand {
+                    Operand::Byte(value) => self.emit(0x6F00 | value as u16, token)?,
+                    Operand::Register(y) => self.emit(0x8F00 | (y as u16) << 4, token)?,
+                }
+                let x = x >> 4;
+                //vF ends up 1 when vX >= operand with =-, operand >= vX with -=
+                match comparison {
+                    Comparison::Less | Comparison::GreaterEqual => self.emit(0x8F07 | x, token)?,
+                    _ => self.emit(0x8F05 | x, token)?,
+                }
+                match comparison {
+                    Comparison::Less | Comparison::Greater => self.emit(0x4F00, token),
+                    _ => self.emit(0x4F01, token),
+                }
+            }
+        }
+    }
+
+    //Collects the tokens up to the brace closing an already read '{'.
+    fn braced(&mut self) -> Result<(Vec<Token>, Token), CompileError> {
+        let mut depth = 0;
+        let mut body = Vec::new();
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => return Ok((body, token)),
+                "}" => depth -= 1,
+                _ => (),
+            }
+            body.push(token);
+        }
+    }
+
+    fn calc(&mut self) -> Result<f64, CompileError> {
+        self.expect("{")?;
+        let (body, close) = self.braced()?;
+        calc::evaluate(&body, &close, self)
+    }
+
+    fn expand_macro(&mut self, token: &Token) -> Result<(), CompileError> {
+        self.expansions += 1;
+        if self.expansions > MAX_MACRO_EXPANSIONS {
+            return Err(error(token, String::from("too many macro expansions")));
+        }
+
+        let count = self.macros[&token.text].parameters.len();
+        let mut arguments = HashMap::new();
+        for index in 0..count {
+            let argument = self.next()?;
+            arguments.insert(self.macros[&token.text].parameters[index].clone(), argument);
+        }
+
+        let expansion: Vec<Token> = self.macros[&token.text]
+            .body
+            .iter()
+            .map(|body_token| match arguments.get(&body_token.text) {
+                Some(argument) => argument.clone(),
+                None => body_token.clone(),
+            })
+            .collect();
+        for expanded in expansion.into_iter().rev() {
+            self.tokens.push_front(expanded);
+        }
+        Ok(())
+    }
+
+    fn directive(&mut self, token: &Token) -> Result<(), CompileError> {
+        match token.text.as_str() {
+            ":" => {
+                let name = self.next()?;
+                self.define_label(&name, self.here)
+            }
+            ":alias" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                let register = self.register()?;
+                self.aliases.insert(name.text, register);
+                Ok(())
+            }
+            ":const" => {
+                let name = self.next()?;
+                self.check_undefined(&name)?;
+                let value = self.next()?;
+                let value = self
+                    .value_of(&value.text)
+                    .or_else(|| self.labels.get(&value.text).map(|address| *address as i64))
+                    .ok_or_else(|| {
+                        error(&value, format!("expected a number, found '{}'", value.text))
+                    })?;
+                self.constants.insert(name.text, value as f64);
+                Ok(())
+            }
+            ":calc" => {
+                let name = self.next()?;
+                self.check_name(&name)?;
+                if self.labels.contains_key(&name.text) || self.macros.contains_key(&name.text) {
+                    return Err(error(&name, format!("'{}' is already defined", name.text)));
+                }
+                let value = self.calc()?;
+                self.constants.insert(name.text, value);
+                Ok(())
+            }
+            ":macro" => {
+                let name = self.next()?;
+                self.check_undefined(&name)?;
+                let mut parameters = Vec::new();
+                loop {
+                    let parameter = self.next()?;
+                    if parameter.text == "{" {
+                        break;
+                    }
+                    parameters.push(parameter.text);
+                }
+                let (body, _) = self.braced()?;
+                self.macros.insert(name.text, Macro { parameters, body });
+                Ok(())
+            }
+            ":byte" => {
+                let value = if self.peek_is("{") {
+                    let value = self.calc()? as i64;
+                    if !(-128..=255).contains(&value) {
+                        return Err(error(token, format!("{value} does not fit in a byte")));
+                    }
+                    value as u8
+                } else {
+                    self.byte()?
+                };
+                self.emit_byte(value, token)
+            }
+            ":org" => {
+                let address = self.next()?;
+                let max = self.platform.memory_end() as i64;
+                self.here = self.ranged(&address, LOAD_ADDRESS as i64, max, "an address")? as u32;
+                Ok(())
+            }
+            ":call" => {
+                let address = self.address(0, FixupKind::Address12)?;
+                self.emit(0x2000 | address as u16, token)
+            }
+            ":unpack" => {
+                let high = self.next()?;
+                let nibble = match high.text.as_str() {
+                    "long" => None,
+                    _ => Some(self.ranged(&high, 0, 15, "a nibble")? as u8),
+                };
+                let address = self.address(0, FixupKind::Unpack(nibble))?;
+                let (v0, v1) = unpack(nibble, address);
+                self.emit(0x6000 | v0 as u16, token)?;
+                self.emit(0x6100 | v1 as u16, token)
+            }
+            ":next" => {
+                let name = self.next()?;
+                self.check_undefined(&name)?;
+                self.pending_next = Some(name);
+                Ok(())
+            }
+            //Breakpoints and prototypes only matter to Octo's own tooling
+            ":breakpoint" | ":proto" => self.next().map(|_| ()),
+            other => Err(error(token, format!("unknown directive '{other}'"))),
+        }
+    }
+
+    fn assignment(&mut self, x: u8, token: &Token) -> Result<(), CompileError> {
+        let x = (x as u16) << 8;
+        let op = self.next()?;
+
+        if op.text == ":="
+            && (self.peek_is("delay") || self.peek_is("key") || self.peek_is("random"))
+        {
+            let source = self.next()?;
+            return match source.text.as_str() {
+                "delay" => self.emit(0xF007 | x, token),
+                "key" => self.emit(0xF00A | x, token),
+                _ => {
+                    let mask = self.byte()?;
+                    self.emit(0xC000 | x | mask as u16, token)
+                }
+            };
+        }
+
+        let suffix = REGISTER_OPERATORS
+            .iter()
+            .find(|(text, _)| *text == op.text)
+            .map(|(_, suffix)| *suffix)
+            .ok_or_else(|| error(&op, format!("unknown operator '{}'", op.text)))?;
+
+        let opcode = match (op.text.as_str(), self.register_or_byte()?) {
+            (":=", Operand::Byte(value)) => 0x6000 | x | value as u16,
+            ("+=", Operand::Byte(value)) => 0x7000 | x | value as u16,
+            ("-=", Operand::Byte(value)) => 0x7000 | x | value.wrapping_neg() as u16,
+            (_, Operand::Register(y)) => 0x8000 | x | (y as u16) << 4 | suffix,
+            (_, Operand::Byte(_)) => {
+                return Err(error(&op, format!("'{}' needs a register", op.text)))
+            }
+        };
+        self.emit(opcode, token)
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), CompileError> {
+        let text = token.text.as_str();
+        let simple = match text {
+            "return" | ";" => Some(0x00EE),
+            "clear" => Some(0x00E0),
+            "scroll-right" => Some(0x00FB),
+            "scroll-left" => Some(0x00FC),
+            "exit" => Some(0x00FD),
+            "lores" => Some(0x00FE),
+            "hires" => Some(0x00FF),
+            "audio" => Some(0xF002),
+            _ => None,
+        };
+        if let Some(opcode) = simple {
+            return self.emit(opcode, &token);
+        }
+
+        if text.starts_with(':') {
+            return self.directive(&token);
+        }
+        if let Some(register) = self.register_of(text) {
+            return self.assignment(register, &token);
+        }
+        if self.macros.contains_key(text) {
+            return self.expand_macro(&token);
+        }
+        if self.value_of(text).is_some() {
+            let byte = self.ranged(&token, -128, 255, "a byte")?;
+            return self.emit_byte(byte as u8, &token);
+        }
+
+        match text {
+            "scroll-down" | "scroll-up" | "plane" => {
+                let n = self.nibble()? as u16;
+                let opcode = match text {
+                    "scroll-down" => 0x00C0 | n,
+                    "scroll-up" => 0x00D0 | n,
+                    _ => 0xF001 | n << 8,
+                };
+                self.emit(opcode, &token)
+            }
+            "bcd" | "saveflags" | "loadflags" => {
+                let x = (self.register()? as u16) << 8;
+                let opcode = match text {
+                    "bcd" => 0xF033,
+                    "saveflags" => 0xF075,
+                    _ => 0xF085,
+                };
+                self.emit(opcode | x, &token)
+            }
+            "save" | "load" => {
+                let x = self.register()? as u16;
+                if self.peek_is("-") {
+                    self.next()?;
+                    let y = self.register()? as u16;
+                    let suffix = if text == "save" { 0x2 } else { 0x3 };
+                    self.emit(0x5000 | x << 8 | y << 4 | suffix, &token)
+                } else {
+                    let suffix = if text == "save" { 0x55 } else { 0x65 };
+                    self.emit(0xF000 | x << 8 | suffix, &token)
+                }
+            }
+            "sprite" => {
+                let x = self.register()? as u16;
+                let y = self.register()? as u16;
+                let height = self.nibble()? as u16;
+                self.emit(0xD000 | x << 8 | y << 4 | height, &token)
+            }
+            "jump" | "jump0" | "native" => {
+                let address = self.address(0, FixupKind::Address12)? as u16;
+                let prefix = match text {
+                    "jump" => 0x1000,
+                    "jump0" => 0xB000,
+                    _ => 0x0000,
+                };
+                self.emit(prefix | address, &token)
+            }
+            "i" => {
+                let op = self.next()?;
+                match op.text.as_str() {
+                    "+=" => {
+                        let x = (self.register()? as u16) << 8;
+                        self.emit(0xF01E | x, &token)
+                    }
+                    ":=" if self.peek_is("hex") || self.peek_is("bighex") => {
+                        let kind = self.next()?;
+                        let x = (self.register()? as u16) << 8;
+                        let suffix = if kind.text == "hex" { 0x29 } else { 0x30 };
+                        self.emit(0xF000 | x | suffix, &kind)
+                    }
+                    ":=" if self.peek_is("long") => {
+                        let long = self.next()?;
+                        let address = self.address(2, FixupKind::Address16)? as u16;
+                        self.emit(0xF000, &long)?;
+                        let [high, low] = address.to_be_bytes();
+                        self.emit_byte(high, &long)?;
+                        self.emit_byte(low, &long)
+                    }
+                    ":=" => {
+                        let address = self.address(0, FixupKind::Address12)? as u16;
+                        self.emit(0xA000 | address, &token)
+                    }
+                    other => Err(error(&op, format!("unknown operator '{other}' for i"))),
+                }
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = (self.register()? as u16) << 8;
+                let suffix = match text {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3A,
+                };
+                self.emit(0xF000 | x | suffix, &token)
+            }
+            "if" => {
+                let condition = self.condition()?;
+                let body = self.next()?;
+                match body.text.as_str() {
+                    "then" => self.emit_skip_unless(condition, &token),
+                    "begin" => {
+                        self.emit_skip_unless(condition.negate(), &token)?;
+                        let jump = self.emit_placeholder_jump(&token)?;
+                        self.blocks.push(Block::If { jump, token });
+                        Ok(())
+                    }
+                    other => Err(error(
+                        &body,
+                        format!("expected 'then' or 'begin', found '{other}'"),
+                    )),
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) => {
+                    let skip = self.emit_placeholder_jump(&token)?;
+                    self.patch_jump(jump, self.here, &token)?;
+                    self.blocks.push(Block::Else { jump: skip, token });
+                    Ok(())
+                }
+                _ => Err(error(&token, String::from("'else' without 'if ... begin'"))),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) | Some(Block::Else { jump, .. }) => {
+                    self.patch_jump(jump, self.here, &token)
+                }
+                _ => Err(error(&token, String::from("'end' without 'begin'"))),
+            },
+            "loop" => {
+                self.blocks.push(Block::Loop {
+                    start: self.here,
+                    exits: Vec::new(),
+                    token,
+                });
+                Ok(())
+            }
+            "while" => {
+                let condition = self.condition()?;
+                self.emit_skip_unless(condition.negate(), &token)?;
+                let jump = self.emit_placeholder_jump(&token)?;
+                let innermost = self.blocks.iter_mut().rev().find_map(|block| match block {
+                    Block::Loop { exits, .. } => Some(exits),
+                    _ => None,
+                });
+                match innermost {
+                    Some(exits) => {
+                        exits.push(jump);
+                        Ok(())
+                    }
+                    None => Err(error(&token, String::from("'while' outside of a loop"))),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, exits, .. }) => {
+                    let jump = self.emit_placeholder_jump(&token)?;
+                    self.patch_jump(jump, start, &token)?;
+                    for exit in exits {
+                        self.patch_jump(exit, self.here, &token)?;
+                    }
+                    Ok(())
+                }
+                _ => Err(error(&token, String::from("'again' without 'loop'"))),
+            },
+            _ => {
+                //Any other name calls the subroutine with that label
+                self.tokens.push_front(token.clone());
+                let address = self.address(0, FixupKind::Address12)? as u16;
+                self.emit(0x2000 | address, &token)
+            }
+        }
+    }
+
+    fn resolve_fixups(&mut self) -> Result<(), CompileError> {
+        for fixup in std::mem::take(&mut self.fixups) {
+            let target = match self.labels.get(&fixup.token.text) {
+                Some(target) => *target,
+                None if fixup.token.text == "main" && fixup.address == LOAD_ADDRESS => {
+                    return Err(error(
+                        &fixup.token,
+                        String::from("program has no 'main' label"),
+                    ))
+                }
+                None => {
+                    return Err(error(
+                        &fixup.token,
+                        format!("undefined name '{}'", fixup.token.text),
+                    ))
+                }
+            };
+
+            let address = fixup.address as usize;
+            match fixup.kind {
+                FixupKind::Address12 => {
+                    if target > 0xFFF {
+                        return Err(error(
+                            &fixup.token,
+                            format!("'{}' is out of range", fixup.token.text),
+                        ));
+                    }
+                    self.memory[address] |= (target >> 8) as u8;
+                    self.memory[address + 1] = target as u8;
+                }
+                FixupKind::Address16 => {
+                    self.memory[address] = (target >> 8) as u8;
+                    self.memory[address + 1] = target as u8;
+                }
+                FixupKind::Unpack(nibble) => {
+                    let (v0, v1) = unpack(nibble, target);
+                    self.memory[address + 1] = v0;
+                    self.memory[address + 3] = v1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Program, CompileError> {
+        if let Some(name) = &self.pending_next {
+            return Err(error(
+                name,
+                String::from("':next' is not followed by an instruction"),
+            ));
+        }
+        match self.blocks.last() {
+            Some(Block::If { token, .. }) | Some(Block::Else { token, .. }) => {
+                return Err(error(
+                    token,
+                    String::from("'begin' is never closed with 'end'"),
+                ))
+            }
+            Some(Block::Loop { token, .. }) => {
+                return Err(error(
+                    token,
+                    String::from("'loop' is never closed with 'again'"),
+                ))
+            }
+            None => (),
+        }
+        self.resolve_fixups()?;
+
+        let start = LOAD_ADDRESS as usize;
+        let end = (self.end as usize).max(start);
+        Ok(Program {
+            rom: self.memory[start..end].to_vec(),
+            symbols: self.symbols,
+        })
+    }
+}
+
+//Immediates of the v0 := and v1 := written by :unpack.
+fn unpack(nibble: Option<u8>, address: u32) -> (u8, u8) {
+    match nibble {
+        Some(nibble) => (nibble << 4 | (address >> 8) as u8 & 0xF, address as u8),
+        None => ((address >> 8) as u8, address as u8),
+    }
+}
+
+//Compiles Octo source into a ROM image loaded at 0x200. Unless the program starts with
+//`: main`, the first instruction is a jump to main. file names the source in the line map.
+pub fn compile(source: &str, file: &str, platform: Platform) -> Result<Program, CompileError> {
+    let tokens: VecDeque<Token> = lexer::tokenize(source).into();
+    let first = tokens.front().cloned().unwrap_or(Token {
+        text: String::new(),
+        line: 1,
+        column: 1,
+    });
+
+    let mut compiler = Compiler {
+        last: first.clone(),
+        tokens,
+        platform,
+        memory: vec![0; platform.memory_end() as usize + 1],
+        here: LOAD_ADDRESS,
+        end: LOAD_ADDRESS,
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        expansions: 0,
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+        pending_next: None,
+        file: file.to_string(),
+        symbols: SymbolMap::default(),
+    };
+
+    let starts_with_main = compiler.tokens.len() >= 2
+        && compiler.tokens[0].text == ":"
+        && compiler.tokens[1].text == "main";
+    if !starts_with_main {
+        let main = Token {
+            text: String::from("main"),
+            ..first
+        };
+        compiler.fixups.push(Fixup {
+            address: LOAD_ADDRESS,
+            kind: FixupKind::Address12,
+            token: main.clone(),
+        });
+        //Written directly so the jump is not attributed to a source line
+        compiler.emit_byte(0x10, &main)?;
+        compiler.emit_byte(0x00, &main)?;
+    }
+
+    while let Some(token) = compiler.tokens.pop_front() {
+        compiler.last = token.clone();
+        compiler.statement(token)?;
+    }
+    compiler.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+
+    fn rom(source: &str) -> Vec<u8> {
+        compile(source, "test.8o", Platform::Chip8)
+            .unwrap_or_else(|err| panic!("{err}"))
+            .rom
+    }
+
+    fn compile_error(source: &str, platform: Platform) -> String {
+        match compile(source, "test.8o", platform) {
+            Ok(_) => panic!("compiled: {source}"),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn jumps_to_main_unless_it_comes_first() {
+        assert_eq!(rom(": main\n  v0 := 1"), [0x60, 0x01]);
+        assert_eq!(
+            rom(": draw\n  return\n: main\n  draw"),
+            [0x12, 0x04, 0x00, 0xEE, 0x22, 0x02]
+        );
+
+        assert_eq!(
+            compile_error(": draw return", Platform::Chip8),
+            "1:1: error: program has no 'main' label"
+        );
+    }
+
+    #[test]
+    fn orders_comparisons_through_vf() {
+        assert_eq!(
+            rom(": main if v1 < 5 then v2 := 1"),
+            [0x6F, 0x05, 0x8F, 0x17, 0x4F, 0x00, 0x62, 0x01]
+        );
+        assert_eq!(
+            rom(": main if v1 >= v3 then v2 := 1"),
+            [0x8F, 0x30, 0x8F, 0x17, 0x4F, 0x01, 0x62, 0x01]
+        );
+
+        //Run every comparison on 4, 5 and 6 against 5
+        let comparisons = [
+            ("<", [true, false, false]),
+            (">", [false, false, true]),
+            ("<=", [true, true, false]),
+            (">=", [false, true, true]),
+        ];
+        for (comparison, expected) in comparisons {
+            for (value, expected) in [4, 5, 6].into_iter().zip(expected) {
+                let source = format!(": main v1 := {value} if v1 {comparison} 5 then v2 := 1");
+                let program = rom(&source);
+                let mut cpu = Cpu::new(program.clone());
+                while (cpu.get_program_counter() as usize) < 0x200 + program.len() {
+                    cpu.clock();
+                }
+                assert_eq!(cpu.get_gp_registers()[2] == 1, expected, "{source}");
+            }
+        }
+    }
+
+    #[test]
+    fn evaluates_calc() {
+        let source = ": main
+            :calc width { 8 * 2 }
+            :calc half { width / 2 - 1 }
+            :calc mask { 0xF0 | 3 }
+            v0 := half
+            v1 := mask
+            :byte { HERE - 0x200 }
+            :byte { @ 0x201 }";
+        //Octo has no precedence and goes right to left, so half is width / (2 - 1)
+        assert_eq!(rom(source), [0x60, 0x10, 0x61, 0xF3, 0x04, 0x10]);
+    }
+
+    #[test]
+    fn rejects_calc_results_that_are_not_finite() {
+        let errors = [
+            (":calc x { 1 / 0 }", "1:13: error: division by zero"),
+            (":calc x { 5 % 0 }", "1:13: error: division by zero"),
+            (
+                ":calc x { sqrt -1 }",
+                "1:11: error: 'sqrt' does not give a finite number",
+            ),
+            (
+                ":calc x { 10 pow 400 }",
+                "1:14: error: 'pow' does not give a finite number",
+            ),
+        ];
+        for (source, error) in errors {
+            assert_eq!(compile_error(source, Platform::Chip8), error, "{source}");
+        }
+    }
+
+    #[test]
+    fn reports_errors_at_their_line_and_column() {
+        let errors = [
+            (
+                ": main\n  v0 := 300",
+                "2:9: error: 300 does not fit in a byte or register",
+            ),
+            (
+                ": main\n  jump nowhere",
+                "2:8: error: undefined name 'nowhere'",
+            ),
+            (
+                ": main\n  sprite v0 v1 16",
+                "2:16: error: 16 does not fit in a nibble",
+            ),
+            (
+                ": main\n  if v0 == 1 begin\n  v1 := 2",
+                "2:3: error: 'begin' is never closed with 'end'",
+            ),
+            (
+                ": main\n  if v0 ~ 1 then",
+                "2:9: error: expected a comparison, found '~'",
+            ),
+            (": main\n: main", "2:3: error: 'main' is already defined"),
+            (
+                ": main\n  scroll-down 4",
+                "2:3: error: 'scroll-down' needs SCHIP, compiling for CHIP-8",
+            ),
+        ];
+        for (source, error) in errors {
+            assert_eq!(compile_error(source, Platform::Chip8), error, "{source}");
+        }
+
+        assert!(compile(": main\n  scroll-down 4", "test.8o", Platform::Schip).is_ok());
+        assert_eq!(
+            compile_error(": main\n  i := long main", Platform::Schip),
+            "2:8: error: 'long' needs XO-CHIP, compiling for SCHIP"
+        );
+    }
+}
@@ -0,0 +1,69 @@
+//Octo source is a stream of whitespace separated words. '#' starts a comment that runs to the
+//end of the line.
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub text: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+pub fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let mut start: Option<usize> = None;
+        let mut word = String::new();
+
+        for (column, character) in line.chars().enumerate() {
+            if character.is_whitespace() || (character == '#' && start.is_none()) {
+                if let Some(start) = start.take() {
+                    tokens.push(Token {
+                        text: std::mem::take(&mut word),
+                        line: index + 1,
+                        column: start + 1,
+                    });
+                }
+                if character == '#' {
+                    break;
+                }
+            } else {
+                start.get_or_insert(column);
+                word.push(character);
+            }
+        }
+
+        if let Some(start) = start {
+            tokens.push(Token {
+                text: word,
+                line: index + 1,
+                column: start + 1,
+            });
+        }
+    }
+
+    tokens
+}
+
+//Octo numbers: decimal, 0x hexadecimal or 0b binary, optionally negative.
+pub fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits
+        .strip_prefix("0b")
+        .or_else(|| digits.strip_prefix("0B"))
+    {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if !digits.is_empty() && digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
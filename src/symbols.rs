@@ -0,0 +1,250 @@
+//Symbol maps written next to assembled ROMs so the debugger can show label names and the
+//source line each instruction came from.
+//
+//Text format, one entry per line after the header:
+//
+//  # chip-8 symbols v1
+//  <address> <name>
+//  <address> :line <file>:<line>
+//
+//Addresses are hexadecimal with a 0x prefix. Lines starting with '#' are comments. Names never
+//start with ':', so line entries cannot be mistaken for labels.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+pub const HEADER: &str = "# chip-8 symbols v1";
+
+const LINE_TAG: &str = ":line";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: String,
+    //1-based
+    pub line: usize,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct SymbolMap {
+    addresses: BTreeMap<String, u16>,
+    //First label defined at each address.
+    labels: BTreeMap<u16, String>,
+    //Source line that produced the bytes starting at each address.
+    lines: BTreeMap<u16, SourceLocation>,
+}
+
+impl SymbolMap {
+    pub fn add_label(&mut self, address: u16, name: &str) {
+        self.addresses.insert(name.to_string(), address);
+        self.labels
+            .entry(address)
+            .or_insert_with(|| name.to_string());
+    }
+
+    pub fn add_line(&mut self, address: u16, file: &str, line: usize) {
+        self.lines.insert(
+            address,
+            SourceLocation {
+                file: file.to_string(),
+                line,
+            },
+        );
+    }
+
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        self.labels.get(&address).map(String::as_str)
+    }
+
+    pub fn address_of(&self, name: &str) -> Option<u16> {
+        self.addresses.get(name).copied()
+    }
+
+    pub fn line_at(&self, address: u16) -> Option<&SourceLocation> {
+        self.lines.get(&address)
+    }
+
+    //Lowest address generated by the given line, or by the closest line after it that produced
+    //any bytes. file can be a trailing part of the recorded path.
+    pub fn address_of_line(&self, file: &str, line: usize) -> Option<u16> {
+        self.lines
+            .iter()
+            .filter(|(_, location)| {
+                location.line >= line && Path::new(&location.file).ends_with(file)
+            })
+            .min_by_key(|(address, location)| (location.line, **address))
+            .map(|(address, _)| *address)
+    }
+
+    //All labels ordered by address.
+    pub fn labels(&self) -> impl Iterator<Item = (u16, &str)> {
+        let mut labels: Vec<(u16, &str)> = self
+            .addresses
+            .iter()
+            .map(|(name, address)| (*address, name.as_str()))
+            .collect();
+        labels.sort();
+        labels.into_iter()
+    }
+
+    //All source line entries ordered by address.
+    pub fn lines(&self) -> impl Iterator<Item = (u16, &SourceLocation)> {
+        self.lines
+            .iter()
+            .map(|(address, location)| (*address, location))
+    }
+
+    pub fn write(&self, path: &str) -> io::Result<()> {
+        let mut output = BufWriter::new(File::create(path)?);
+
+        writeln!(output, "{HEADER}")?;
+        for (address, name) in self.labels() {
+            writeln!(output, "{:#05X} {}", address, name)?;
+        }
+        for (address, location) in self.lines() {
+            writeln!(output, "{:#05X} {LINE_TAG} {}", address, location)?;
+        }
+        output.flush()
+    }
+
+    pub fn read(path: &str) -> Result<SymbolMap, Box<dyn Error>> {
+        let mut symbols = SymbolMap::default();
+
+        for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let parse_error = || {
+                format!(
+                    "{}:{}: expected '<address> <name>' or '<address> {LINE_TAG} <file>:<line>'",
+                    path,
+                    number + 1
+                )
+            };
+            let (address, entry) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(parse_error)?;
+            let address = address
+                .strip_prefix("0x")
+                .or_else(|| address.strip_prefix("0X"))
+                .and_then(|hex| u16::from_str_radix(hex, 16).ok())
+                .ok_or_else(parse_error)?;
+
+            match entry.trim().strip_prefix(LINE_TAG) {
+                Some(location) => {
+                    let (file, line) = location
+                        .trim()
+                        .rsplit_once(':')
+                        .and_then(|(file, line)| Some((file, line.parse::<usize>().ok()?)))
+                        .ok_or_else(parse_error)?;
+                    symbols.add_line(address, file, line);
+                }
+                None => symbols.add_label(address, entry.trim()),
+            }
+        }
+
+        Ok(symbols)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    //Path in the temporary directory that no other test uses.
+    fn temp_path(name: &str) -> String {
+        let path = env::temp_dir().join(format!("chip8-symbols-{}-{name}", std::process::id()));
+        path.to_string_lossy().into_owned()
+    }
+
+    fn read_text(name: &str, text: &str) -> Result<SymbolMap, String> {
+        let path = temp_path(name);
+        fs::write(&path, text).unwrap();
+        let symbols = SymbolMap::read(&path).map_err(|err| err.to_string());
+        fs::remove_file(&path).unwrap();
+        symbols
+    }
+
+    #[test]
+    fn reads_labels_and_lines() {
+        let text = format!(
+            "{HEADER}\n\n0x200 main\n0x200 start\n0X20a draw-player\n\
+             0x200 :line src/game.asm:3\n0x20A :line src/game.asm:12\n# comment\n"
+        );
+        let symbols = read_text("read", &text).unwrap();
+
+        assert_eq!(symbols.address_of("main"), Some(0x200));
+        assert_eq!(symbols.address_of("start"), Some(0x200));
+        assert_eq!(symbols.address_of("draw-player"), Some(0x20A));
+        assert_eq!(symbols.address_of("missing"), None);
+        //The first label at an address names it
+        assert_eq!(symbols.label_at(0x200), Some("main"));
+        assert_eq!(
+            symbols.line_at(0x20A).unwrap().to_string(),
+            "src/game.asm:12"
+        );
+
+        //Lines without code go to the next one that has some, files can be given by their end
+        assert_eq!(symbols.address_of_line("game.asm", 3), Some(0x200));
+        assert_eq!(symbols.address_of_line("src/game.asm", 4), Some(0x20A));
+        assert_eq!(symbols.address_of_line("game.asm", 13), None);
+        assert_eq!(symbols.address_of_line("other.asm", 3), None);
+    }
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        let mut symbols = SymbolMap::default();
+        symbols.add_label(0x204, "loop");
+        symbols.add_label(0x200, "main");
+        symbols.add_line(0x200, "game.8o", 1);
+        symbols.add_line(0x204, "game.8o", 5);
+
+        let path = temp_path("round-trip");
+        symbols.write(&path).unwrap();
+        let read = SymbolMap::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            read.labels().collect::<Vec<_>>(),
+            [(0x200, "main"), (0x204, "loop")]
+        );
+        assert_eq!(
+            read.lines().map(|(address, _)| address).collect::<Vec<_>>(),
+            [0x200, 0x204]
+        );
+        assert_eq!(read.line_at(0x204), symbols.line_at(0x204));
+    }
+
+    #[test]
+    fn reports_malformed_entries() {
+        let expected = |line: usize| {
+            format!(
+                "{}:{line}: expected '<address> <name>' or '<address> :line <file>:<line>'",
+                temp_path("bad")
+            )
+        };
+        let entries = [
+            "main",
+            "200 main",
+            "0xZZ main",
+            "0x200 :line game.asm",
+            "0x200 :line game.asm:x",
+        ];
+        for entry in entries {
+            let text = format!("{HEADER}\n{entry}\n");
+            assert_eq!(read_text("bad", &text).unwrap_err(), expected(2), "{entry}");
+        }
+    }
+}
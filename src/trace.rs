@@ -0,0 +1,647 @@
+//Instruction traces, written while the emulator runs so they can be diffed against the
+//trace of another emulator.
+//
+//Text format (one header line, then one line per executed instruction):
+//
+//  # chip-8 trace v1
+//  <cycle> <pc> <opcode> [<location>=<value> ...] [; <mnemonic>]
+//
+//cycle is decimal, every other number is hexadecimal without prefix. Locations are V0-VF,
+//I, DT, ST, SP and M<address> for a byte of memory. Only the state changed by the
+//instruction is listed, so a full register dump on every line is also a valid trace.
+//Anything after ';' is a comment.
+//
+//Binary format: the 8 byte magic "C8TRACE\x01" followed by records of
+//  cycle delta (unsigned LEB128), pc (u16 LE), opcode (u16 LE), change count (unsigned LEB128)
+//and one entry per change: a tag byte (0x0-0xF for V0-VF, 0x10 I, 0x11 DT, 0x12 ST,
+//0x13 SP, 0x14 memory), the address as u16 LE for memory, then the value (u16 LE for I,
+//u8 otherwise).
+
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use crate::cpu::decoder;
+use crate::cpu::Cpu;
+
+pub const BINARY_MAGIC: &[u8; 8] = b"C8TRACE\x01";
+pub const TEXT_HEADER: &str = "# chip-8 trace v1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Text,
+    Binary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Location {
+    Register(u8),
+    IRegister,
+    TRegister,
+    SRegister,
+    StackPointer,
+    Memory(u16),
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Location::Register(register) => write!(f, "V{:X}", register),
+            Location::IRegister => write!(f, "I"),
+            Location::TRegister => write!(f, "DT"),
+            Location::SRegister => write!(f, "ST"),
+            Location::StackPointer => write!(f, "SP"),
+            Location::Memory(address) => write!(f, "M{:03X}", address),
+        }
+    }
+}
+
+impl FromStr for Location {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Location, String> {
+        let upper = text.to_uppercase();
+        let location = match upper.as_str() {
+            "I" => Location::IRegister,
+            "DT" => Location::TRegister,
+            "ST" => Location::SRegister,
+            "SP" => Location::StackPointer,
+            _ => {
+                let parsed = if let Some(register) = upper.strip_prefix('V') {
+                    u8::from_str_radix(register, 16)
+                        .ok()
+                        .filter(|register| *register <= 0xF)
+                        .map(Location::Register)
+                } else if let Some(address) = upper.strip_prefix('M') {
+                    u16::from_str_radix(address, 16)
+                        .ok()
+                        .filter(|address| *address <= 0xFFF)
+                        .map(Location::Memory)
+                } else {
+                    None
+                };
+                parsed.ok_or_else(|| format!("unknown location '{text}'"))?
+            }
+        };
+        Ok(location)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Change {
+    pub location: Location,
+    pub value: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub cycle: u64,
+    pub program_counter: u16,
+    pub opcode: u16,
+    pub changes: Vec<Change>,
+}
+
+pub struct TraceOptions {
+    pub path: String,
+    pub format: TraceFormat,
+    //Only instructions whose address falls in this range are logged.
+    pub range: Option<RangeInclusive<u16>>,
+}
+
+//Copy of the state an instruction can change, taken before it executes.
+struct Snapshot {
+    gp_registers: [u8; 16],
+    i_register: u16,
+    t_register: u8,
+    s_register: u8,
+    stack_pointer: u8,
+    memory: Box<[u8; 4096]>,
+}
+
+impl Snapshot {
+    fn capture(cpu: &Cpu) -> Snapshot {
+        Snapshot {
+            gp_registers: *cpu.get_gp_registers(),
+            i_register: cpu.get_i_register(),
+            t_register: cpu.get_t_register(),
+            s_register: cpu.get_s_register(),
+            stack_pointer: cpu.get_stack_pointer(),
+            memory: Box::new(*cpu.get_memory()),
+        }
+    }
+
+    fn changes(&self, cpu: &Cpu) -> Vec<Change> {
+        let mut changes = Vec::new();
+
+        for (register, (before, after)) in self
+            .gp_registers
+            .iter()
+            .zip(cpu.get_gp_registers())
+            .enumerate()
+        {
+            if before != after {
+                changes.push(Change {
+                    location: Location::Register(register as u8),
+                    value: *after as u16,
+                });
+            }
+        }
+
+        let specials = [
+            (Location::IRegister, self.i_register, cpu.get_i_register()),
+            (
+                Location::TRegister,
+                self.t_register as u16,
+                cpu.get_t_register() as u16,
+            ),
+            (
+                Location::SRegister,
+                self.s_register as u16,
+                cpu.get_s_register() as u16,
+            ),
+            (
+                Location::StackPointer,
+                self.stack_pointer as u16,
+                cpu.get_stack_pointer() as u16,
+            ),
+        ];
+        for (location, before, after) in specials {
+            if before != after {
+                changes.push(Change {
+                    location,
+                    value: after,
+                });
+            }
+        }
+
+        for (address, (before, after)) in self.memory.iter().zip(cpu.get_memory()).enumerate() {
+            if before != after {
+                changes.push(Change {
+                    location: Location::Memory(address as u16),
+                    value: *after as u16,
+                });
+            }
+        }
+
+        changes
+    }
+}
+
+//Unsigned LEB128: 7 bits per byte, low bits first, the top bit set on all but the last byte.
+fn write_leb128(output: &mut impl Write, mut value: u64) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            return output.write_all(&[byte]);
+        }
+        output.write_all(&[byte | 0x80])?;
+    }
+}
+
+pub struct TraceWriter {
+    output: BufWriter<File>,
+    format: TraceFormat,
+    last_cycle: u64,
+}
+
+impl TraceWriter {
+    pub fn create(path: &str, format: TraceFormat) -> io::Result<TraceWriter> {
+        let mut output = BufWriter::new(File::create(path)?);
+
+        match format {
+            TraceFormat::Text => writeln!(output, "{TEXT_HEADER}")?,
+            TraceFormat::Binary => output.write_all(BINARY_MAGIC)?,
+        }
+
+        Ok(TraceWriter {
+            output,
+            format,
+            last_cycle: 0,
+        })
+    }
+
+    pub fn write(&mut self, record: &TraceRecord) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Text => self.write_text(record),
+            TraceFormat::Binary => self.write_binary(record),
+        }
+    }
+
+    fn write_text(&mut self, record: &TraceRecord) -> io::Result<()> {
+        write!(
+            self.output,
+            "{} {:03X} {:04X}",
+            record.cycle, record.program_counter, record.opcode
+        )?;
+        for change in &record.changes {
+            match change.location {
+                Location::IRegister => write!(self.output, " I={:03X}", change.value)?,
+                location => write!(self.output, " {}={:02X}", location, change.value)?,
+            }
+        }
+        writeln!(self.output, " ; {}", decoder::decode(record.opcode))
+    }
+
+    fn write_binary(&mut self, record: &TraceRecord) -> io::Result<()> {
+        //Cycle numbers only ever grow, deltas keep them to a byte most of the time
+        write_leb128(&mut self.output, record.cycle - self.last_cycle)?;
+        self.last_cycle = record.cycle;
+
+        self.output
+            .write_all(&record.program_counter.to_le_bytes())?;
+        self.output.write_all(&record.opcode.to_le_bytes())?;
+        write_leb128(&mut self.output, record.changes.len() as u64)?;
+
+        for change in &record.changes {
+            match change.location {
+                Location::Register(register) => {
+                    self.output.write_all(&[register, change.value as u8])?
+                }
+                Location::IRegister => {
+                    self.output.write_all(&[0x10])?;
+                    self.output.write_all(&change.value.to_le_bytes())?;
+                }
+                Location::TRegister => self.output.write_all(&[0x11, change.value as u8])?,
+                Location::SRegister => self.output.write_all(&[0x12, change.value as u8])?,
+                Location::StackPointer => self.output.write_all(&[0x13, change.value as u8])?,
+                Location::Memory(address) => {
+                    self.output.write_all(&[0x14])?;
+                    self.output.write_all(&address.to_le_bytes())?;
+                    self.output.write_all(&[change.value as u8])?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+//Runs the CPU one instruction at a time and logs what each instruction changed.
+pub struct Tracer {
+    writer: TraceWriter,
+    range: Option<RangeInclusive<u16>>,
+    cycle: u64,
+}
+
+impl Tracer {
+    pub fn create(options: &TraceOptions) -> io::Result<Tracer> {
+        Ok(Tracer {
+            writer: TraceWriter::create(&options.path, options.format)?,
+            range: options.range.clone(),
+            cycle: 0,
+        })
+    }
+
+    pub fn step(&mut self, cpu: &mut Cpu) -> io::Result<()> {
+        //Nothing executes while the CPU waits for a key or is halted on a fault
+        if cpu.is_blocked() {
+            cpu.clock();
+            return Ok(());
+        }
+
+        let program_counter = cpu.get_program_counter();
+        let opcode = cpu.get_current_instruction();
+        let snapshot = Snapshot::capture(cpu);
+
+        let cycle = self.cycle;
+        cpu.clock();
+        self.cycle += 1;
+
+        if let Some(range) = &self.range {
+            if !range.contains(&program_counter) {
+                return Ok(());
+            }
+        }
+
+        self.writer.write(&TraceRecord {
+            cycle,
+            program_counter,
+            opcode,
+            changes: snapshot.changes(cpu),
+        })
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+//Reads a trace in either format, telling them apart by the binary magic.
+pub fn read_trace(path: &str) -> Result<Vec<TraceRecord>, Box<dyn Error>> {
+    let data = fs::read(path)?;
+
+    match data.strip_prefix(BINARY_MAGIC) {
+        Some(records) => read_binary(records),
+        None => read_text(&String::from_utf8(data)?),
+    }
+}
+
+fn read_text(text: &str) -> Result<Vec<TraceRecord>, Box<dyn Error>> {
+    let mut records = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or("").trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let parse_error = |message: String| format!("line {}: {}", number + 1, message);
+        let mut fields = line.split_whitespace();
+        let mut next_field = |name: &str| {
+            fields
+                .next()
+                .ok_or_else(|| parse_error(format!("missing {name}")))
+        };
+
+        let cycle = next_field("cycle")?;
+        let cycle = cycle
+            .parse::<u64>()
+            .map_err(|_| parse_error(format!("invalid cycle '{cycle}'")))?;
+        let program_counter = next_field("pc")?;
+        let program_counter = u16::from_str_radix(program_counter, 16)
+            .map_err(|_| parse_error(format!("invalid pc '{program_counter}'")))?;
+        let opcode = next_field("opcode")?;
+        let opcode = u16::from_str_radix(opcode, 16)
+            .map_err(|_| parse_error(format!("invalid opcode '{opcode}'")))?;
+
+        let mut changes = Vec::new();
+        for field in fields {
+            let (location, value) = field.split_once('=').ok_or_else(|| {
+                parse_error(format!("expected <location>=<value>, got '{field}'"))
+            })?;
+            changes.push(Change {
+                location: location.parse().map_err(parse_error)?,
+                value: u16::from_str_radix(value, 16)
+                    .map_err(|_| parse_error(format!("invalid value '{value}'")))?,
+            });
+        }
+
+        records.push(TraceRecord {
+            cycle,
+            program_counter,
+            opcode,
+            changes,
+        });
+    }
+
+    Ok(records)
+}
+
+fn take<'a>(data: &mut &'a [u8], count: usize) -> Result<&'a [u8], String> {
+    if data.len() < count {
+        return Err(String::from("binary trace ends in the middle of a record"));
+    }
+    let (taken, rest) = data.split_at(count);
+    *data = rest;
+    Ok(taken)
+}
+
+//Reads what write_leb128 wrote, rejecting numbers that do not fit in a u64.
+fn read_leb128(data: &mut &[u8], name: &str) -> Result<u64, String> {
+    let mut value: u64 = 0;
+    let mut shift = 0;
+    loop {
+        let bits = take(data, 1)?[0];
+        //Check before shifting, bits shifted past the top would be lost
+        if shift >= 64 || (bits & 0x7F) as u64 > u64::MAX >> shift {
+            return Err(format!("binary trace has an invalid {name}"));
+        }
+        value |= ((bits & 0x7F) as u64) << shift;
+        shift += 7;
+        if bits & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn read_binary(mut data: &[u8]) -> Result<Vec<TraceRecord>, Box<dyn Error>> {
+    let mut records = Vec::new();
+    let mut cycle: u64 = 0;
+
+    while !data.is_empty() {
+        let delta = read_leb128(&mut data, "cycle delta")?;
+        cycle = cycle
+            .checked_add(delta)
+            .ok_or("binary trace has a cycle count that overflows")?;
+
+        let header = take(&mut data, 4)?;
+        let program_counter = u16::from_le_bytes([header[0], header[1]]);
+        let opcode = u16::from_le_bytes([header[2], header[3]]);
+        let count = read_leb128(&mut data, "change count")?;
+
+        let mut changes = Vec::new();
+        for _ in 0..count {
+            let tag = take(&mut data, 1)?[0];
+            let change = match tag {
+                0x0..=0xF => Change {
+                    location: Location::Register(tag),
+                    value: take(&mut data, 1)?[0] as u16,
+                },
+                0x10 => {
+                    let value = take(&mut data, 2)?;
+                    Change {
+                        location: Location::IRegister,
+                        value: u16::from_le_bytes([value[0], value[1]]),
+                    }
+                }
+                0x11..=0x13 => Change {
+                    location: match tag {
+                        0x11 => Location::TRegister,
+                        0x12 => Location::SRegister,
+                        _ => Location::StackPointer,
+                    },
+                    value: take(&mut data, 1)?[0] as u16,
+                },
+                0x14 => {
+                    let entry = take(&mut data, 3)?;
+                    let address = u16::from_le_bytes([entry[0], entry[1]]);
+                    if address > 0xFFF {
+                        return Err(format!(
+                            "binary trace has a memory address above 0xFFF: {address:#06x}"
+                        )
+                        .into());
+                    }
+                    Change {
+                        location: Location::Memory(address),
+                        value: entry[2] as u16,
+                    }
+                }
+                _ => {
+                    return Err(format!("binary trace has an unknown change tag {tag:#04x}").into())
+                }
+            };
+            changes.push(change);
+        }
+
+        records.push(TraceRecord {
+            cycle,
+            program_counter,
+            opcode,
+            changes,
+        });
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn records() -> Vec<TraceRecord> {
+        let change = |location, value| Change { location, value };
+        vec![
+            TraceRecord {
+                cycle: 0,
+                program_counter: 0x200,
+                opcode: 0x6A42,
+                changes: vec![change(Location::Register(0xA), 0x42)],
+            },
+            TraceRecord {
+                cycle: 1,
+                program_counter: 0x202,
+                opcode: 0xA2F0,
+                changes: vec![change(Location::IRegister, 0x2F0)],
+            },
+            //Far enough from the last cycle to need a two byte delta
+            TraceRecord {
+                cycle: 300,
+                program_counter: 0x204,
+                opcode: 0xF333,
+                changes: vec![
+                    change(Location::Memory(0x2F0), 0x00),
+                    change(Location::Memory(0x2F1), 0x06),
+                    change(Location::Memory(0x2F2), 0x06),
+                ],
+            },
+            TraceRecord {
+                cycle: 301,
+                program_counter: 0x206,
+                opcode: 0x2300,
+                changes: vec![
+                    change(Location::StackPointer, 1),
+                    change(Location::TRegister, 0x3C),
+                    change(Location::SRegister, 0x05),
+                ],
+            },
+            TraceRecord {
+                cycle: 302,
+                program_counter: 0x300,
+                opcode: 0x00E0,
+                changes: Vec::new(),
+            },
+        ]
+    }
+
+    //Path in the temporary directory that no other test uses.
+    fn temp_path(name: &str) -> String {
+        let path = env::temp_dir().join(format!("chip8-trace-{}-{name}", std::process::id()));
+        path.to_string_lossy().into_owned()
+    }
+
+    fn read_back(format: TraceFormat, name: &str) -> Vec<TraceRecord> {
+        let path = temp_path(name);
+        let mut writer = TraceWriter::create(&path, format).unwrap();
+        for record in records() {
+            writer.write(&record).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let read = read_trace(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        read
+    }
+
+    #[test]
+    fn text_trace_reads_back() {
+        assert_eq!(read_back(TraceFormat::Text, "text"), records());
+    }
+
+    #[test]
+    fn binary_trace_reads_back() {
+        assert_eq!(read_back(TraceFormat::Binary, "binary"), records());
+    }
+
+    #[test]
+    fn text_accepts_comments_and_reports_lines() {
+        let text = format!("{TEXT_HEADER}\n\n0 200 6A42 VA=42 ; LD VA, 0x42\n1 202 1200 V1\n");
+        let err = read_text(&text).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 4: expected <location>=<value>, got 'V1'"
+        );
+
+        let records = read_text(&text[..text.rfind("1 202").unwrap()]).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].changes[0].location, Location::Register(0xA));
+    }
+
+    #[test]
+    fn binary_rejects_broken_records() {
+        //The pc and opcode are cut short
+        let err = read_binary(&[0x00, 0x00, 0x02]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "binary trace ends in the middle of a record"
+        );
+
+        //Two records whose cycle deltas, u64::MAX each, add up past it
+        let mut data = Vec::new();
+        for _ in 0..2 {
+            data.extend([0xFF; 9]);
+            data.extend([0x01, 0x00, 0x02, 0xE0, 0x00, 0x00]);
+        }
+        let err = read_binary(&data).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "binary trace has a cycle count that overflows"
+        );
+
+        //A tenth byte may only hold the top bit of a u64
+        let mut data = vec![0xFF; 9];
+        data.extend([0x02, 0x00, 0x02, 0xE0, 0x00, 0x00]);
+        let err = read_binary(&data).unwrap_err();
+        assert_eq!(err.to_string(), "binary trace has an invalid cycle delta");
+
+        //A memory write outside the 4K of memory
+        let data = [0x00, 0x00, 0x02, 0x55, 0xF0, 0x01, 0x14, 0x00, 0x10, 0x01];
+        let err = read_binary(&data).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "binary trace has a memory address above 0xFFF: 0x1000"
+        );
+    }
+
+    #[test]
+    fn binary_counts_every_change() {
+        let changes: Vec<Change> = (0..300)
+            .map(|address| Change {
+                location: Location::Memory(address),
+                value: address & 0xFF,
+            })
+            .collect();
+        let record = TraceRecord {
+            cycle: 0,
+            program_counter: 0x200,
+            opcode: 0xFF55,
+            changes,
+        };
+
+        let path = temp_path("many-changes");
+        let mut writer = TraceWriter::create(&path, TraceFormat::Binary).unwrap();
+        writer.write(&record).unwrap();
+        writer.flush().unwrap();
+        let read = read_trace(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read, [record]);
+    }
+}